@@ -0,0 +1,61 @@
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use reqwest::Client;
+use serde_json::{json, Value};
+
+use super::{Message, NotificationChannel};
+use crate::config::{ChatConfig, ChatFormat};
+
+/// Posts to a chat's incoming webhook in the shape it expects.
+pub struct ChatChannel {
+    client: Client,
+    url: String,
+    format: ChatFormat,
+}
+
+impl ChatChannel {
+    pub fn new(config: &ChatConfig, client: Client) -> Self {
+        Self {
+            client,
+            url: config.url.clone(),
+            format: config.format,
+        }
+    }
+
+    fn payload(&self, message: &Message) -> Value {
+        match self.format {
+            ChatFormat::Slack => json!({
+                "text": format!("*{}*\n{}", message.subject, message.body),
+            }),
+            ChatFormat::Discord => json!({
+                "content": format!("**{}**\n{}", message.subject, message.body),
+            }),
+            // Legacy connector card, which Teams workflows accept as well.
+            ChatFormat::Teams => json!({
+                "@type": "MessageCard",
+                "@context": "https://schema.org/extensions",
+                "summary": message.subject,
+                "title": message.subject,
+                "text": message.body,
+            }),
+        }
+    }
+}
+
+#[async_trait]
+impl NotificationChannel for ChatChannel {
+    async fn send(&self, message: &Message) -> Result<()> {
+        let response = self
+            .client
+            .post(&self.url)
+            .json(&self.payload(message))
+            .send()
+            .await
+            // Chat webhook URLs carry their credentials.
+            .map_err(|e| e.without_url())?;
+        if !response.status().is_success() {
+            bail!("chat webhook answered {}", response.status());
+        }
+        Ok(())
+    }
+}
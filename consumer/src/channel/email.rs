@@ -0,0 +1,170 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use lettre::message::{header::ContentType, Mailbox};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Tokio1Executor};
+use std::time::Duration;
+
+use super::{Message, NotificationChannel};
+use crate::config::{EmailConfig, SmtpTls};
+
+/// Sends plain text emails through an SMTP server.
+pub struct EmailChannel {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+    to: Vec<Mailbox>,
+}
+
+impl EmailChannel {
+    pub fn new(config: &EmailConfig, timeout: Duration) -> Result<Self> {
+        let mut builder = match config.tls {
+            SmtpTls::Starttls => {
+                AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.host)?
+            }
+            SmtpTls::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&config.host)?,
+            SmtpTls::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&config.host),
+        };
+        if let Some(port) = config.port {
+            builder = builder.port(port);
+        }
+        if let (Some(username), Some(password)) = (&config.username, &config.password) {
+            builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+        }
+        let from = config
+            .from
+            .parse()
+            .with_context(|| format!("Invalid sender {:?}", config.from))?;
+        let to = config
+            .to
+            .iter()
+            .map(|to| {
+                to.parse()
+                    .with_context(|| format!("Invalid recipient {to:?}"))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            transport: builder.timeout(Some(timeout)).build(),
+            from,
+            to,
+        })
+    }
+}
+
+#[async_trait]
+impl NotificationChannel for EmailChannel {
+    async fn send(&self, message: &Message) -> Result<()> {
+        let mut email = lettre::Message::builder()
+            .from(self.from.clone())
+            .subject(&message.subject)
+            .header(ContentType::TEXT_PLAIN);
+        for to in &self.to {
+            email = email.to(to.clone());
+        }
+        self.transport
+            .send(email.body(message.body.clone())?)
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+    use tokio::task::JoinHandle;
+
+    /// Just enough of an SMTP server to accept one message, returning the
+    /// envelope commands and the message data it received.
+    async fn mock_smtp() -> (u16, JoinHandle<(Vec<String>, String)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (read, mut write) = stream.into_split();
+            let mut lines = BufReader::new(read).lines();
+            let mut commands = Vec::new();
+            let mut data = String::new();
+            write.write_all(b"220 mock ESMTP\r\n").await.unwrap();
+            while let Some(line) = lines.next_line().await.unwrap() {
+                let verb = line.split(' ').next().unwrap_or_default().to_uppercase();
+                let reply: &[u8] = match verb.as_str() {
+                    "EHLO" => b"250 mock\r\n",
+                    "DATA" => {
+                        write.write_all(b"354 go ahead\r\n").await.unwrap();
+                        while let Some(line) = lines.next_line().await.unwrap() {
+                            if line == "." {
+                                break;
+                            }
+                            data.push_str(&line);
+                            data.push('\n');
+                        }
+                        b"250 queued\r\n"
+                    }
+                    "QUIT" => {
+                        write.write_all(b"221 bye\r\n").await.unwrap();
+                        break;
+                    }
+                    _ => b"250 ok\r\n",
+                };
+                commands.push(line);
+                write.write_all(reply).await.unwrap();
+            }
+            (commands, data)
+        });
+        (port, server)
+    }
+
+    #[tokio::test]
+    async fn test_sends_email() {
+        let (port, server) = mock_smtp().await;
+        let channel = EmailChannel::new(
+            &EmailConfig {
+                host: "127.0.0.1".to_string(),
+                port: Some(port),
+                tls: SmtpTls::None,
+                username: None,
+                password: None,
+                from: "Alerts <alerts@example.org>".to_string(),
+                to: vec!["ops@example.org".to_string(), "cfo@example.org".to_string()],
+            },
+            Duration::from_secs(5),
+        )
+        .unwrap();
+
+        channel
+            .send(&Message {
+                subject: "treasury: transaction 0xabc".to_string(),
+                body: "Sent 10 ETH".to_string(),
+            })
+            .await
+            .unwrap();
+
+        let (commands, data) = server.await.unwrap();
+        assert!(commands.contains(&"MAIL FROM:<alerts@example.org>".to_string()));
+        assert!(commands.contains(&"RCPT TO:<ops@example.org>".to_string()));
+        assert!(commands.contains(&"RCPT TO:<cfo@example.org>".to_string()));
+        assert!(
+            data.contains("Subject: treasury: transaction 0xabc"),
+            "{data}"
+        );
+        assert!(data.contains("Sent 10 ETH"), "{data}");
+    }
+
+    #[test]
+    fn test_rejects_invalid_addresses() {
+        let config = EmailConfig {
+            host: "localhost".to_string(),
+            port: None,
+            tls: SmtpTls::None,
+            username: None,
+            password: None,
+            from: "alerts@example.org".to_string(),
+            to: vec!["not an address".to_string()],
+        };
+        let err = EmailChannel::new(&config, Duration::from_secs(5))
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("Invalid recipient"), "{err}");
+    }
+}
@@ -0,0 +1,210 @@
+mod chat;
+mod email;
+mod telegram;
+
+pub use chat::ChatChannel;
+pub use email::EmailChannel;
+pub use telegram::TelegramChannel;
+
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use reqwest::Client;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::config::ChannelConfig;
+use crate::models::webhook::WebhookEvent;
+use crate::template::{MatchedRules, Templates};
+
+/// A rendered notification.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    pub subject: String,
+    pub body: String,
+}
+
+/// Somewhere people read notifications: a mailbox, a chat room, a bot.
+#[async_trait]
+pub trait NotificationChannel: Send + Sync {
+    async fn send(&self, message: &Message) -> Result<()>;
+}
+
+/// A configured channel: its templates and where the messages go.
+pub struct Channel {
+    pub name: String,
+    subject: String,
+    template: String,
+    templates: Arc<Templates>,
+    transport: Box<dyn NotificationChannel>,
+}
+
+impl Channel {
+    /// HTTP based channels share `client`; `timeout` bounds SMTP commands.
+    pub fn from_config(
+        config: &ChannelConfig,
+        templates: Arc<Templates>,
+        client: &Client,
+        timeout: Duration,
+    ) -> Result<Self> {
+        let transport: Box<dyn NotificationChannel> =
+            match (&config.email, &config.chat, &config.telegram) {
+                (Some(email), None, None) => Box::new(EmailChannel::new(email, timeout)?),
+                (None, Some(chat), None) => Box::new(ChatChannel::new(chat, client.clone())),
+                (None, None, Some(telegram)) => {
+                    Box::new(TelegramChannel::new(telegram, client.clone()))
+                }
+                _ => bail!("Channel {:?} needs exactly one transport", config.name),
+            };
+        if templates.content_type(&config.template).is_none() {
+            bail!("No template named {:?}", config.template);
+        }
+        Ok(Self {
+            name: config.name.clone(),
+            subject: config.subject.clone(),
+            template: config.template.clone(),
+            templates,
+            transport,
+        })
+    }
+
+    /// The message for `event`, matched by `rules`.
+    pub fn message(&self, event: &WebhookEvent, rules: &MatchedRules) -> Result<Message> {
+        Ok(Message {
+            subject: self
+                .templates
+                .render_inline(&self.subject, event, rules)?
+                .trim()
+                .to_string(),
+            body: self.templates.render(&self.template, event, rules)?,
+        })
+    }
+
+    pub async fn send(&self, message: &Message) -> Result<()> {
+        self.transport.send(message).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ChatConfig, ChatFormat, TelegramConfig};
+    use axum::{extract::State, routing::post, Json, Router, Server};
+    use serde_json::{json, Value};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+
+    type Received = Arc<Mutex<Vec<(String, Value)>>>;
+
+    /// Local stand-in for chat and bot APIs, recording every JSON body with
+    /// the path it was posted to and answering with `reply`.
+    async fn mock_api(reply: Value) -> (String, Received) {
+        let received = Received::default();
+        let app = Router::new()
+            .route(
+                "/*path",
+                post(
+                    move |State(received): State<Received>,
+                          uri: axum::http::Uri,
+                          Json(body): Json<Value>| async move {
+                        received
+                            .lock()
+                            .unwrap()
+                            .push((uri.path().to_string(), body));
+                        Json(reply)
+                    },
+                ),
+            )
+            .with_state(received.clone());
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(
+            Server::from_tcp(listener)
+                .unwrap()
+                .serve(app.into_make_service()),
+        );
+        (url, received)
+    }
+
+    fn message() -> Message {
+        Message {
+            subject: "whale".to_string(),
+            body: "moved 42 wei".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_chat_formats() {
+        let (url, received) = mock_api(json!({})).await;
+        for format in [ChatFormat::Slack, ChatFormat::Discord, ChatFormat::Teams] {
+            let config = ChatConfig {
+                url: format!("{url}/hook"),
+                format,
+            };
+            ChatChannel::new(&config, Client::new())
+                .send(&message())
+                .await
+                .unwrap();
+        }
+
+        let bodies: Vec<Value> = received
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(_, body)| body.clone())
+            .collect();
+        assert_eq!(bodies[0], json!({ "text": "*whale*\nmoved 42 wei" }));
+        assert_eq!(bodies[1], json!({ "content": "**whale**\nmoved 42 wei" }));
+        assert_eq!(bodies[2]["@type"], "MessageCard");
+        assert_eq!(bodies[2]["title"], "whale");
+        assert_eq!(bodies[2]["text"], "moved 42 wei");
+    }
+
+    #[tokio::test]
+    async fn test_chat_error_hides_url() {
+        let config = ChatConfig {
+            // Nothing listens on port 9 of localhost.
+            url: "http://127.0.0.1:9/services/T000/B000/secret".to_string(),
+            format: ChatFormat::Slack,
+        };
+        let err = ChatChannel::new(&config, Client::new())
+            .send(&message())
+            .await
+            .unwrap_err();
+        assert!(!err.to_string().contains("secret"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn test_telegram() {
+        let (url, received) = mock_api(json!({ "ok": true, "result": {} })).await;
+        let config = TelegramConfig {
+            bot_token: "123:abc".to_string(),
+            chat_id: "-100200".to_string(),
+            api_url: url,
+        };
+        TelegramChannel::new(&config, Client::new())
+            .send(&message())
+            .await
+            .unwrap();
+
+        let received = received.lock().unwrap();
+        let (path, body) = &received[0];
+        assert_eq!(path, "/bot123:abc/sendMessage");
+        assert_eq!(body["chat_id"], "-100200");
+        assert_eq!(body["text"], "whale\n\nmoved 42 wei");
+    }
+
+    #[tokio::test]
+    async fn test_telegram_error() {
+        let (url, _) = mock_api(json!({ "ok": false, "description": "chat not found" })).await;
+        let config = TelegramConfig {
+            bot_token: "123:abc".to_string(),
+            chat_id: "1".to_string(),
+            api_url: url,
+        };
+        let err = TelegramChannel::new(&config, Client::new())
+            .send(&message())
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("chat not found"), "{err}");
+    }
+}
@@ -0,0 +1,62 @@
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::Deserialize;
+use serde_json::json;
+
+use super::{Message, NotificationChannel};
+use crate::config::TelegramConfig;
+
+/// Sends messages to a chat through the Telegram Bot API.
+pub struct TelegramChannel {
+    client: Client,
+    url: String,
+    chat_id: String,
+}
+
+#[derive(Deserialize)]
+struct ApiResponse {
+    ok: bool,
+    description: Option<String>,
+}
+
+impl TelegramChannel {
+    pub fn new(config: &TelegramConfig, client: Client) -> Self {
+        Self {
+            client,
+            url: format!(
+                "{}/bot{}/sendMessage",
+                config.api_url.trim_end_matches('/'),
+                config.bot_token
+            ),
+            chat_id: config.chat_id.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl NotificationChannel for TelegramChannel {
+    async fn send(&self, message: &Message) -> Result<()> {
+        let response = self
+            .client
+            .post(&self.url)
+            .json(&json!({
+                "chat_id": self.chat_id,
+                "text": format!("{}\n\n{}", message.subject, message.body),
+                "disable_web_page_preview": true,
+            }))
+            .send()
+            .await
+            // The URL holds the bot token.
+            .map_err(|e| e.without_url())?;
+        let status = response.status();
+        let answer: ApiResponse = response.json().await.map_err(|e| e.without_url())?;
+        if !answer.ok {
+            bail!(
+                "Telegram answered {status}: {}",
+                answer.description.unwrap_or_default()
+            );
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,567 @@
+use anyhow::{bail, Context, Result};
+use clap::Parser;
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::template::{self, BUILTIN_TEMPLATES};
+
+const DEFAULT_CONFIG_FILE: &str = "consumer.toml";
+
+/// Command line flags. Each can also be set through the environment variable
+/// named in its help, and both take precedence over the config file.
+#[derive(Parser, Debug, Default)]
+pub struct Args {
+    /// TOML config file, `consumer.toml` is read when present
+    #[arg(long, env = "CONFIG_FILE")]
+    pub config: Option<PathBuf>,
+
+    /// JSON file keeping webhooks and notification rules across restarts
+    #[arg(long, env = "STATE_FILE")]
+    pub state_file: Option<PathBuf>,
+
+    /// Comma separated Kafka brokers
+    #[arg(long, env = "KAFKA_BROKER_HOST", value_delimiter = ',')]
+    pub kafka_broker_host: Option<Vec<String>>,
+
+    /// Comma separated transaction topics
+    #[arg(long, env = "KAFKA_TX_TOPIC", value_delimiter = ',')]
+    pub kafka_tx_topic: Option<Vec<String>>,
+
+    /// Comma separated block topics
+    #[arg(long, env = "KAFKA_BLOCK_TOPIC", value_delimiter = ',')]
+    pub kafka_block_topic: Option<Vec<String>>,
+
+    #[arg(long, env = "KAFKA_GROUP")]
+    pub kafka_group: Option<String>,
+
+    /// Address of the GraphQL, health and metrics server
+    #[arg(long, env = "SERVER_ADDR")]
+    pub server_addr: Option<SocketAddr>,
+
+    #[arg(long, env = "MAX_CONSUMER_LAG")]
+    pub max_consumer_lag: Option<i64>,
+
+    /// Attempts per webhook delivery before it is logged as failed
+    #[arg(long, env = "WEBHOOK_MAX_ATTEMPTS")]
+    pub webhook_max_attempts: Option<u32>,
+
+    /// Timeout of one webhook request in milliseconds
+    #[arg(long, env = "WEBHOOK_TIMEOUT_MS")]
+    pub webhook_timeout_ms: Option<u64>,
+
+    /// File the consumed records and Kafka offsets are snapshotted to and
+    /// restored from
+    #[arg(long, env = "SNAPSHOT_FILE")]
+    pub snapshot_file: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KafkaConfig {
+    pub hosts: Vec<String>,
+    /// Every prefixed topic when the crawler runs several chains.
+    pub tx_topics: Vec<String>,
+    pub block_topics: Vec<String>,
+    /// Consumer group whose offsets are committed to Kafka.
+    pub group: String,
+}
+
+impl Default for KafkaConfig {
+    fn default() -> Self {
+        Self {
+            hosts: Vec::new(),
+            tx_topics: vec!["tx".to_string()],
+            block_topics: vec!["block".to_string()],
+            group: "group".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub addr: SocketAddr,
+    /// `/readyz` fails when any partition lags further behind than this.
+    pub max_consumer_lag: i64,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            addr: SocketAddr::from(([0, 0, 0, 0], 3000)),
+            max_consumer_lag: 1000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebhookConfig {
+    /// Attempts per delivery, the first one included.
+    pub max_attempts: u32,
+    /// Wait before the second attempt; it doubles for every further one.
+    pub initial_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+    pub timeout_ms: u64,
+    /// Finished deliveries kept for the `webhookDeliveries` query.
+    pub delivery_log_size: usize,
+    /// How long in seconds a sent notification is remembered, so that the
+    /// same event is not notified again for the same rule. 0 turns
+    /// de-duplication off.
+    pub dedup_window_secs: u64,
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_backoff_ms: 1000,
+            max_backoff_ms: 60_000,
+            timeout_ms: 10_000,
+            delivery_log_size: 1000,
+            dedup_window_secs: 7 * 24 * 60 * 60,
+        }
+    }
+}
+
+impl WebhookConfig {
+    /// Wait before the attempt following `failures` failed ones.
+    pub fn backoff(&self, failures: u32) -> Duration {
+        let factor = 2u64.saturating_pow(failures.saturating_sub(1));
+        Duration::from_millis(
+            self.initial_backoff_ms
+                .saturating_mul(factor)
+                .min(self.max_backoff_ms),
+        )
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SnapshotConfig {
+    /// Snapshots are only taken when it is set.
+    pub file: Option<PathBuf>,
+    /// Seconds between snapshots; one is also taken on shutdown.
+    pub interval_secs: u64,
+}
+
+impl Default for SnapshotConfig {
+    fn default() -> Self {
+        Self {
+            file: None,
+            interval_secs: 300,
+        }
+    }
+}
+
+/// Which consumed records are kept. Every policy that is set applies; with
+/// none, records are kept for as long as the process runs.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetentionConfig {
+    /// Blocks kept per chain, counting back from the latest one.
+    pub max_blocks: Option<u64>,
+    /// Blocks older than this many days are removed.
+    pub max_age_days: Option<u64>,
+    /// Only transactions from or to an address that a rule, a webhook filter
+    /// or an address book names are kept.
+    pub watched_only: bool,
+    /// Seconds between pruning runs.
+    pub interval_secs: u64,
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        Self {
+            max_blocks: None,
+            max_age_days: None,
+            watched_only: false,
+            interval_secs: 60,
+        }
+    }
+}
+
+impl RetentionConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.max_blocks.is_some() || self.max_age_days.is_some() || self.watched_only
+    }
+}
+
+/// Where a notification channel sends its messages and how they read. Set
+/// exactly one of `email`, `chat` and `telegram`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChannelConfig {
+    /// What notification rules list in `notify`.
+    pub name: String,
+    /// Handlebars template of the subject line, rendered like the body.
+    #[serde(default = "default_subject")]
+    pub subject: String,
+    /// Name of the body template, built in or from `templates`.
+    #[serde(default = "default_channel_template")]
+    pub template: String,
+    pub email: Option<EmailConfig>,
+    pub chat: Option<ChatConfig>,
+    pub telegram: Option<TelegramConfig>,
+}
+
+fn default_subject() -> String {
+    "{{rule}}: {{kind}} {{short hash}}".to_string()
+}
+
+fn default_channel_template() -> String {
+    "text".to_string()
+}
+
+/// A named Handlebars template that channels and webhooks can pick.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TemplateConfig {
+    pub source: String,
+    /// Sent with webhook deliveries rendered with it.
+    #[serde(default = "default_content_type")]
+    pub content_type: String,
+}
+
+fn default_content_type() -> String {
+    "text/plain; charset=utf-8".to_string()
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpTls {
+    /// Upgrades a plain connection, on port 587 unless `port` is set.
+    #[default]
+    Starttls,
+    /// TLS from the start, on port 465 unless `port` is set.
+    Tls,
+    /// Plain text, on port 25 unless `port` is set. Only for local relays.
+    None,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EmailConfig {
+    pub host: String,
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub tls: SmtpTls,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub from: String,
+    pub to: Vec<String>,
+}
+
+/// Payload shape expected by the chat's incoming webhooks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChatFormat {
+    Slack,
+    Discord,
+    Teams,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChatConfig {
+    pub url: String,
+    pub format: ChatFormat,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TelegramConfig {
+    pub bot_token: String,
+    pub chat_id: String,
+    #[serde(default = "default_telegram_api")]
+    pub api_url: String,
+}
+
+fn default_telegram_api() -> String {
+    "https://api.telegram.org".to_string()
+}
+
+/// Consumer settings, merged from the config file, the environment and the
+/// command line in increasing order of precedence.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Where webhooks and notification rules are saved. They only live as
+    /// long as the process when unset.
+    pub state_file: Option<PathBuf>,
+    pub kafka: KafkaConfig,
+    pub server: ServerConfig,
+    pub webhook: WebhookConfig,
+    pub snapshot: SnapshotConfig,
+    pub retention: RetentionConfig,
+    /// Named address lists that rule conditions refer to as `@name`.
+    pub address_books: BTreeMap<String, Vec<String>>,
+    pub channels: Vec<ChannelConfig>,
+    pub templates: BTreeMap<String, TemplateConfig>,
+    /// Block explorer base URL by chain id, for links in templates. Adds to
+    /// or replaces the built-in ones of well known chains.
+    pub explorers: BTreeMap<String, String>,
+}
+
+impl Config {
+    /// Builds and validates the configuration, so that every mistake is
+    /// reported before the consumer connects to anything.
+    pub fn load(args: &Args) -> Result<Self> {
+        let mut config = match &args.config {
+            Some(path) => Self::from_file(path)?,
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => {
+                Self::from_file(Path::new(DEFAULT_CONFIG_FILE))?
+            }
+            None => Self::default(),
+        };
+        config.apply_args(args);
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn from_file(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+        toml::from_str(&content).with_context(|| format!("Invalid config file {}", path.display()))
+    }
+
+    fn apply_args(&mut self, args: &Args) {
+        if let Some(path) = &args.state_file {
+            self.state_file = Some(path.clone());
+        }
+        if let Some(hosts) = &args.kafka_broker_host {
+            self.kafka.hosts = hosts.clone();
+        }
+        if let Some(topics) = &args.kafka_tx_topic {
+            self.kafka.tx_topics = topics.clone();
+        }
+        if let Some(topics) = &args.kafka_block_topic {
+            self.kafka.block_topics = topics.clone();
+        }
+        if let Some(group) = &args.kafka_group {
+            self.kafka.group = group.clone();
+        }
+        if let Some(addr) = args.server_addr {
+            self.server.addr = addr;
+        }
+        if let Some(lag) = args.max_consumer_lag {
+            self.server.max_consumer_lag = lag;
+        }
+        if let Some(attempts) = args.webhook_max_attempts {
+            self.webhook.max_attempts = attempts;
+        }
+        if let Some(timeout) = args.webhook_timeout_ms {
+            self.webhook.timeout_ms = timeout;
+        }
+        if let Some(path) = &args.snapshot_file {
+            self.snapshot.file = Some(path.clone());
+        }
+    }
+
+    fn has_template(&self, name: &str) -> bool {
+        BUILTIN_TEMPLATES.contains(&name) || self.templates.contains_key(name)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.kafka.hosts.iter().all(|host| host.trim().is_empty()) {
+            bail!("No Kafka hosts: set kafka.hosts, KAFKA_BROKER_HOST or --kafka-broker-host");
+        }
+        if self.kafka.tx_topics.is_empty() || self.kafka.block_topics.is_empty() {
+            bail!("At least one transaction and one block topic is required");
+        }
+        if let Some(topic) = self
+            .kafka
+            .tx_topics
+            .iter()
+            .find(|topic| self.kafka.block_topics.contains(topic))
+        {
+            bail!("Topic {topic} is listed as both a transaction and a block topic");
+        }
+        if self.kafka.group.is_empty() {
+            bail!("Kafka consumer group must not be empty");
+        }
+        if self.server.max_consumer_lag < 0 {
+            bail!("max_consumer_lag must not be negative");
+        }
+        if self.webhook.max_attempts == 0 {
+            bail!("webhook.max_attempts must be at least 1");
+        }
+        if self.webhook.timeout_ms == 0 {
+            bail!("webhook.timeout_ms must be positive");
+        }
+        if self.snapshot.interval_secs == 0 {
+            bail!("snapshot.interval_secs must be positive");
+        }
+        if self.retention.max_blocks == Some(0) || self.retention.max_age_days == Some(0) {
+            bail!("retention.max_blocks and retention.max_age_days must be positive");
+        }
+        if self.retention.interval_secs == 0 {
+            bail!("retention.interval_secs must be positive");
+        }
+        if let Some(name) = self
+            .address_books
+            .keys()
+            .find(|name| name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_'))
+        {
+            bail!("Invalid address book name {name:?}");
+        }
+        for (name, template) in &self.templates {
+            template::compile(&template.source)
+                .with_context(|| format!("Invalid template {name:?}"))?;
+        }
+        if let Some(chain_id) = self
+            .explorers
+            .keys()
+            .find(|chain_id| chain_id.parse::<u64>().is_err())
+        {
+            bail!("explorers must be keyed by chain id, not {chain_id:?}");
+        }
+        let mut names = BTreeSet::new();
+        for channel in &self.channels {
+            validate_channel(channel)
+                .with_context(|| format!("Invalid channel {:?}", channel.name))?;
+            if !self.has_template(&channel.template) {
+                bail!(
+                    "Channel {:?} uses unknown template {:?}",
+                    channel.name,
+                    channel.template
+                );
+            }
+            if !names.insert(&channel.name) {
+                bail!("Channel {:?} is defined twice", channel.name);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn validate_channel(channel: &ChannelConfig) -> Result<()> {
+    if channel.name.trim().is_empty() {
+        bail!("name must not be empty");
+    }
+    template::compile(&channel.subject).context("subject")?;
+    let transports = [
+        channel.email.is_some(),
+        channel.chat.is_some(),
+        channel.telegram.is_some(),
+    ];
+    if transports.into_iter().filter(|&set| set).count() != 1 {
+        bail!("set exactly one of email, chat and telegram");
+    }
+    if let Some(email) = &channel.email {
+        if email.to.is_empty() {
+            bail!("email.to must list at least one recipient");
+        }
+        if email.username.is_some() != email.password.is_some() {
+            bail!("email.username and email.password go together");
+        }
+    }
+    if let Some(chat) = &channel.chat {
+        if !chat.url.starts_with("http://") && !chat.url.starts_with("https://") {
+            bail!("chat.url must be an http(s) URL");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_from_toml_with_overrides() {
+        let mut config: Config = toml::from_str(
+            r#"
+            [kafka]
+            hosts = ["localhost:9092"]
+            tx_topics = ["tx", "sepolia_tx"]
+
+            [server]
+            addr = "127.0.0.1:4000"
+
+            [address_books]
+            treasury = ["0xabc", "0xdef"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.kafka.block_topics, vec!["block"]);
+        assert_eq!(config.address_books["treasury"].len(), 2);
+        assert_eq!(config.server.addr.port(), 4000);
+
+        config.apply_args(&Args {
+            kafka_group: Some("graphql".to_string()),
+            max_consumer_lag: Some(10),
+            snapshot_file: Some(PathBuf::from("snapshot.json")),
+            ..Default::default()
+        });
+        config.validate().unwrap();
+
+        assert_eq!(config.kafka.tx_topics, vec!["tx", "sepolia_tx"]);
+        assert_eq!(config.kafka.group, "graphql");
+        assert_eq!(config.server.max_consumer_lag, 10);
+        assert_eq!(config.snapshot.file, Some(PathBuf::from("snapshot.json")));
+    }
+
+    #[test]
+    fn test_config_validation_errors() {
+        let err = Config::default().validate().unwrap_err().to_string();
+        assert!(err.contains("KAFKA_BROKER_HOST"), "{err}");
+
+        let mut config = Config::default();
+        config.kafka.hosts = vec!["localhost:9092".to_string()];
+        config.kafka.block_topics = vec!["tx".to_string()];
+        let err = config.validate().unwrap_err().to_string();
+        assert!(
+            err.contains("both a transaction and a block topic"),
+            "{err}"
+        );
+
+        let err = toml::from_str::<Config>("[server]\nport = 1")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("unknown field `port`"), "{err}");
+
+        let mut config: Config = toml::from_str(
+            r#"
+            [kafka]
+            hosts = ["localhost:9092"]
+
+            [templates.short]
+            source = "{{ether value}} ETH"
+
+            [[channels]]
+            name = "ops"
+            subject = "{{#if rule}}"
+            template = "long"
+            [channels.chat]
+            url = "https://hooks.slack.com/services/T/B/x"
+            format = "slack"
+            "#,
+        )
+        .unwrap();
+        let err = format!("{:#}", config.validate().unwrap_err());
+        assert!(
+            err.starts_with("Invalid channel \"ops\": subject: "),
+            "{err}"
+        );
+        config.channels[0].subject = "{{rule}}".to_string();
+        let err = config.validate().unwrap_err().to_string();
+        assert_eq!(err, "Channel \"ops\" uses unknown template \"long\"");
+        config.channels[0].template = "short".to_string();
+        config.validate().unwrap();
+        config.channels[0].telegram = Some(TelegramConfig {
+            bot_token: "t".to_string(),
+            chat_id: "1".to_string(),
+            api_url: default_telegram_api(),
+        });
+        let err = format!("{:#}", config.validate().unwrap_err());
+        assert!(
+            err.contains("exactly one of email, chat and telegram"),
+            "{err}"
+        );
+    }
+}
@@ -1,50 +1,99 @@
-use async_graphql::*;
+use async_graphql::{dataloader::DataLoader, *};
 use std::sync::Arc;
 use tokio::sync::RwLock;
 
-use crate::models::{block::Block, storage::Storage, transaction::Transaction};
+use crate::loader::{BlockLoader, TransactionLoader};
+use crate::metrics::ResolverMetrics;
+use crate::models::{
+    block::Block,
+    rule::{NotificationRule, NotificationRuleInput, NotificationRuleUpdate},
+    storage::Storage,
+    transaction::Transaction,
+    webhook::{Delivery, Webhook, WebhookFilter},
+};
+
+pub type AppSchema = Schema<QueryRoot, MutationRoot, EmptySubscription>;
+
+pub fn build_schema(storage: Arc<RwLock<Storage>>) -> AppSchema {
+    Schema::build(QueryRoot, MutationRoot, EmptySubscription)
+        .extension(ResolverMetrics)
+        .data(DataLoader::new(
+            BlockLoader::new(storage.clone()),
+            tokio::spawn,
+        ))
+        .data(DataLoader::new(
+            TransactionLoader::new(storage.clone()),
+            tokio::spawn,
+        ))
+        .data(storage)
+        .finish()
+}
 
 pub struct QueryRoot;
 
 #[Object]
 impl QueryRoot {
-    async fn block(&self, ctx: &Context<'_>, hash: String) -> Result<Option<Block>, Error> {
+    async fn block(
+        &self,
+        ctx: &Context<'_>,
+        chain_id: Option<u64>,
+        hash: String,
+    ) -> Result<Option<Block>, Error> {
         let storage = ctx.data::<Arc<RwLock<Storage>>>().unwrap();
         let storage = storage.read().await;
-        Ok(storage.get_block(&hash).await)
+        Ok(storage.get_block(chain_id, &hash).await)
     }
-    async fn blocks_by_number(&self, ctx: &Context<'_>, number: u64) -> Result<Vec<Block>, Error> {
+    async fn blocks_by_number(
+        &self,
+        ctx: &Context<'_>,
+        chain_id: Option<u64>,
+        number: u64,
+    ) -> Result<Vec<Block>, Error> {
         let storage = ctx.data::<Arc<RwLock<Storage>>>().unwrap();
         let storage = storage.read().await;
 
         Ok(storage
             .blocks
             .values()
-            .filter(|block| block.number == number)
+            .filter(|block| {
+                block.number == number && chain_id.is_none_or(|id| block.chain_id == id)
+            })
             .cloned()
             .collect())
     }
 
-    async fn latest_blocks(&self, ctx: &Context<'_>, limit: i32) -> Result<Vec<Block>, Error> {
+    async fn latest_blocks(
+        &self,
+        ctx: &Context<'_>,
+        chain_id: Option<u64>,
+        limit: i32,
+    ) -> Result<Vec<Block>, Error> {
         let storage = ctx.data::<Arc<RwLock<Storage>>>().unwrap();
         let storage = storage.read().await;
-        let mut blocks = storage.blocks.values().cloned().collect::<Vec<_>>();
-        blocks.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
+        let mut blocks = storage
+            .blocks
+            .values()
+            .filter(|block| chain_id.is_none_or(|id| block.chain_id == id))
+            .cloned()
+            .collect::<Vec<_>>();
+        blocks.sort_by_key(|block| std::cmp::Reverse(block.timestamp));
         Ok(blocks.into_iter().take(limit as usize).collect())
     }
     async fn transaction(
         &self,
         ctx: &Context<'_>,
+        chain_id: Option<u64>,
         hash: String,
     ) -> Result<Option<Transaction>, Error> {
         let storage = ctx.data::<Arc<RwLock<Storage>>>().unwrap();
         let storage = storage.read().await;
-        Ok(storage.get_transaction(&hash).await)
+        Ok(storage.get_transaction(chain_id, &hash).await)
     }
 
     async fn transactions_for_block(
         &self,
         ctx: &Context<'_>,
+        chain_id: Option<u64>,
         block_hash: Option<String>,
         block_number: Option<u64>,
     ) -> Result<Vec<Transaction>, Error> {
@@ -55,107 +104,237 @@ impl QueryRoot {
             .transactions
             .values()
             .filter(|tx| {
-                block_hash
-                    .as_ref()
-                    .map_or(true, |hash| &tx.block_hash == hash)
-                    && block_number.map_or(true, |number| tx.block_number == number)
+                chain_id.is_none_or(|id| tx.chain_id == id)
+                    && block_hash
+                        .as_ref()
+                        .is_none_or(|hash| &tx.block_hash == hash)
+                    && block_number.is_none_or(|number| tx.block_number == number)
             })
             .cloned()
             .collect())
     }
+
+    async fn webhooks(&self, ctx: &Context<'_>) -> Result<Vec<Webhook>, Error> {
+        let storage = ctx.data::<Arc<RwLock<Storage>>>().unwrap();
+        let storage = storage.read().await;
+        Ok(storage.subscriptions.webhooks.values().cloned().collect())
+    }
+
+    async fn notification_rules(&self, ctx: &Context<'_>) -> Result<Vec<NotificationRule>, Error> {
+        let storage = ctx.data::<Arc<RwLock<Storage>>>().unwrap();
+        let storage = storage.read().await;
+        Ok(storage.subscriptions.rules.values().cloned().collect())
+    }
+
+    /// Finished deliveries, newest first.
+    async fn webhook_deliveries(
+        &self,
+        ctx: &Context<'_>,
+        webhook_id: Option<u64>,
+        #[graphql(default = 100)] limit: i32,
+    ) -> Result<Vec<Delivery>, Error> {
+        let storage = ctx.data::<Arc<RwLock<Storage>>>().unwrap();
+        let storage = storage.read().await;
+        Ok(storage
+            .deliveries
+            .iter()
+            .rev()
+            .filter(|delivery| webhook_id.is_none_or(|id| delivery.webhook_id == id))
+            .take(limit.max(0) as usize)
+            .cloned()
+            .collect())
+    }
+}
+
+pub struct MutationRoot;
+
+#[Object]
+impl MutationRoot {
+    /// Registers an endpoint that is POSTed every block or transaction
+    /// matching `filter` or a rule it is a channel of, signed with `secret`.
+    /// The body is the standard JSON payload unless `template` names a
+    /// configured template to render it with.
+    async fn register_webhook(
+        &self,
+        ctx: &Context<'_>,
+        url: String,
+        secret: String,
+        filter: Option<WebhookFilter>,
+        template: Option<String>,
+    ) -> Result<Webhook, Error> {
+        let parsed =
+            reqwest::Url::parse(&url).map_err(|e| Error::new(format!("Invalid url: {e}")))?;
+        if !matches!(parsed.scheme(), "http" | "https") {
+            return Err(Error::new("Webhook url must use http or https"));
+        }
+        if secret.is_empty() {
+            return Err(Error::new("Webhook secret must not be empty"));
+        }
+        let filter = filter.map(|mut filter| {
+            filter.address = filter.address.map(|address| address.to_lowercase());
+            filter
+        });
+
+        let storage = ctx.data::<Arc<RwLock<Storage>>>().unwrap();
+        let mut storage = storage.write().await;
+        Ok(storage.add_webhook(url, secret, filter, template)?)
+    }
+
+    /// Removes a webhook; `false` when there was none with this id. Fails
+    /// while a rule still uses it as a channel.
+    async fn delete_webhook(&self, ctx: &Context<'_>, id: u64) -> Result<bool, Error> {
+        let storage = ctx.data::<Arc<RwLock<Storage>>>().unwrap();
+        let mut storage = storage.write().await;
+        Ok(storage.remove_webhook(id)?.is_some())
+    }
+
+    async fn create_rule(
+        &self,
+        ctx: &Context<'_>,
+        input: NotificationRuleInput,
+    ) -> Result<NotificationRule, Error> {
+        let storage = ctx.data::<Arc<RwLock<Storage>>>().unwrap();
+        let mut storage = storage.write().await;
+        Ok(storage.add_rule(input)?)
+    }
+
+    async fn update_rule(
+        &self,
+        ctx: &Context<'_>,
+        id: u64,
+        update: NotificationRuleUpdate,
+    ) -> Result<NotificationRule, Error> {
+        let storage = ctx.data::<Arc<RwLock<Storage>>>().unwrap();
+        let mut storage = storage.write().await;
+        Ok(storage.update_rule(id, update)?)
+    }
+
+    /// Stops a rule from matching until `resumeRule`.
+    async fn pause_rule(&self, ctx: &Context<'_>, id: u64) -> Result<NotificationRule, Error> {
+        let storage = ctx.data::<Arc<RwLock<Storage>>>().unwrap();
+        let mut storage = storage.write().await;
+        Ok(storage.set_rule_paused(id, true)?)
+    }
+
+    async fn resume_rule(&self, ctx: &Context<'_>, id: u64) -> Result<NotificationRule, Error> {
+        let storage = ctx.data::<Arc<RwLock<Storage>>>().unwrap();
+        let mut storage = storage.write().await;
+        Ok(storage.set_rule_paused(id, false)?)
+    }
+
+    /// Removes a rule; `false` when there was none with this id.
+    async fn delete_rule(&self, ctx: &Context<'_>, id: u64) -> Result<bool, Error> {
+        let storage = ctx.data::<Arc<RwLock<Storage>>>().unwrap();
+        let mut storage = storage.write().await;
+        Ok(storage.remove_rule(id)?.is_some())
+    }
 }
 #[cfg(test)]
 mod tests {
     use super::*;
-    use async_graphql::Schema;
-    use std::collections::HashMap;
+
+    fn test_block(hash: &str, number: u64, timestamp: u64, transactions: Vec<&str>) -> Block {
+        Block {
+            chain_id: 1,
+            hash: hash.to_string(),
+            parent_hash: format!("parent_{hash}"),
+            number,
+            timestamp,
+            miner: Some("miner1".to_string()),
+            gas_used: 21000,
+            gas_limit: 30000000,
+            base_fee_per_gas: Some(7),
+            size: Some(1000),
+            extra_data: "0x".to_string(),
+            transactions: transactions.into_iter().map(String::from).collect(),
+            withdrawals: vec![],
+        }
+    }
+
+    fn test_transaction(
+        hash: &str,
+        block_hash: &str,
+        block_number: u64,
+        from: &str,
+        to: &str,
+        value: u64,
+    ) -> Transaction {
+        Transaction {
+            chain_id: 1,
+            hash: hash.to_string(),
+            block_hash: block_hash.to_string(),
+            block_number,
+            from: from.to_string(),
+            to: to.to_string(),
+            value: value.into(),
+            nonce: 0,
+            gas: 21000,
+            gas_price: Some(1000),
+            max_fee_per_gas: None,
+            max_priority_fee_per_gas: None,
+            input: "0x".to_string(),
+            transaction_type: Some(0),
+            transaction_index: Some(0),
+        }
+    }
 
     async fn create_test_storage() -> Arc<RwLock<Storage>> {
-        let mut blocks = HashMap::new();
-        let mut transactions = HashMap::new();
-
-        let block1 = Block {
-            hash: "hash1".to_string(),
-            number: 1,
-            timestamp: 1000,
-            transactions: vec!["tx1".to_string(), "tx2".to_string()],
-        };
-
-        let block2 = Block {
-            hash: "hash2".to_string(),
-            number: 2,
-            timestamp: 1100,
-            transactions: vec!["tx3".to_string()],
-        };
-
-        let block3 = Block {
-            hash: "hash3".to_string(),
-            number: 3,
-            timestamp: 1200,
-            transactions: vec!["tx4".to_string()],
-        };
-
-        blocks.insert(block1.hash.clone(), block1);
-        blocks.insert(block2.hash.clone(), block2);
-        blocks.insert(block3.hash.clone(), block3);
+        let mut storage = Storage::new();
+
+        storage
+            .add_block(test_block("hash1", 1, 1000, vec!["tx1", "tx2"]))
+            .await;
+        storage
+            .add_block(test_block("hash2", 2, 1100, vec!["tx3"]))
+            .await;
+        storage
+            .add_block(test_block("hash3", 3, 1200, vec!["tx4"]))
+            .await;
 
         // Create sample transactions
-        let tx1 = Transaction {
-            hash: "tx1".to_string(),
-            block_hash: "hash1".to_string(),
-            block_number: 1,
-            from: "addr1".to_string(),
-            to: "addr2".to_string(),
-            value: 100,
-        };
-
-        let tx2 = Transaction {
-            hash: "tx2".to_string(),
-            block_hash: "hash1".to_string(),
-            block_number: 1,
-            from: "addr2".to_string(),
-            to: "addr3".to_string(),
-            value: 200,
-        };
-
-        transactions.insert(tx1.hash.clone(), tx1);
-        transactions.insert(tx2.hash.clone(), tx2);
-
-        Arc::new(RwLock::new(Storage {
-            blocks,
-            transactions,
-        }))
+        storage
+            .add_transaction(test_transaction("tx1", "hash1", 1, "addr1", "addr2", 100))
+            .await;
+        storage
+            .add_transaction(test_transaction("tx2", "hash1", 1, "addr2", "addr3", 200))
+            .await;
+
+        Arc::new(RwLock::new(storage))
     }
 
     #[tokio::test]
     async fn test_get_block_by_hash() {
         let storage = create_test_storage().await;
-        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
-            .data(storage)
-            .finish();
+        let schema = build_schema(storage);
 
         let query = r#"
             query {
                 block(hash: "hash1") {
                     hash
+                    parentHash
                     number
                     timestamp
+                    miner
+                    gasUsed
+                    baseFeePerGas
                 }
             }
         "#;
 
         let res = schema.execute(query).await;
         println!("{res:?}");
-        assert!(res.data.to_string().contains("hash1"));
+        let data = res.data.to_string();
+        assert!(data.contains("hash1"));
+        assert!(data.contains("parent_hash1"));
+        assert!(data.contains("miner1"));
+        assert!(data.contains("21000"));
         assert!(res.errors.is_empty());
     }
 
     #[tokio::test]
     async fn test_get_blocks_by_number() {
         let storage = create_test_storage().await;
-        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
-            .data(storage)
-            .finish();
+        let schema = build_schema(storage);
 
         let query = r#"
             query {
@@ -180,9 +359,7 @@ mod tests {
     #[tokio::test]
     async fn test_get_latest_blocks() {
         let storage = create_test_storage().await;
-        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
-            .data(storage)
-            .finish();
+        let schema = build_schema(storage);
 
         let query = r#"
             query {
@@ -205,9 +382,7 @@ mod tests {
     #[tokio::test]
     async fn test_get_transaction() {
         let storage = create_test_storage().await;
-        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
-            .data(storage)
-            .finish();
+        let schema = build_schema(storage);
 
         let query = r#"
             query {
@@ -234,9 +409,7 @@ mod tests {
     #[tokio::test]
     async fn test_get_transactions_for_block() {
         let storage = create_test_storage().await;
-        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
-            .data(storage)
-            .finish();
+        let schema = build_schema(storage);
 
         let query = r#"
             query {
@@ -273,12 +446,117 @@ mod tests {
         assert!(res.errors.is_empty());
     }
 
+    #[tokio::test]
+    async fn test_get_block_with_nested_transactions() {
+        let storage = create_test_storage().await;
+        let schema = build_schema(storage);
+
+        let query = r#"
+            query {
+                block(hash: "hash1") {
+                    transactionHashes
+                    transactions {
+                        hash
+                        value
+                        block {
+                            number
+                        }
+                    }
+                }
+            }
+        "#;
+
+        let res = schema.execute(query).await;
+        assert!(res.errors.is_empty());
+        assert_eq!(
+            res.data.into_json().unwrap(),
+            serde_json::json!({
+                "block": {
+                    "transactionHashes": ["tx1", "tx2"],
+                    "transactions": [
+                        { "hash": "tx1", "value": "100", "block": { "number": 1 } },
+                        { "hash": "tx2", "value": "200", "block": { "number": 1 } },
+                    ]
+                }
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_transaction_block() {
+        let storage = create_test_storage().await;
+        let schema = build_schema(storage);
+
+        let query = r#"
+            query {
+                transaction(hash: "tx1") {
+                    block {
+                        hash
+                        timestamp
+                    }
+                }
+            }
+        "#;
+
+        let res = schema.execute(query).await;
+        let data = res.data.to_string();
+
+        assert!(data.contains("hash1"));
+        assert!(data.contains("1000"));
+        assert!(res.errors.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_query_per_chain() {
+        let storage = create_test_storage().await;
+        {
+            let mut storage = storage.write().await;
+            let mut block = test_block("hash1", 7, 900, vec!["tx1"]);
+            block.chain_id = 2;
+            storage.add_block(block).await;
+            let mut tx = test_transaction("tx1", "hash1", 7, "addr9", "addr8", 900);
+            tx.chain_id = 2;
+            storage.add_transaction(tx).await;
+        }
+        let schema = build_schema(storage);
+
+        let query = r#"
+            query {
+                block(chainId: 2, hash: "hash1") {
+                    chainId
+                    number
+                    transactions {
+                        value
+                        block {
+                            chainId
+                        }
+                    }
+                }
+                blocksByNumber(chainId: 1, number: 7) {
+                    hash
+                }
+            }
+        "#;
+
+        let res = schema.execute(query).await;
+        assert!(res.errors.is_empty());
+        assert_eq!(
+            res.data.into_json().unwrap(),
+            serde_json::json!({
+                "block": {
+                    "chainId": 2,
+                    "number": 7,
+                    "transactions": [{ "value": "900", "block": { "chainId": 2 } }]
+                },
+                "blocksByNumber": []
+            })
+        );
+    }
+
     #[tokio::test]
     async fn test_get_nonexistent_block() {
         let storage = create_test_storage().await;
-        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
-            .data(storage)
-            .finish();
+        let schema = build_schema(storage);
 
         let query = r#"
             query {
@@ -293,4 +571,146 @@ mod tests {
         assert!(res.data.to_string().contains("null"));
         assert!(res.errors.is_empty());
     }
+
+    #[tokio::test]
+    async fn test_register_and_delete_webhook() {
+        let storage = create_test_storage().await;
+        let schema = build_schema(storage.clone());
+
+        let mutation = r#"
+            mutation {
+                registerWebhook(
+                    url: "http://localhost:9000/hook"
+                    secret: "s3cret"
+                    filter: { address: "0xABC", minValue: "100000000000000000000", eventKinds: [TRANSACTION] }
+                ) {
+                    id
+                    url
+                    filter { address minValue eventKinds }
+                }
+            }
+        "#;
+        let res = schema.execute(mutation).await;
+        assert!(res.errors.is_empty(), "{:?}", res.errors);
+        assert_eq!(
+            res.data.into_json().unwrap(),
+            serde_json::json!({
+                "registerWebhook": {
+                    "id": 1,
+                    "url": "http://localhost:9000/hook",
+                    "filter": { "address": "0xabc", "minValue": "100000000000000000000", "eventKinds": ["TRANSACTION"] }
+                }
+            })
+        );
+        assert_eq!(
+            storage.read().await.subscriptions.webhooks[&1].secret,
+            "s3cret"
+        );
+
+        let res = schema
+            .execute(r#"mutation { registerWebhook(url: "ftp://host", secret: "s") { id } }"#)
+            .await;
+        assert!(res.errors[0].message.contains("http or https"));
+
+        let res = schema
+            .execute("mutation { first: deleteWebhook(id: 1) second: deleteWebhook(id: 1) }")
+            .await;
+        assert_eq!(
+            res.data.into_json().unwrap(),
+            serde_json::json!({ "first": true, "second": false })
+        );
+        assert!(storage.read().await.subscriptions.webhooks.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_manage_notification_rules() {
+        let storage = create_test_storage().await;
+        storage
+            .write()
+            .await
+            .add_webhook(
+                "http://localhost:9000/hook".to_string(),
+                "s".to_string(),
+                None,
+                None,
+            )
+            .unwrap();
+        let schema = build_schema(storage.clone());
+
+        let res = schema
+            .execute(
+                r#"
+                mutation {
+                    createRule(input: {
+                        name: "treasury"
+                        watchedAddresses: ["0xTREASURY"]
+                        minValue: 1000
+                        condition: "to != 0xdead"
+                        channels: [1]
+                    }) { id watchedAddresses condition paused }
+                }
+                "#,
+            )
+            .await;
+        assert!(res.errors.is_empty(), "{:?}", res.errors);
+        assert_eq!(
+            res.data.into_json().unwrap(),
+            serde_json::json!({
+                "createRule": {
+                    "id": 1,
+                    "watchedAddresses": ["0xtreasury"],
+                    "condition": "to != 0xdead",
+                    "paused": false
+                }
+            })
+        );
+
+        let res = schema
+            .execute(
+                r#"
+                mutation {
+                    updateRule(id: 1, update: { minValue: null, contracts: ["0xC0FFEE"], digestWindowSecs: 300 }) {
+                        name minValue contracts digestWindowSecs
+                    }
+                    pauseRule(id: 1) { paused }
+                }
+                "#,
+            )
+            .await;
+        assert!(res.errors.is_empty(), "{:?}", res.errors);
+        assert_eq!(
+            res.data.into_json().unwrap(),
+            serde_json::json!({
+                "updateRule": {
+                    "name": "treasury",
+                    "minValue": null,
+                    "contracts": ["0xc0ffee"],
+                    "digestWindowSecs": 300
+                },
+                "pauseRule": { "paused": true }
+            })
+        );
+
+        let res = schema
+            .execute(r#"mutation { createRule(input: { name: "x", channels: [2] }) { id } }"#)
+            .await;
+        assert!(res.errors[0].message.contains("No webhook with id 2"));
+
+        let res = schema
+            .execute(
+                r#"mutation { createRule(input: { name: "x", condition: "value >", channels: [1] }) { id } }"#,
+            )
+            .await;
+        assert_eq!(
+            res.errors[0].message,
+            "Invalid condition: expected a field or value, found end of expression at position 7"
+        );
+
+        let res = schema.execute("mutation { deleteWebhook(id: 1) }").await;
+        assert!(res.errors[0].message.contains("channel of rule 1"));
+
+        let res = schema.execute("mutation { deleteRule(id: 1) }").await;
+        assert!(res.errors.is_empty());
+        assert!(storage.read().await.subscriptions.rules.is_empty());
+    }
 }
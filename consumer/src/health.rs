@@ -0,0 +1,136 @@
+use axum::{extract::Extension, http::StatusCode, Json};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::RwLock;
+use tokio::time::timeout;
+
+use crate::metrics::CONSUMER_LAG;
+use crate::models::storage::Storage;
+
+/// Kafka state shared between `KafkaConsumer` and the health endpoints.
+pub struct ConsumerHealth {
+    kafka_connected: AtomicBool,
+    /// Messages behind the latest offset, keyed by `(topic, partition)`.
+    lag: Mutex<BTreeMap<(String, i32), i64>>,
+    /// Readiness fails when any partition lags further than this.
+    pub max_lag: i64,
+}
+
+impl ConsumerHealth {
+    pub fn new(max_lag: i64) -> Self {
+        Self {
+            kafka_connected: AtomicBool::new(false),
+            lag: Mutex::new(BTreeMap::new()),
+            max_lag,
+        }
+    }
+
+    pub fn set_kafka_connected(&self, connected: bool) {
+        self.kafka_connected.store(connected, Ordering::Relaxed);
+    }
+
+    pub fn kafka_connected(&self) -> bool {
+        self.kafka_connected.load(Ordering::Relaxed)
+    }
+
+    pub fn set_lag(&self, topic: &str, partition: i32, lag: i64) {
+        CONSUMER_LAG
+            .with_label_values(&[topic, &partition.to_string()])
+            .set(lag);
+        self.lag
+            .lock()
+            .unwrap()
+            .insert((topic.to_string(), partition), lag);
+    }
+
+    pub fn max_partition_lag(&self) -> i64 {
+        self.lag
+            .lock()
+            .unwrap()
+            .values()
+            .copied()
+            .max()
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReadinessReport {
+    status: &'static str,
+    kafka_connected: bool,
+    storage_reachable: bool,
+    max_partition_lag: i64,
+}
+
+pub(crate) async fn liveness() -> &'static str {
+    "ok"
+}
+
+pub(crate) async fn readiness(
+    health: Extension<Arc<ConsumerHealth>>,
+    storage: Extension<Arc<RwLock<Storage>>>,
+) -> (StatusCode, Json<ReadinessReport>) {
+    // A writer holding the lock for this long means queries would hang too.
+    let storage_reachable = timeout(Duration::from_secs(1), storage.read())
+        .await
+        .is_ok();
+    let kafka_connected = health.kafka_connected();
+    let max_partition_lag = health.max_partition_lag();
+
+    let ready = kafka_connected && storage_reachable && max_partition_lag <= health.max_lag;
+    let (code, status) = if ready {
+        (StatusCode::OK, "ok")
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, "unavailable")
+    };
+    (
+        code,
+        Json(ReadinessReport {
+            status,
+            kafka_connected,
+            storage_reachable,
+            max_partition_lag,
+        }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_readiness() {
+        let health = Arc::new(ConsumerHealth::new(100));
+        let storage = Arc::new(RwLock::new(Storage::new()));
+
+        let (code, report) = readiness(Extension(health.clone()), Extension(storage.clone())).await;
+        assert_eq!(code, StatusCode::SERVICE_UNAVAILABLE);
+        assert!(!report.kafka_connected);
+
+        health.set_kafka_connected(true);
+        health.set_lag("tx", 0, 5);
+        let (code, _) = readiness(Extension(health.clone()), Extension(storage.clone())).await;
+        assert_eq!(code, StatusCode::OK);
+
+        health.set_lag("tx", 1, 500);
+        let (code, report) = readiness(Extension(health.clone()), Extension(storage.clone())).await;
+        assert_eq!(code, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(report.max_partition_lag, 500);
+    }
+
+    #[tokio::test]
+    async fn test_readiness_fails_when_storage_locked() {
+        let health = Arc::new(ConsumerHealth::new(100));
+        health.set_kafka_connected(true);
+        let storage = Arc::new(RwLock::new(Storage::new()));
+
+        let _guard = storage.write().await;
+        let (code, report) = readiness(Extension(health), Extension(storage.clone())).await;
+        assert_eq!(code, StatusCode::SERVICE_UNAVAILABLE);
+        assert!(!report.storage_reachable);
+    }
+}
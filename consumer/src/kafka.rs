@@ -1,88 +1,353 @@
 use crate::{
+    config::{KafkaConfig, SnapshotConfig},
+    health::ConsumerHealth,
+    metrics::{MESSAGES_CONSUMED, PARSE_FAILURES, STORAGE_RECORDS},
     models::{
         block::{Block, BlockData},
+        event::Event,
         storage::Storage,
         transaction::{Transaction, TransactionData},
+        webhook::WebhookEvent,
     },
-    utils::hex_to_dec,
+    snapshot::{self, SavedOffset},
+    tracer::extract_context,
 };
 use kafka::{
-    client::{FetchOffset, GroupOffsetStorage},
+    client::{CommitOffset, FetchOffset, GroupOffsetStorage, KafkaClient},
     consumer::Consumer,
     Error,
 };
+use std::collections::HashMap;
 use std::sync::Arc;
-use tokio::sync::{Mutex, RwLock};
+use tokio::sync::{mpsc, Mutex, MutexGuard, RwLock};
+use tokio::task;
+use tokio::time::{sleep, Duration, Instant};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, field, info, info_span, warn, Instrument, Span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+const POLL_RETRY_DELAY: Duration = Duration::from_secs(1);
+const LAG_CHECK_INTERVAL: Duration = Duration::from_secs(10);
 
 pub struct KafkaConsumer {
     consumer: Arc<Mutex<Consumer>>,
+    group: String,
     storage: Arc<RwLock<Storage>>,
+    snapshot: SnapshotConfig,
+    block_topics: Vec<String>,
+    tx_topics: Vec<String>,
+    health: Arc<ConsumerHealth>,
+    webhooks: mpsc::Sender<WebhookEvent>,
 }
 
 impl KafkaConsumer {
+    /// `restored` are the offsets of a snapshot the storage was restored
+    /// from. They are committed for the consumer group before consuming
+    /// starts, so that consuming resumes where the snapshot was taken.
     pub fn new(
-        hosts: Vec<String>,
-        topics: Vec<String>,
+        config: &KafkaConfig,
+        snapshot: &SnapshotConfig,
+        restored: Option<&[SavedOffset]>,
         storage: Arc<RwLock<Storage>>,
+        health: Arc<ConsumerHealth>,
+        webhooks: mpsc::Sender<WebhookEvent>,
     ) -> Result<Self, Error> {
-        let mut consumer_builder = Consumer::from_hosts(hosts)
+        let mut client = KafkaClient::new(config.hosts.clone());
+        client.set_group_offset_storage(Some(GroupOffsetStorage::Kafka));
+        if let Some(offsets) = restored {
+            let topics: Vec<&String> = config
+                .block_topics
+                .iter()
+                .chain(config.tx_topics.iter())
+                .collect();
+            client.load_metadata(&topics)?;
+            // Offsets of topics that are no longer consumed are left alone.
+            let offsets: Vec<CommitOffset> = offsets
+                .iter()
+                .filter(|saved| topics.contains(&&saved.topic))
+                .map(|saved| CommitOffset::new(&saved.topic, saved.partition, saved.offset))
+                .collect();
+            client.commit_offsets(&config.group, &offsets)?;
+            info!(
+                partitions = offsets.len(),
+                "Resuming from the snapshot's offsets"
+            );
+        }
+        let mut consumer_builder = Consumer::from_client(client)
             .with_fallback_offset(FetchOffset::Earliest)
-            .with_group("group".to_owned())
+            .with_group(config.group.clone())
             .with_offset_storage(Some(GroupOffsetStorage::Kafka));
-        for topic in topics {
-            consumer_builder = consumer_builder.with_topic(topic);
+        for topic in config.block_topics.iter().chain(config.tx_topics.iter()) {
+            consumer_builder = consumer_builder.with_topic(topic.clone());
         }
-        let consumer = consumer_builder.create().unwrap();
+        let consumer = consumer_builder.create()?;
 
         Ok(Self {
             consumer: Arc::new(Mutex::new(consumer)),
+            group: config.group.clone(),
             storage,
+            snapshot: snapshot.clone(),
+            block_topics: config.block_topics.clone(),
+            tx_topics: config.tx_topics.clone(),
+            health,
+            webhooks,
         })
     }
 
-    pub async fn start_consuming(&self) {
-        loop {
+    /// Consumes until `shutdown` is cancelled. A batch that is being processed
+    /// when that happens is finished and its offsets committed first.
+    /// Snapshots are taken between batches, after their offsets are committed.
+    pub async fn start_consuming(&self, shutdown: CancellationToken) {
+        let mut last_lag_check: Option<Instant> = None;
+        let mut last_snapshot = Instant::now();
+        let snapshot_interval = Duration::from_secs(self.snapshot.interval_secs);
+        while !shutdown.is_cancelled() {
             let mut consumer = self.consumer.lock().await;
-            for ms in consumer.poll().unwrap().iter() {
+            let message_sets = match consumer.poll() {
+                Ok(message_sets) => {
+                    self.health.set_kafka_connected(true);
+                    message_sets
+                }
+                Err(e) => {
+                    warn!(error = %e, "Failed to poll Kafka");
+                    self.health.set_kafka_connected(false);
+                    drop(consumer);
+                    tokio::select! {
+                        _ = sleep(POLL_RETRY_DELAY) => {}
+                        _ = shutdown.cancelled() => {}
+                    }
+                    continue;
+                }
+            };
+            for ms in message_sets.iter() {
                 for message in ms.messages() {
-                    match ms.topic() {
-                        "block" => match serde_json::from_slice::<BlockData>(message.value) {
-                            Ok(block) => {
-                                let mut storage = self.storage.write().await;
-                                storage
-                                    .add_block(Block {
-                                        hash: block.hash,
-                                        number: hex_to_dec(block.number),
-                                        timestamp: hex_to_dec(block.timestamp),
-                                        transactions: block.transactions,
-                                    })
-                                    .await;
+                    let topic = ms.topic();
+                    MESSAGES_CONSUMED.with_label_values(&[topic]).inc();
+                    if self.block_topics.iter().any(|t| t == topic) {
+                        match serde_json::from_slice::<Event<BlockData>>(message.value) {
+                            Ok(event) => {
+                                let span = consume_span(
+                                    &event.headers,
+                                    topic,
+                                    ms.partition(),
+                                    message.offset,
+                                );
+                                let block: Block = event.into();
+                                span.record("block.number", block.number);
+                                async {
+                                    debug!(block.number = block.number, "Consumed block");
+                                    self.insert_block(block).await;
+                                }
+                                .instrument(span)
+                                .await;
                             }
-                            Err(e) => eprintln!("Failed to parse block: {}", e),
-                        },
-                        "tx" => match serde_json::from_slice::<TransactionData>(message.value) {
-                            Ok(transaction) => {
-                                let mut storage = self.storage.write().await;
-
-                                storage
-                                    .add_transaction(Transaction {
-                                        hash: transaction.hash,
-                                        block_hash: transaction.block_hash,
-                                        from: transaction.from,
-                                        to: transaction.to,
-                                        value: hex_to_dec(transaction.value),
-                                        block_number: hex_to_dec(transaction.block_number),
-                                    })
-                                    .await;
+                            Err(e) => {
+                                PARSE_FAILURES.with_label_values(&[topic]).inc();
+                                error!(
+                                    topic,
+                                    partition = ms.partition(),
+                                    offset = message.offset,
+                                    error = %e,
+                                    "Failed to parse block"
+                                )
+                            }
+                        }
+                    } else if self.tx_topics.iter().any(|t| t == topic) {
+                        match serde_json::from_slice::<Event<TransactionData>>(message.value) {
+                            Ok(event) => {
+                                let span = consume_span(
+                                    &event.headers,
+                                    topic,
+                                    ms.partition(),
+                                    message.offset,
+                                );
+                                let transaction: Transaction = event.into();
+                                span.record("block.number", transaction.block_number);
+                                span.record("tx.hash", transaction.hash.as_str());
+                                async {
+                                    debug!(tx.hash = %transaction.hash, "Consumed transaction");
+                                    self.insert_transaction(transaction).await;
+                                }
+                                .instrument(span)
+                                .await;
+                            }
+                            Err(e) => {
+                                PARSE_FAILURES.with_label_values(&[topic]).inc();
+                                error!(
+                                    topic,
+                                    partition = ms.partition(),
+                                    offset = message.offset,
+                                    error = %e,
+                                    "Failed to parse transaction"
+                                )
                             }
-                            Err(e) => eprintln!("Failed to parse transaction: {}", e),
-                        },
-                        _ => {}
+                        }
                     }
                 }
                 let _ = consumer.consume_messageset(ms);
             }
-            consumer.commit_consumed().unwrap();
+            let committed = match consumer.commit_consumed() {
+                Ok(()) => true,
+                Err(e) => {
+                    warn!(error = %e, "Failed to commit offsets");
+                    self.health.set_kafka_connected(false);
+                    false
+                }
+            };
+            if last_lag_check.is_none_or(|checked| checked.elapsed() >= LAG_CHECK_INTERVAL) {
+                self.update_lag(&mut consumer);
+                last_lag_check = Some(Instant::now());
+            }
+            if committed && last_snapshot.elapsed() >= snapshot_interval {
+                self.take_snapshot(consumer).await;
+                last_snapshot = Instant::now();
+            }
+        }
+
+        // Retries a commit that failed on the last batch; a no-op otherwise.
+        let mut consumer = self.consumer.lock().await;
+        match consumer.commit_consumed() {
+            Ok(()) => {
+                info!("Kafka consumer stopped, offsets committed");
+                self.take_snapshot(consumer).await;
+            }
+            Err(e) => error!(error = %e, "Failed to commit offsets on shutdown"),
         }
     }
+
+    /// Writes the storage's records with the group's committed offsets to the
+    /// snapshot file, when there is one. Called right after a commit, so the
+    /// offsets are those of the last record in storage. The consumer is
+    /// released once the offsets are fetched, and the storage once the
+    /// records are serialized; the file is written on the blocking pool.
+    async fn take_snapshot(&self, mut consumer: MutexGuard<'_, Consumer>) {
+        let Some(path) = &self.snapshot.file else {
+            return;
+        };
+        let mut offsets = Vec::new();
+        for topic in consumer.subscriptions().into_keys() {
+            match consumer
+                .client_mut()
+                .fetch_group_topic_offset(&self.group, &topic)
+            {
+                Ok(partitions) => offsets.extend(
+                    partitions
+                        .into_iter()
+                        // Partitions the group has not committed yet.
+                        .filter(|partition| partition.offset >= 0)
+                        .map(|partition| SavedOffset {
+                            topic: topic.clone(),
+                            partition: partition.partition,
+                            offset: partition.offset,
+                        }),
+                ),
+                Err(e) => {
+                    warn!(topic, error = %e, "Failed to fetch committed offsets, no snapshot taken");
+                    return;
+                }
+            }
+        }
+        drop(consumer);
+        let storage = self.storage.read().await;
+        let (blocks, transactions) = (storage.blocks.len(), storage.transactions.len());
+        let content = snapshot::encode(&storage, &offsets);
+        drop(storage);
+        let file = path.clone();
+        let result = match content {
+            Ok(content) => task::spawn_blocking(move || snapshot::write(&file, &content))
+                .await
+                .map_err(anyhow::Error::from)
+                .and_then(|result| result),
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(()) => info!(
+                path = %path.display(),
+                blocks,
+                transactions,
+                "Snapshot taken"
+            ),
+            Err(e) => warn!(error = %e, "Failed to take snapshot"),
+        }
+    }
+
+    #[tracing::instrument(name = "storage_insert", skip_all)]
+    async fn insert_block(&self, block: Block) {
+        let event = WebhookEvent::Block(block.clone());
+        {
+            let mut storage = self.storage.write().await;
+            storage.add_block(block).await;
+            STORAGE_RECORDS
+                .with_label_values(&["block"])
+                .set(storage.blocks.len() as i64);
+        }
+        self.dispatch(event).await;
+    }
+
+    #[tracing::instrument(name = "storage_insert", skip_all)]
+    async fn insert_transaction(&self, transaction: Transaction) {
+        let event = WebhookEvent::Transaction(transaction.clone());
+        {
+            let mut storage = self.storage.write().await;
+            storage.add_transaction(transaction).await;
+            STORAGE_RECORDS
+                .with_label_values(&["transaction"])
+                .set(storage.transactions.len() as i64);
+        }
+        self.dispatch(event).await;
+    }
+
+    /// Hands a stored record to the webhook dispatcher, waiting while its
+    /// queue is full.
+    async fn dispatch(&self, event: WebhookEvent) {
+        if self.webhooks.send(event).await.is_err() {
+            warn!("Webhook dispatcher is gone, event not dispatched");
+        }
+    }
+
+    /// Records how far each partition with consumed messages is behind its
+    /// latest offset. Partitions we have not consumed from yet are skipped.
+    fn update_lag(&self, consumer: &mut Consumer) {
+        let topics: Vec<String> = consumer.subscriptions().into_keys().collect();
+        let latest = match consumer
+            .client_mut()
+            .fetch_offsets(&topics, FetchOffset::Latest)
+        {
+            Ok(latest) => latest,
+            Err(e) => {
+                warn!(error = %e, "Failed to fetch latest offsets");
+                return;
+            }
+        };
+        for (topic, partitions) in latest {
+            for partition in partitions {
+                if let Some(consumed) = consumer.last_consumed_message(&topic, partition.partition)
+                {
+                    // The latest offset is the one the next message will get.
+                    let lag = (partition.offset - consumed - 1).max(0);
+                    self.health.set_lag(&topic, partition.partition, lag);
+                }
+            }
+        }
+    }
+}
+
+/// Starts the span for one consumed message, continuing the producer's trace
+/// when the message carries one.
+fn consume_span(
+    headers: &HashMap<String, String>,
+    topic: &str,
+    partition: i32,
+    offset: i64,
+) -> Span {
+    let span = info_span!(
+        "kafka_consume",
+        messaging.destination.name = topic,
+        messaging.kafka.destination.partition = partition,
+        messaging.kafka.message.offset = offset,
+        block.number = field::Empty,
+        tx.hash = field::Empty,
+    );
+    span.set_parent(extract_context(headers));
+    span
 }
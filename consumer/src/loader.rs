@@ -0,0 +1,70 @@
+use async_graphql::dataloader::Loader;
+use async_graphql::*;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+use crate::models::{
+    block::Block,
+    storage::{RecordKey, Storage},
+    transaction::Transaction,
+};
+
+/// Batches block lookups by chain and hash so nested resolvers share one storage read.
+pub struct BlockLoader {
+    storage: Arc<RwLock<Storage>>,
+}
+
+impl BlockLoader {
+    pub fn new(storage: Arc<RwLock<Storage>>) -> Self {
+        Self { storage }
+    }
+}
+
+#[async_trait::async_trait]
+impl Loader<RecordKey> for BlockLoader {
+    type Value = Block;
+    type Error = Infallible;
+
+    async fn load(&self, keys: &[RecordKey]) -> Result<HashMap<RecordKey, Block>, Infallible> {
+        let storage = self.storage.read().await;
+        Ok(keys
+            .iter()
+            .filter_map(|key| storage.blocks.get(key).map(|b| (key.clone(), b.clone())))
+            .collect())
+    }
+}
+
+/// Batches transaction lookups by chain and hash so nested resolvers share one storage read.
+pub struct TransactionLoader {
+    storage: Arc<RwLock<Storage>>,
+}
+
+impl TransactionLoader {
+    pub fn new(storage: Arc<RwLock<Storage>>) -> Self {
+        Self { storage }
+    }
+}
+
+#[async_trait::async_trait]
+impl Loader<RecordKey> for TransactionLoader {
+    type Value = Transaction;
+    type Error = Infallible;
+
+    async fn load(
+        &self,
+        keys: &[RecordKey],
+    ) -> Result<HashMap<RecordKey, Transaction>, Infallible> {
+        let storage = self.storage.read().await;
+        Ok(keys
+            .iter()
+            .filter_map(|key| {
+                storage
+                    .transactions
+                    .get(key)
+                    .map(|tx| (key.clone(), tx.clone()))
+            })
+            .collect())
+    }
+}
@@ -1,52 +1,170 @@
+mod channel;
+mod config;
 mod controller;
+mod health;
 mod kafka;
+mod loader;
+mod metrics;
 mod models;
+mod retention;
 mod routes;
+mod snapshot;
+mod template;
+mod tracer;
 mod utils;
-use crate::controller::QueryRoot;
+mod webhook;
+use crate::config::{Args, Config};
+use crate::controller::build_schema;
 use crate::models::storage::Storage;
-use async_graphql::{EmptyMutation, EmptySubscription, Schema};
 use axum::{extract::Extension, routing::get, Router, Server};
+use clap::Parser;
 use dotenv::dotenv;
+use health::{liveness, readiness, ConsumerHealth};
 use kafka::KafkaConsumer;
+use metrics::metrics_handler;
+use opentelemetry::global;
 use routes::{graphql_handler, graphql_playground};
-use std::env;
+use std::process;
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use template::Templates;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{mpsc, RwLock};
+use tokio_util::sync::CancellationToken;
+use tracer::{init_tracing, TracingConfig};
+use tracing::{error, info};
+use webhook::{WebhookDispatcher, EVENT_QUEUE_SIZE};
+
+/// Cancels `shutdown` on SIGINT or SIGTERM.
+async fn cancel_on_signal(shutdown: CancellationToken) {
+    let mut terminate = signal(SignalKind::terminate()).expect("Failed to listen for SIGTERM");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate.recv() => {}
+    }
+    info!("Shutdown requested");
+    shutdown.cancel();
+}
 
 #[tokio::main]
 async fn main() {
     dotenv().ok();
-    let storage = Arc::new(RwLock::new(Storage::new()));
-    let hosts: Vec<String> = env::var("KAFKA_BROKER_HOST")
-        .expect("KAFKA_BROKER_HOST not set")
-        .split(',')
-        .map(|s| s.to_string())
+    let args = Args::parse();
+    init_tracing(&TracingConfig::from_env());
+    let config = Config::load(&args).unwrap_or_else(|e| {
+        error!("Invalid configuration: {e:#}");
+        process::exit(1);
+    });
+
+    let mut storage = match &config.state_file {
+        Some(path) => Storage::with_state_file(path).unwrap_or_else(|e| {
+            error!("Failed to restore state: {e:#}");
+            process::exit(1);
+        }),
+        None => Storage::new(),
+    };
+    let templates = Templates::new(&config.templates, &config.explorers).unwrap_or_else(|e| {
+        error!("Failed to set up templates: {e:#}");
+        process::exit(1);
+    });
+    let templates = Arc::new(templates);
+    let restored = match &config.snapshot.file {
+        Some(path) => match snapshot::read(path) {
+            Ok(Some(snapshot)) => {
+                info!(
+                    path = %path.display(),
+                    taken_at = snapshot.taken_at,
+                    blocks = snapshot.blocks.len(),
+                    transactions = snapshot.transactions.len(),
+                    "Restoring snapshot"
+                );
+                Some(snapshot.restore(&mut storage).await)
+            }
+            Ok(None) => None,
+            Err(e) => {
+                error!("Failed to restore snapshot: {e:#}");
+                process::exit(1);
+            }
+        },
+        None => None,
+    };
+    storage.address_books = config.address_books.clone().into_iter().collect();
+    storage.templates = templates.names();
+    storage.notification_channels = config
+        .channels
+        .iter()
+        .map(|channel| channel.name.clone())
         .collect();
-    let tsx_topic = env::var("KAFKA_TX_TOPIC").expect("KAFKA_TX_TOPIC not set");
-    let block_topic = env::var("KAFKA_BLOCK_TOPIC").expect("KAFKA_BLOCK_TOPIC not set");
-    let kafka_consumer = KafkaConsumer::new(hosts, vec![tsx_topic, block_topic], storage.clone())
-        .expect("Failed to create Kafka consumer");
+    let storage = Arc::new(RwLock::new(storage));
+    let health = Arc::new(ConsumerHealth::new(config.server.max_consumer_lag));
+    let (webhook_events, webhook_receiver) = mpsc::channel(EVENT_QUEUE_SIZE);
+    let dispatcher = WebhookDispatcher::new(
+        &config.webhook,
+        &config.channels,
+        templates,
+        storage.clone(),
+    )
+    .unwrap_or_else(|e| {
+        error!("Failed to set up notification channels: {e:#}");
+        process::exit(1);
+    });
+    let kafka_consumer = KafkaConsumer::new(
+        &config.kafka,
+        &config.snapshot,
+        restored.as_deref(),
+        storage.clone(),
+        health.clone(),
+        webhook_events,
+    )
+    .unwrap_or_else(|e| {
+        error!("Failed to create Kafka consumer: {e:#}");
+        process::exit(1);
+    });
+
+    let shutdown = CancellationToken::new();
+    tokio::spawn(cancel_on_signal(shutdown.clone()));
+
+    // Stops once the Kafka consumer, which holds the only sender, is done.
+    let dispatcher_handle = tokio::spawn(dispatcher.run(webhook_receiver, shutdown.clone()));
 
+    let consumer_shutdown = shutdown.clone();
     let consumer_handle = tokio::spawn(async move {
-        kafka_consumer.start_consuming().await;
+        kafka_consumer.start_consuming(consumer_shutdown).await;
     });
 
-    let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
-        .data(storage.clone())
-        .finish();
+    let retention_handle = tokio::spawn(retention::run(
+        storage.clone(),
+        config.retention.clone(),
+        shutdown.clone(),
+    ));
+
+    let schema = build_schema(storage.clone());
 
     let app = Router::new()
         .route("/", get(graphql_playground).post(graphql_handler))
-        .layer(Extension(schema));
+        .route("/healthz", get(liveness))
+        .route("/readyz", get(readiness))
+        .route("/metrics", get(metrics_handler))
+        .layer(Extension(schema))
+        .layer(Extension(health))
+        .layer(Extension(storage.clone()));
 
     let server_handle = tokio::spawn(async move {
-        Server::bind(&"0.0.0.0:3000".parse().unwrap())
+        info!(addr = %config.server.addr, "GraphQL server listening");
+        Server::bind(&config.server.addr)
             .serve(app.into_make_service())
+            .with_graceful_shutdown(shutdown.cancelled_owned())
             .await
             .unwrap();
     });
 
-    // Wait for both tasks
-    tokio::try_join!(consumer_handle, server_handle).unwrap();
+    // Wait for all tasks
+    tokio::try_join!(
+        consumer_handle,
+        dispatcher_handle,
+        retention_handle,
+        server_handle
+    )
+    .unwrap();
+
+    global::shutdown_tracer_provider();
 }
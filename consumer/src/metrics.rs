@@ -0,0 +1,171 @@
+use async_graphql::extensions::{
+    Extension, ExtensionContext, ExtensionFactory, NextResolve, ResolveInfo,
+};
+use async_graphql::{async_trait, ServerResult, Value};
+use axum::http::{header, StatusCode};
+use prometheus::{
+    register_histogram_vec, register_int_counter, register_int_counter_vec, register_int_gauge_vec,
+    Encoder, HistogramVec, IntCounter, IntCounterVec, IntGaugeVec, TextEncoder,
+};
+use std::sync::{Arc, LazyLock};
+
+pub static MESSAGES_CONSUMED: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "consumer_messages_consumed_total",
+        "Kafka messages consumed",
+        &["topic"]
+    )
+    .unwrap()
+});
+
+pub static PARSE_FAILURES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "consumer_parse_failures_total",
+        "Kafka messages that could not be parsed",
+        &["topic"]
+    )
+    .unwrap()
+});
+
+pub static STORAGE_RECORDS: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register_int_gauge_vec!(
+        "consumer_storage_records",
+        "Records held in storage",
+        &["kind"]
+    )
+    .unwrap()
+});
+
+pub static PRUNED_RECORDS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "consumer_pruned_records_total",
+        "Records removed from storage by the retention policies",
+        &["kind"]
+    )
+    .unwrap()
+});
+
+pub static CONSUMER_LAG: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register_int_gauge_vec!(
+        "consumer_partition_lag",
+        "Messages between the last consumed and the latest offset",
+        &["topic", "partition"]
+    )
+    .unwrap()
+});
+
+pub static WEBHOOK_DELIVERIES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "consumer_webhook_deliveries_total",
+        "Webhook deliveries by final status",
+        &["status"]
+    )
+    .unwrap()
+});
+
+pub static WEBHOOK_ATTEMPTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "consumer_webhook_attempts_total",
+        "Webhook requests by outcome",
+        &["outcome"]
+    )
+    .unwrap()
+});
+
+pub static CHANNEL_NOTIFICATIONS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "consumer_channel_notifications_total",
+        "Notification channel messages by channel and final status",
+        &["channel", "status"]
+    )
+    .unwrap()
+});
+
+pub static DUPLICATE_NOTIFICATIONS: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(
+        "consumer_duplicate_notifications_total",
+        "Rule notifications skipped because the event was already notified"
+    )
+    .unwrap()
+});
+
+pub static RESOLVER_LATENCY: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "consumer_graphql_resolver_latency_seconds",
+        "GraphQL resolver latency",
+        &["parent_type", "field"]
+    )
+    .unwrap()
+});
+
+/// Renders every registered metric in the Prometheus text format.
+pub(crate) async fn metrics_handler() -> (StatusCode, [(header::HeaderName, String); 1], Vec<u8>) {
+    let encoder = TextEncoder::new();
+    let mut buffer = Vec::new();
+    match encoder.encode(&prometheus::gather(), &mut buffer) {
+        Ok(()) => (
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, encoder.format_type().to_string())],
+            buffer,
+        ),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            [(header::CONTENT_TYPE, "text/plain".to_string())],
+            e.to_string().into_bytes(),
+        ),
+    }
+}
+
+/// Schema extension recording the latency of every non-introspection resolver.
+pub struct ResolverMetrics;
+
+impl ExtensionFactory for ResolverMetrics {
+    fn create(&self) -> Arc<dyn Extension> {
+        Arc::new(ResolverMetricsExtension)
+    }
+}
+
+struct ResolverMetricsExtension;
+
+#[async_trait::async_trait]
+impl Extension for ResolverMetricsExtension {
+    async fn resolve(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        info: ResolveInfo<'_>,
+        next: NextResolve<'_>,
+    ) -> ServerResult<Option<Value>> {
+        if info.is_for_introspection {
+            return next.run(ctx, info).await;
+        }
+        let timer = RESOLVER_LATENCY
+            .with_label_values(&[info.parent_type, info.name])
+            .start_timer();
+        let result = next.run(ctx, info).await;
+        timer.observe_duration();
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controller::build_schema;
+    use crate::models::storage::Storage;
+    use tokio::sync::RwLock;
+
+    #[tokio::test]
+    async fn test_resolver_latency_is_exported() {
+        let schema = build_schema(Arc::new(RwLock::new(Storage::new())));
+        let res = schema.execute("{ latestBlocks(limit: 1) { hash } }").await;
+        assert!(res.errors.is_empty());
+
+        let (code, _, body) = metrics_handler().await;
+        let body = String::from_utf8(body).unwrap();
+
+        assert_eq!(code, StatusCode::OK);
+        assert!(body.contains(
+            r#"consumer_graphql_resolver_latency_seconds_count{field="latestBlocks",parent_type="QueryRoot"}"#
+        ));
+    }
+}
@@ -1,28 +1,87 @@
-use async_graphql::*;
+use async_graphql::{dataloader::DataLoader, *};
 use serde::{Deserialize, Serialize};
 
+use super::event::Event;
+use super::transaction::Transaction;
+use super::withdrawal::{Withdrawal, WithdrawalData};
+use crate::loader::TransactionLoader;
+use crate::utils::hex_to_dec;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct BlockData {
     pub hash: String,
+    pub parent_hash: String,
     pub number: String,
     pub timestamp: String,
+    pub miner: Option<String>,
+    pub gas_used: String,
+    pub gas_limit: String,
+    pub base_fee_per_gas: Option<String>,
+    pub size: Option<String>,
+    pub extra_data: String,
     pub transactions: Vec<String>,
+    #[serde(default)]
+    pub withdrawals: Option<Vec<WithdrawalData>>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Block {
+    pub chain_id: u64,
     pub hash: String,
+    pub parent_hash: String,
     pub number: u64,
     pub timestamp: u64,
+    pub miner: Option<String>,
+    pub gas_used: u64,
+    pub gas_limit: u64,
+    pub base_fee_per_gas: Option<u64>,
+    pub size: Option<u64>,
+    pub extra_data: String,
     pub transactions: Vec<String>,
+    pub withdrawals: Vec<Withdrawal>,
+}
+
+impl From<Event<BlockData>> for Block {
+    fn from(event: Event<BlockData>) -> Self {
+        let block = event.data;
+        Self {
+            chain_id: event.chain_id,
+            hash: block.hash,
+            parent_hash: block.parent_hash,
+            number: hex_to_dec(block.number),
+            timestamp: hex_to_dec(block.timestamp),
+            miner: block.miner,
+            gas_used: hex_to_dec(block.gas_used),
+            gas_limit: hex_to_dec(block.gas_limit),
+            base_fee_per_gas: block.base_fee_per_gas.map(hex_to_dec),
+            size: block.size.map(hex_to_dec),
+            extra_data: block.extra_data,
+            transactions: block.transactions,
+            withdrawals: block
+                .withdrawals
+                .unwrap_or_default()
+                .into_iter()
+                .map(Withdrawal::from)
+                .collect(),
+        }
+    }
 }
 
 #[Object]
 impl Block {
+    async fn chain_id(&self) -> u64 {
+        self.chain_id
+    }
+
     async fn hash(&self) -> &str {
         &self.hash
     }
 
+    async fn parent_hash(&self) -> &str {
+        &self.parent_hash
+    }
+
     async fn number(&self) -> u64 {
         self.number
     }
@@ -31,7 +90,114 @@ impl Block {
         self.timestamp
     }
 
-    async fn transactions(&self) -> &Vec<String> {
+    async fn miner(&self) -> Option<&str> {
+        self.miner.as_deref()
+    }
+
+    async fn gas_used(&self) -> u64 {
+        self.gas_used
+    }
+
+    async fn gas_limit(&self) -> u64 {
+        self.gas_limit
+    }
+
+    /// Base fee in wei, absent for pre-London blocks.
+    async fn base_fee_per_gas(&self) -> Option<u64> {
+        self.base_fee_per_gas
+    }
+
+    /// Block size in bytes.
+    async fn size(&self) -> Option<u64> {
+        self.size
+    }
+
+    async fn extra_data(&self) -> &str {
+        &self.extra_data
+    }
+
+    async fn withdrawals(&self) -> &Vec<Withdrawal> {
+        &self.withdrawals
+    }
+
+    async fn transaction_hashes(&self) -> &Vec<String> {
         &self.transactions
     }
+
+    async fn transactions(&self, ctx: &Context<'_>) -> Result<Vec<Transaction>, Error> {
+        let loader = ctx.data::<DataLoader<TransactionLoader>>()?;
+        let keys = self
+            .transactions
+            .iter()
+            .map(|hash| (self.chain_id, hash.clone()))
+            .collect::<Vec<_>>();
+        let mut transactions = loader.load_many(keys.iter().cloned()).await?;
+        Ok(keys
+            .iter()
+            .filter_map(|key| transactions.remove(key))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_block_from_ethers_payload() {
+        let payload = r#"{ "chainId": 1, "data": {
+            "hash": "0xabc",
+            "parentHash": "0xdef",
+            "sha3Uncles": "0x00",
+            "miner": "0x4200000000000000000000000000000000000011",
+            "number": "0x105f1a2",
+            "gasUsed": "0x5208",
+            "gasLimit": "0x1c9c380",
+            "extraData": "0x",
+            "timestamp": "0x6543a1b0",
+            "transactions": ["0x01", "0x02"],
+            "size": "0x3e8",
+            "baseFeePerGas": "0x7",
+            "withdrawals": [
+                { "index": "0x1", "validatorIndex": "0x2", "address": "0x00", "amount": "0x64" }
+            ]
+        } }"#;
+
+        let block = Block::from(serde_json::from_str::<Event<BlockData>>(payload).unwrap());
+
+        assert_eq!(block.chain_id, 1);
+        assert_eq!(block.parent_hash, "0xdef");
+        assert_eq!(block.number, 17166754);
+        assert_eq!(block.gas_used, 21000);
+        assert_eq!(block.gas_limit, 30000000);
+        assert_eq!(block.base_fee_per_gas, Some(7));
+        assert_eq!(block.size, Some(1000));
+        assert_eq!(block.transactions.len(), 2);
+        assert_eq!(block.withdrawals.len(), 1);
+        assert_eq!(block.withdrawals[0].validator_index, 2);
+        assert_eq!(block.withdrawals[0].amount, 100);
+    }
+
+    #[test]
+    fn test_block_without_london_fields() {
+        let payload = r#"{ "chainId": 1, "data": {
+            "hash": "0xabc",
+            "parentHash": "0xdef",
+            "miner": null,
+            "number": "0x1",
+            "gasUsed": "0x0",
+            "gasLimit": "0x1388",
+            "extraData": "0x",
+            "timestamp": "0x0",
+            "transactions": [],
+            "size": null,
+            "baseFeePerGas": null
+        } }"#;
+
+        let block = Block::from(serde_json::from_str::<Event<BlockData>>(payload).unwrap());
+
+        assert_eq!(block.miner, None);
+        assert_eq!(block.base_fee_per_gas, None);
+        assert!(block.withdrawals.is_empty());
+    }
 }
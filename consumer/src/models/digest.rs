@@ -0,0 +1,264 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use super::rule::NotificationRule;
+use super::webhook::WebhookEvent;
+
+/// Counterparties listed in a digest, most active first.
+const TOP_COUNTERPARTIES: usize = 5;
+
+/// The events a rule matched on one chain within its digest window, summed
+/// up into one notification.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Digest {
+    pub rule_id: u64,
+    pub chain_id: u64,
+    /// Unix time in seconds of the first event and of the end of the window.
+    pub started_at: u64,
+    pub ended_at: u64,
+    pub events: u64,
+    pub transactions: u64,
+    pub blocks: u64,
+    /// Sum of the transaction values in wei, as a decimal string since it
+    /// does not always fit 64 bits.
+    pub total_value: String,
+    pub top_counterparties: Vec<Counterparty>,
+}
+
+/// An address on the other side of a rule's events: the sender or recipient
+/// that is not watched, or the miner of a block.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Counterparty {
+    pub address: String,
+    pub events: u64,
+    /// Wei, as a decimal string.
+    pub value: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct Tally {
+    events: u64,
+    value: u128,
+}
+
+/// A digest still collecting events.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct PendingDigest {
+    rule_id: u64,
+    chain_id: u64,
+    started_at: u64,
+    /// When the digest is sent.
+    due_at: u64,
+    transactions: u64,
+    blocks: u64,
+    total_value: u128,
+    counterparties: BTreeMap<String, Tally>,
+}
+
+impl PendingDigest {
+    fn finish(self) -> Digest {
+        let mut counterparties: Vec<(String, Tally)> = self.counterparties.into_iter().collect();
+        counterparties
+            .sort_by(|(_, a), (_, b)| b.events.cmp(&a.events).then(b.value.cmp(&a.value)));
+        Digest {
+            rule_id: self.rule_id,
+            chain_id: self.chain_id,
+            started_at: self.started_at,
+            ended_at: self.due_at,
+            events: self.transactions + self.blocks,
+            transactions: self.transactions,
+            blocks: self.blocks,
+            total_value: self.total_value.to_string(),
+            top_counterparties: counterparties
+                .into_iter()
+                .take(TOP_COUNTERPARTIES)
+                .map(|(address, tally)| Counterparty {
+                    address,
+                    events: tally.events,
+                    value: tally.value.to_string(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Digests of the rules with a digest window, saved next to the state file
+/// so that a restart does not lose the events collected so far.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Digests {
+    pending: Vec<PendingDigest>,
+    /// Whether they changed since they were last taken to be saved.
+    #[serde(skip)]
+    changed: bool,
+}
+
+impl Digests {
+    /// Adds `event` to the rule's digest on the event's chain, starting one
+    /// that is due `window` seconds from `now` if there is none.
+    pub fn add(&mut self, rule: &NotificationRule, window: u64, event: &WebhookEvent, now: u64) {
+        let chain_id = event.chain_id();
+        self.changed = true;
+        let index = match self
+            .pending
+            .iter()
+            .position(|digest| digest.rule_id == rule.id && digest.chain_id == chain_id)
+        {
+            Some(index) => index,
+            None => {
+                self.pending.push(PendingDigest {
+                    rule_id: rule.id,
+                    chain_id,
+                    started_at: now,
+                    due_at: now.saturating_add(window),
+                    transactions: 0,
+                    blocks: 0,
+                    total_value: 0,
+                    counterparties: BTreeMap::new(),
+                });
+                self.pending.len() - 1
+            }
+        };
+        let digest = &mut self.pending[index];
+        let watched = |address: &&str| {
+            rule.watched_addresses
+                .iter()
+                .any(|watched| watched.eq_ignore_ascii_case(address))
+        };
+        let (value, addresses) = match event {
+            WebhookEvent::Transaction(transaction) => {
+                digest.transactions += 1;
+                let value = transaction.value.0;
+                digest.total_value += value;
+                (
+                    value,
+                    vec![transaction.from.as_str(), transaction.to.as_str()],
+                )
+            }
+            WebhookEvent::Block(block) => {
+                digest.blocks += 1;
+                (0, block.miner.as_deref().into_iter().collect())
+            }
+            WebhookEvent::Digest(_) => return,
+        };
+        for address in addresses
+            .into_iter()
+            .filter(|address| !address.is_empty() && !watched(address))
+        {
+            let tally = digest
+                .counterparties
+                .entry(address.to_lowercase())
+                .or_default();
+            tally.events += 1;
+            tally.value += value;
+        }
+    }
+
+    /// Removes the digests whose window has ended by `now`.
+    pub fn take_due(&mut self, now: u64) -> Vec<Digest> {
+        let (due, pending) = self
+            .pending
+            .drain(..)
+            .partition(|digest| digest.due_at <= now);
+        self.pending = pending;
+        self.changed |= !due.is_empty();
+        due.into_iter().map(PendingDigest::finish).collect()
+    }
+
+    /// A copy to save when they changed since the last call.
+    pub fn take_changed(&mut self) -> Option<Digests> {
+        std::mem::take(&mut self.changed).then(|| self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::rule::NotificationRuleInput;
+    use crate::models::transaction::Transaction;
+    use crate::models::wei::Wei;
+
+    /// 100 ether, past what fits 64 bits.
+    const HUNDRED_ETHER: u128 = 100_000_000_000_000_000_000;
+
+    fn transaction(chain_id: u64, from: &str, to: &str, value: u128) -> WebhookEvent {
+        WebhookEvent::Transaction(Transaction {
+            chain_id,
+            hash: "0xtx".to_string(),
+            block_hash: "0xblock".to_string(),
+            from: from.to_string(),
+            to: to.to_string(),
+            value: Wei(value),
+            block_number: 1,
+            nonce: 0,
+            gas: 21000,
+            gas_price: None,
+            max_fee_per_gas: None,
+            max_priority_fee_per_gas: None,
+            input: "0x".to_string(),
+            transaction_type: None,
+            transaction_index: None,
+        })
+    }
+
+    #[test]
+    fn test_sums_up_events_per_chain() {
+        let input = NotificationRuleInput {
+            name: "treasury".to_string(),
+            watched_addresses: vec!["0xTreasury".to_string()],
+            contracts: vec![],
+            min_value: None,
+            event_kinds: vec![],
+            chain_id: None,
+            condition: None,
+            digest_window_secs: Some(60),
+            channels: vec![1],
+            notify: vec![],
+        };
+        let rule = NotificationRule::new(1, input, None, 0);
+        let mut digests = Digests::default();
+        digests.add(&rule, 60, &transaction(1, "0xtreasury", "0xBob", 10), 100);
+        digests.add(&rule, 60, &transaction(1, "0xbob", "0xtreasury", 5), 130);
+        digests.add(
+            &rule,
+            60,
+            &transaction(1, "0xtreasury", "0xcarol", HUNDRED_ETHER),
+            140,
+        );
+        digests.add(&rule, 60, &transaction(10, "0xtreasury", "0xbob", 1), 150);
+
+        assert!(digests.take_changed().is_some());
+        assert!(digests.take_changed().is_none());
+
+        // Survives the state file.
+        let mut digests: Digests =
+            serde_json::from_slice(&serde_json::to_vec(&digests).unwrap()).unwrap();
+        assert!(digests.take_due(159).is_empty());
+        let due = digests.take_due(160);
+        assert_eq!(due.len(), 1);
+        let digest = &due[0];
+        assert_eq!(
+            (digest.chain_id, digest.started_at, digest.ended_at),
+            (1, 100, 160)
+        );
+        assert_eq!(digest.events, 3);
+        assert_eq!(digest.total_value, (HUNDRED_ETHER + 15).to_string());
+        assert_eq!(
+            digest.top_counterparties,
+            vec![
+                Counterparty {
+                    address: "0xbob".to_string(),
+                    events: 2,
+                    value: "15".to_string(),
+                },
+                Counterparty {
+                    address: "0xcarol".to_string(),
+                    events: 1,
+                    value: HUNDRED_ETHER.to_string(),
+                },
+            ]
+        );
+        assert_eq!(digests.take_due(210)[0].chain_id, 10);
+        assert!(digests.take_due(u64::MAX).is_empty());
+    }
+}
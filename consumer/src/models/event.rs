@@ -0,0 +1,13 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Envelope the crawler wraps around every published block and transaction.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Event<T> {
+    pub chain_id: u64,
+    /// Message headers, e.g. the W3C trace context of the producing span.
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    pub data: T,
+}
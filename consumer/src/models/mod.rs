@@ -1,3 +1,10 @@
 pub mod block;
+pub mod digest;
+pub mod event;
+pub mod notified;
+pub mod rule;
 pub mod storage;
 pub mod transaction;
+pub mod webhook;
+pub mod wei;
+pub mod withdrawal;
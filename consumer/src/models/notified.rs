@@ -0,0 +1,235 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::Path;
+use tracing::warn;
+
+use super::webhook::WebhookEvent;
+
+/// One notification: an event on a chain, sent for a rule.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct NotificationKey {
+    pub chain_id: u64,
+    pub hash: String,
+    /// Position of the log in its transaction for log events. Blocks and
+    /// transactions are notified as a whole and have none.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_index: Option<u64>,
+    /// `None` for webhooks matched by their own filter.
+    pub rule_id: Option<u64>,
+}
+
+impl NotificationKey {
+    pub fn new(event: &WebhookEvent, rule_id: Option<u64>) -> Self {
+        Self {
+            chain_id: event.chain_id(),
+            hash: event.hash().to_lowercase(),
+            log_index: None,
+            rule_id,
+        }
+    }
+}
+
+/// How a notification is kept in the log file, one JSON object per line.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Entry {
+    #[serde(flatten)]
+    key: NotificationKey,
+    notified_at: u64,
+}
+
+/// Notifications already sent, so that events the crawler emits again, or
+/// the consumer reads again after a restart, are not notified twice.
+///
+/// The log file next to the state file is append-only: claimed notifications
+/// are appended to it, and it is only rewritten when expired ones are
+/// dropped.
+#[derive(Debug, Clone, Default)]
+pub struct NotificationLog {
+    /// Unix time in seconds each notification was first sent.
+    sent: HashMap<NotificationKey, u64>,
+    /// Claimed since they were last taken to be appended to the file.
+    unsaved: Vec<Entry>,
+}
+
+/// Notifications taken from the log to be written to its file, so that the
+/// write can happen once the storage lock is released.
+#[derive(Debug, Clone, Default)]
+pub struct Entries(Vec<Entry>);
+
+impl Entries {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Appends the notifications to the log file at `path`.
+    pub fn append(&self, path: &Path) -> Result<()> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        file.write_all(&self.to_lines()?)
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// Replaces the log file at `path` with these notifications.
+    pub fn replace(&self, path: &Path) -> Result<()> {
+        let tmp_path: &Path = &path.with_extension("tmp");
+        fs::write(tmp_path, self.to_lines()?)
+            .with_context(|| format!("Failed to write {}", tmp_path.display()))?;
+        fs::rename(tmp_path, path).with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    fn to_lines(&self) -> Result<Vec<u8>> {
+        let mut lines = Vec::new();
+        for entry in &self.0 {
+            serde_json::to_writer(&mut lines, entry)?;
+            lines.push(b'\n');
+        }
+        Ok(lines)
+    }
+}
+
+impl NotificationLog {
+    /// Reads the log file at `path`; empty when it does not exist yet. A
+    /// line cut short by a crash is skipped.
+    pub fn read(path: &Path) -> Result<Self> {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        let mut sent = HashMap::new();
+        for line in content.lines().filter(|line| !line.trim().is_empty()) {
+            match serde_json::from_str::<Entry>(line) {
+                Ok(entry) => {
+                    let notified_at = sent.entry(entry.key).or_insert(entry.notified_at);
+                    *notified_at = (*notified_at).max(entry.notified_at);
+                }
+                Err(e) => {
+                    warn!(path = %path.display(), error = %e, "Skipping invalid notification")
+                }
+            }
+        }
+        Ok(Self {
+            sent,
+            unsaved: Vec::new(),
+        })
+    }
+
+    /// Records the notifications of `keys` as sent at `now` and returns those
+    /// that were not sent within the last `retention` seconds.
+    pub fn claim(
+        &mut self,
+        keys: impl IntoIterator<Item = NotificationKey>,
+        now: u64,
+        retention: u64,
+    ) -> Vec<NotificationKey> {
+        let cutoff = now.saturating_sub(retention);
+        let mut fresh = Vec::new();
+        for key in keys {
+            let notified_at = self.sent.entry(key.clone()).or_insert(0);
+            if *notified_at > cutoff {
+                continue;
+            }
+            *notified_at = now;
+            self.unsaved.push(Entry {
+                key: key.clone(),
+                notified_at: now,
+            });
+            fresh.push(key);
+        }
+        fresh
+    }
+
+    /// Forgets claimed notifications that could not be saved, unless they
+    /// were claimed again since.
+    pub fn release(&mut self, entries: &Entries) {
+        for entry in &entries.0 {
+            if self.sent.get(&entry.key) == Some(&entry.notified_at) {
+                self.sent.remove(&entry.key);
+            }
+        }
+    }
+
+    /// Takes the notifications claimed since the last call, to be appended
+    /// to the log file.
+    pub fn take_unsaved(&mut self) -> Entries {
+        Entries(std::mem::take(&mut self.unsaved))
+    }
+
+    /// Forgets the notifications sent more than `retention` seconds before
+    /// `now`. Returns every notification left, to replace the log file with,
+    /// or `None` when nothing expired.
+    pub fn expire(&mut self, now: u64, retention: u64) -> Option<Entries> {
+        let cutoff = now.saturating_sub(retention);
+        let before = self.sent.len();
+        self.sent.retain(|_, notified_at| *notified_at > cutoff);
+        if self.sent.len() == before {
+            return None;
+        }
+        self.unsaved.clear();
+        let mut entries: Vec<Entry> = self
+            .sent
+            .iter()
+            .map(|(key, notified_at)| Entry {
+                key: key.clone(),
+                notified_at: *notified_at,
+            })
+            .collect();
+        entries.sort_by_key(|entry| entry.notified_at);
+        Some(Entries(entries))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn key(hash: &str, rule_id: Option<u64>) -> NotificationKey {
+        NotificationKey {
+            chain_id: 1,
+            hash: hash.to_string(),
+            log_index: None,
+            rule_id,
+        }
+    }
+
+    #[test]
+    fn test_claims_each_notification_once() {
+        let path = env::temp_dir().join(format!("consumer-notified-{}.jsonl", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mut log = NotificationLog::default();
+        let fresh = log.claim([key("0xa", Some(1)), key("0xa", None)], 100, 60);
+        assert_eq!(fresh.len(), 2);
+        log.take_unsaved().append(&path).unwrap();
+        let fresh = log.claim([key("0xa", Some(1)), key("0xa", Some(2))], 120, 60);
+        assert_eq!(fresh, vec![key("0xa", Some(2))]);
+        log.take_unsaved().append(&path).unwrap();
+        assert!(log.take_unsaved().is_empty());
+
+        // Survives the log file.
+        let mut log = NotificationLog::read(&path).unwrap();
+        assert!(log.claim([key("0xa", None)], 130, 60).is_empty());
+
+        // Claimable again once the retention window has passed, and dropped
+        // from the file when they expire.
+        let fresh = log.claim([key("0xa", Some(1)), key("0xa", Some(2))], 170, 60);
+        assert_eq!(fresh, vec![key("0xa", Some(1))]);
+        assert!(log.expire(150, 60).is_none());
+        log.expire(185, 60).unwrap().replace(&path).unwrap();
+        let mut log = NotificationLog::read(&path).unwrap();
+        assert_eq!(
+            log.claim([key("0xa", None)], 185, 60),
+            vec![key("0xa", None)]
+        );
+        assert!(log.claim([key("0xa", Some(1))], 185, 60).is_empty());
+
+        fs::remove_file(&path).unwrap();
+    }
+}
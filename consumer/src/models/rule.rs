@@ -0,0 +1,206 @@
+use async_graphql::*;
+use filter_expr::{AddressBooks, Filter};
+use serde::{Deserialize, Serialize};
+
+use super::webhook::{EventKind, WebhookEvent};
+use super::wei::Wei;
+
+/// What a user wants to be notified about, and where.
+///
+/// Every condition that is set must match. A paused rule keeps its settings
+/// but matches nothing until it is resumed.
+#[derive(SimpleObject, Debug, Clone, Serialize, Deserialize)]
+#[graphql(complex)]
+pub struct NotificationRule {
+    pub id: u64,
+    pub name: String,
+    /// Transactions from or to any of these, or blocks mined by one.
+    pub watched_addresses: Vec<String>,
+    /// Transactions calling any of these contracts. Blocks never match.
+    pub contracts: Vec<String>,
+    /// Smallest transaction value in wei. Blocks never match.
+    pub min_value: Option<Wei>,
+    /// Empty means every kind.
+    pub event_kinds: Vec<EventKind>,
+    pub chain_id: Option<u64>,
+    /// Expression over the event's fields, checked on top of the others.
+    #[graphql(skip)]
+    #[serde(default)]
+    pub condition: Option<Filter>,
+    /// Matching events are collected for this many seconds and sent as one
+    /// digest; `null` sends every event on its own.
+    #[serde(default)]
+    pub digest_window_secs: Option<u64>,
+    /// Webhooks notified of matching events.
+    pub channels: Vec<u64>,
+    /// Configured notification channels, by name, that get a message about
+    /// matching events.
+    #[serde(default)]
+    pub notify: Vec<String>,
+    pub paused: bool,
+    /// Unix time in seconds.
+    pub created_at: u64,
+    pub updated_at: u64,
+}
+
+#[derive(InputObject, Debug, Clone)]
+pub struct NotificationRuleInput {
+    pub name: String,
+    #[graphql(default)]
+    pub watched_addresses: Vec<String>,
+    #[graphql(default)]
+    pub contracts: Vec<String>,
+    pub min_value: Option<Wei>,
+    #[graphql(default)]
+    pub event_kinds: Vec<EventKind>,
+    pub chain_id: Option<u64>,
+    /// Filter expression, e.g. `from in @treasury and value > 10 ether`.
+    pub condition: Option<String>,
+    pub digest_window_secs: Option<u64>,
+    #[graphql(default)]
+    pub channels: Vec<u64>,
+    #[graphql(default)]
+    pub notify: Vec<String>,
+}
+
+/// Fields left out are kept as they are.
+#[derive(InputObject, Debug, Clone, Default)]
+pub struct NotificationRuleUpdate {
+    pub name: Option<String>,
+    pub watched_addresses: Option<Vec<String>>,
+    pub contracts: Option<Vec<String>>,
+    pub min_value: MaybeUndefined<Wei>,
+    pub event_kinds: Option<Vec<EventKind>>,
+    pub chain_id: MaybeUndefined<u64>,
+    pub condition: MaybeUndefined<String>,
+    pub digest_window_secs: MaybeUndefined<u64>,
+    pub channels: Option<Vec<u64>>,
+    pub notify: Option<Vec<String>>,
+}
+
+#[ComplexObject]
+impl NotificationRule {
+    /// Filter expression, as it was written.
+    #[graphql(name = "condition")]
+    async fn graphql_condition(&self) -> Option<&str> {
+        self.condition.as_ref().map(Filter::source)
+    }
+}
+
+/// Parses a rule condition and checks that the address books it uses exist.
+pub fn parse_condition(source: &str, books: &AddressBooks) -> anyhow::Result<Filter> {
+    let filter: Filter = source
+        .parse()
+        .map_err(|e| anyhow::anyhow!("Invalid condition: {e}"))?;
+    filter
+        .check_address_books(books)
+        .map_err(|e| anyhow::anyhow!("Invalid condition: {e}"))?;
+    Ok(filter)
+}
+
+fn lowercase(addresses: Vec<String>) -> Vec<String> {
+    addresses
+        .into_iter()
+        .map(|address| address.to_lowercase())
+        .collect()
+}
+
+impl NotificationRule {
+    pub fn new(id: u64, input: NotificationRuleInput, condition: Option<Filter>, now: u64) -> Self {
+        Self {
+            id,
+            name: input.name,
+            watched_addresses: lowercase(input.watched_addresses),
+            contracts: lowercase(input.contracts),
+            min_value: input.min_value,
+            event_kinds: input.event_kinds,
+            chain_id: input.chain_id,
+            condition,
+            digest_window_secs: input.digest_window_secs,
+            channels: input.channels,
+            notify: input.notify,
+            paused: false,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    /// Applies `update`, whose condition the caller has already parsed into
+    /// `condition`.
+    pub fn update(
+        &mut self,
+        update: NotificationRuleUpdate,
+        condition: MaybeUndefined<Filter>,
+        now: u64,
+    ) {
+        if let Some(name) = update.name {
+            self.name = name;
+        }
+        if let Some(addresses) = update.watched_addresses {
+            self.watched_addresses = lowercase(addresses);
+        }
+        if let Some(contracts) = update.contracts {
+            self.contracts = lowercase(contracts);
+        }
+        update.min_value.update_to(&mut self.min_value);
+        if let Some(event_kinds) = update.event_kinds {
+            self.event_kinds = event_kinds;
+        }
+        update.chain_id.update_to(&mut self.chain_id);
+        condition.update_to(&mut self.condition);
+        update
+            .digest_window_secs
+            .update_to(&mut self.digest_window_secs);
+        if let Some(channels) = update.channels {
+            self.channels = channels;
+        }
+        if let Some(notify) = update.notify {
+            self.notify = notify;
+        }
+        self.updated_at = now;
+    }
+
+    pub fn matches(&self, event: &WebhookEvent, books: &AddressBooks) -> bool {
+        if self.paused {
+            return false;
+        }
+        if !self.event_kinds.is_empty() && !self.event_kinds.contains(&event.kind()) {
+            return false;
+        }
+        if self.chain_id.is_some_and(|id| id != event.chain_id()) {
+            return false;
+        }
+        if !self
+            .condition
+            .as_ref()
+            .is_none_or(|condition| condition.matches(event, books))
+        {
+            return false;
+        }
+        let watched = |address: &str| {
+            self.watched_addresses
+                .iter()
+                .any(|watched| watched.eq_ignore_ascii_case(address))
+        };
+        match event {
+            WebhookEvent::Block(block) => {
+                self.min_value.is_none()
+                    && self.contracts.is_empty()
+                    && (self.watched_addresses.is_empty()
+                        || block.miner.as_deref().is_some_and(watched))
+            }
+            WebhookEvent::Transaction(transaction) => {
+                self.min_value.is_none_or(|min| transaction.value >= min)
+                    && (self.watched_addresses.is_empty()
+                        || watched(&transaction.from)
+                        || watched(&transaction.to))
+                    && (self.contracts.is_empty()
+                        || self
+                            .contracts
+                            .iter()
+                            .any(|contract| contract.eq_ignore_ascii_case(&transaction.to)))
+            }
+            WebhookEvent::Digest(_) => false,
+        }
+    }
+}
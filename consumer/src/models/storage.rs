@@ -1,11 +1,69 @@
-use std::collections::HashMap;
+use anyhow::{bail, Context, Result};
+use async_graphql::MaybeUndefined;
+use filter_expr::{AddressBooks, Filter};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
 
 use super::block::Block;
+use super::digest::Digests;
+use super::notified::NotificationLog;
+use super::rule::{
+    parse_condition, NotificationRule, NotificationRuleInput, NotificationRuleUpdate,
+};
 use super::transaction::Transaction;
+use super::webhook::{Delivery, Webhook, WebhookFilter};
+use crate::utils::unix_now;
+
+/// Records are keyed by `(chain_id, hash)` so the same storage can serve
+/// several chains.
+pub type RecordKey = (u64, String);
+
+/// What users set up through the GraphQL mutations, written to the state
+/// file after every change so that it survives restarts.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Subscriptions {
+    pub webhooks: BTreeMap<u64, Webhook>,
+    pub rules: BTreeMap<u64, NotificationRule>,
+    next_webhook_id: u64,
+    next_rule_id: u64,
+}
+
+impl Default for Subscriptions {
+    fn default() -> Self {
+        Self {
+            webhooks: BTreeMap::new(),
+            rules: BTreeMap::new(),
+            next_webhook_id: 1,
+            next_rule_id: 1,
+        }
+    }
+}
 
 pub struct Storage {
-    pub blocks: HashMap<String, Block>,
-    pub transactions: HashMap<String, Transaction>,
+    pub blocks: HashMap<RecordKey, Block>,
+    pub transactions: HashMap<RecordKey, Transaction>,
+    pub chain_ids: BTreeSet<u64>,
+    pub subscriptions: Subscriptions,
+    /// Notifications sent within the de-duplication window, saved next to
+    /// the state file.
+    pub notified: NotificationLog,
+    /// Digests of rules with a digest window still collecting events, saved
+    /// next to the state file.
+    pub digests: Digests,
+    /// Finished webhook deliveries, oldest first.
+    pub deliveries: VecDeque<Delivery>,
+    /// Named address lists that rule conditions refer to as `@name`, from
+    /// the configuration.
+    pub address_books: AddressBooks,
+    /// Names of the configured notification channels.
+    pub notification_channels: BTreeSet<String>,
+    /// Names of the templates webhooks can pick.
+    pub templates: BTreeSet<String>,
+    state_file: Option<PathBuf>,
 }
 
 impl Storage {
@@ -13,23 +71,582 @@ impl Storage {
         Self {
             blocks: HashMap::new(),
             transactions: HashMap::new(),
+            chain_ids: BTreeSet::new(),
+            subscriptions: Subscriptions::default(),
+            notified: NotificationLog::default(),
+            digests: Digests::default(),
+            deliveries: VecDeque::new(),
+            address_books: AddressBooks::new(),
+            notification_channels: BTreeSet::new(),
+            templates: BTreeSet::new(),
+            state_file: None,
+        }
+    }
+
+    /// Restores webhooks and rules from `path`, and the sent notifications
+    /// and pending digests from the files next to it, and keeps saving them
+    /// there. Starts empty when the files do not exist yet.
+    pub fn with_state_file(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        Ok(Self {
+            subscriptions: read_state(&path)?,
+            notified: NotificationLog::read(&sidecar_path(&path, "notified.jsonl"))?,
+            digests: read_state(&sidecar_path(&path, "digests.json"))?,
+            state_file: Some(path),
+            ..Self::new()
+        })
+    }
+
+    /// Writes `subscriptions` to the state file and only then makes them the
+    /// current ones, so a change that could not be saved does not take
+    /// effect either.
+    fn commit(&mut self, subscriptions: Subscriptions) -> Result<()> {
+        if let Some(path) = &self.state_file {
+            write_state(path, &subscriptions)?;
         }
+        self.subscriptions = subscriptions;
+        Ok(())
+    }
+
+    /// Log of the sent notifications next to the state file. They change
+    /// with every notified event, so they are kept apart from the
+    /// subscriptions; `None` without a state file.
+    pub fn notified_file(&self) -> Option<PathBuf> {
+        Some(sidecar_path(self.state_file.as_ref()?, "notified.jsonl"))
+    }
+
+    /// File of the pending digests next to the state file; `None` without a
+    /// state file.
+    pub fn digests_file(&self) -> Option<PathBuf> {
+        Some(sidecar_path(self.state_file.as_ref()?, "digests.json"))
     }
 
     pub async fn add_block(&mut self, block: Block) {
-        self.blocks.insert(block.hash.clone(), block);
+        self.chain_ids.insert(block.chain_id);
+        self.blocks
+            .insert((block.chain_id, block.hash.clone()), block);
     }
 
     pub async fn add_transaction(&mut self, transaction: Transaction) {
-        self.transactions
-            .insert(transaction.hash.clone(), transaction);
+        self.chain_ids.insert(transaction.chain_id);
+        self.transactions.insert(
+            (transaction.chain_id, transaction.hash.clone()),
+            transaction,
+        );
+    }
+
+    /// Looks a block up on `chain_id`, or on every known chain when it is `None`.
+    pub async fn get_block(&self, chain_id: Option<u64>, hash: &str) -> Option<Block> {
+        self.lookup_chains(chain_id)
+            .find_map(|chain_id| self.blocks.get(&(chain_id, hash.to_string())))
+            .cloned()
+    }
+
+    /// Looks a transaction up on `chain_id`, or on every known chain when it is `None`.
+    pub async fn get_transaction(&self, chain_id: Option<u64>, hash: &str) -> Option<Transaction> {
+        self.lookup_chains(chain_id)
+            .find_map(|chain_id| self.transactions.get(&(chain_id, hash.to_string())))
+            .cloned()
+    }
+
+    pub fn add_webhook(
+        &mut self,
+        url: String,
+        secret: String,
+        filter: Option<WebhookFilter>,
+        template: Option<String>,
+    ) -> Result<Webhook> {
+        if let Some(name) = template
+            .as_ref()
+            .filter(|name| !self.templates.contains(*name))
+        {
+            bail!("No template named {name:?}");
+        }
+        let mut subscriptions = self.subscriptions.clone();
+        let webhook = Webhook {
+            id: subscriptions.next_webhook_id,
+            url,
+            secret,
+            filter,
+            template,
+        };
+        subscriptions.next_webhook_id += 1;
+        subscriptions.webhooks.insert(webhook.id, webhook.clone());
+        self.commit(subscriptions)?;
+        Ok(webhook)
     }
 
-    pub async fn get_block(&self, hash: &str) -> Option<Block> {
-        self.blocks.get(hash).cloned()
+    /// Removes a webhook unless a rule still notifies it; `None` when there
+    /// was none with this id.
+    pub fn remove_webhook(&mut self, id: u64) -> Result<Option<Webhook>> {
+        if let Some(rule) = self
+            .subscriptions
+            .rules
+            .values()
+            .find(|rule| rule.channels.contains(&id))
+        {
+            bail!("Webhook {id} is a channel of rule {}", rule.id);
+        }
+        if !self.subscriptions.webhooks.contains_key(&id) {
+            return Ok(None);
+        }
+        let mut subscriptions = self.subscriptions.clone();
+        let webhook = subscriptions.webhooks.remove(&id);
+        self.commit(subscriptions)?;
+        Ok(webhook)
+    }
+
+    fn check_channels(&self, channels: &[u64], notify: &[String]) -> Result<()> {
+        if channels.is_empty() && notify.is_empty() {
+            bail!("A rule needs at least one channel");
+        }
+        if let Some(id) = channels
+            .iter()
+            .find(|id| !self.subscriptions.webhooks.contains_key(id))
+        {
+            bail!("No webhook with id {id}");
+        }
+        if let Some(name) = notify
+            .iter()
+            .find(|name| !self.notification_channels.contains(*name))
+        {
+            bail!("No notification channel named {name:?}");
+        }
+        Ok(())
+    }
+
+    pub fn add_rule(&mut self, input: NotificationRuleInput) -> Result<NotificationRule> {
+        if input.name.trim().is_empty() {
+            bail!("Rule name must not be empty");
+        }
+        if input.digest_window_secs == Some(0) {
+            bail!("Digest window must be positive");
+        }
+        self.check_channels(&input.channels, &input.notify)?;
+        let condition = input
+            .condition
+            .as_deref()
+            .map(|source| parse_condition(source, &self.address_books))
+            .transpose()?;
+        let mut subscriptions = self.subscriptions.clone();
+        let rule = NotificationRule::new(subscriptions.next_rule_id, input, condition, unix_now());
+        subscriptions.next_rule_id += 1;
+        subscriptions.rules.insert(rule.id, rule.clone());
+        self.commit(subscriptions)?;
+        Ok(rule)
+    }
+
+    pub fn update_rule(
+        &mut self,
+        id: u64,
+        update: NotificationRuleUpdate,
+    ) -> Result<NotificationRule> {
+        if update
+            .name
+            .as_ref()
+            .is_some_and(|name| name.trim().is_empty())
+        {
+            bail!("Rule name must not be empty");
+        }
+        if update.digest_window_secs == MaybeUndefined::Value(0) {
+            bail!("Digest window must be positive");
+        }
+        let Some(rule) = self.subscriptions.rules.get(&id) else {
+            bail!("No rule with id {id}");
+        };
+        if update.channels.is_some() || update.notify.is_some() {
+            self.check_channels(
+                update.channels.as_ref().unwrap_or(&rule.channels),
+                update.notify.as_ref().unwrap_or(&rule.notify),
+            )?;
+        }
+        let condition = match &update.condition {
+            MaybeUndefined::Value(source) => {
+                MaybeUndefined::Value(parse_condition(source, &self.address_books)?)
+            }
+            MaybeUndefined::Null => MaybeUndefined::Null,
+            MaybeUndefined::Undefined => MaybeUndefined::Undefined,
+        };
+        let mut subscriptions = self.subscriptions.clone();
+        let rule = subscriptions
+            .rules
+            .get_mut(&id)
+            .expect("rule was looked up above");
+        rule.update(update, condition, unix_now());
+        let rule = rule.clone();
+        self.commit(subscriptions)?;
+        Ok(rule)
+    }
+
+    pub fn set_rule_paused(&mut self, id: u64, paused: bool) -> Result<NotificationRule> {
+        let mut subscriptions = self.subscriptions.clone();
+        let Some(rule) = subscriptions.rules.get_mut(&id) else {
+            bail!("No rule with id {id}");
+        };
+        rule.paused = paused;
+        rule.updated_at = unix_now();
+        let rule = rule.clone();
+        self.commit(subscriptions)?;
+        Ok(rule)
+    }
+
+    /// `None` when there was no rule with this id.
+    pub fn remove_rule(&mut self, id: u64) -> Result<Option<NotificationRule>> {
+        if !self.subscriptions.rules.contains_key(&id) {
+            return Ok(None);
+        }
+        let mut subscriptions = self.subscriptions.clone();
+        let rule = subscriptions.rules.remove(&id);
+        self.commit(subscriptions)?;
+        Ok(rule)
+    }
+
+    /// Appends to the delivery log, dropping the oldest entries beyond `keep`.
+    pub fn record_delivery(&mut self, delivery: Delivery, keep: usize) {
+        self.deliveries.push_back(delivery);
+        while self.deliveries.len() > keep {
+            self.deliveries.pop_front();
+        }
     }
 
-    pub async fn get_transaction(&self, hash: &str) -> Option<Transaction> {
-        self.transactions.get(hash).cloned()
+    /// Removes the blocks numbered below `number` on `chain_id` together with
+    /// their transactions, and the chain once nothing is left of it. Returns
+    /// the number of blocks and transactions removed.
+    pub fn prune_before(&mut self, chain_id: u64, number: u64) -> (usize, usize) {
+        let blocks = self.blocks.len();
+        let transactions = self.transactions.len();
+        let mut pruned_hashes = BTreeSet::new();
+        let mut removed = HashSet::new();
+        self.blocks.retain(|(chain, _), block| {
+            if *chain != chain_id || block.number >= number {
+                return true;
+            }
+            pruned_hashes.insert(block.hash.clone());
+            pruned_hashes.extend(block.transactions.iter().cloned());
+            false
+        });
+        // Transactions go with their block, and so do those of blocks that
+        // were never stored or pruned before.
+        self.transactions.retain(|(chain, hash), transaction| {
+            let keep = *chain != chain_id
+                || (transaction.block_number >= number
+                    && !pruned_hashes.contains(hash)
+                    && !pruned_hashes.contains(&transaction.block_hash));
+            if !keep {
+                removed.insert(hash.clone());
+            }
+            keep
+        });
+        self.strip_transactions(chain_id, &removed);
+        self.forget_empty_chains();
+        (
+            blocks - self.blocks.len(),
+            transactions - self.transactions.len(),
+        )
+    }
+
+    /// Removes the transactions neither sent from nor to any of `watched`
+    /// (lowercase). Returns the number removed.
+    pub fn prune_unwatched(&mut self, watched: &BTreeSet<String>) -> usize {
+        let transactions = self.transactions.len();
+        let mut removed: BTreeMap<u64, HashSet<String>> = BTreeMap::new();
+        self.transactions.retain(|(chain, hash), transaction| {
+            let keep = watched.contains(&transaction.from.to_lowercase())
+                || watched.contains(&transaction.to.to_lowercase());
+            if !keep {
+                removed.entry(*chain).or_default().insert(hash.clone());
+            }
+            keep
+        });
+        for (chain_id, hashes) in &removed {
+            self.strip_transactions(*chain_id, hashes);
+        }
+        self.forget_empty_chains();
+        transactions - self.transactions.len()
+    }
+
+    /// Drops the `removed` transaction hashes from the blocks of `chain_id`,
+    /// so that blocks only list the transactions still stored.
+    fn strip_transactions(&mut self, chain_id: u64, removed: &HashSet<String>) {
+        if removed.is_empty() {
+            return;
+        }
+        for block in self
+            .blocks
+            .values_mut()
+            .filter(|block| block.chain_id == chain_id)
+        {
+            block.transactions.retain(|hash| !removed.contains(hash));
+        }
+    }
+
+    /// Addresses that rules, including their conditions, webhook filters and
+    /// address books refer to, lowercase.
+    pub fn watched_addresses(&self) -> BTreeSet<String> {
+        let rules = self.subscriptions.rules.values().flat_map(|rule| {
+            let conditions = rule.condition.iter().flat_map(Filter::addresses);
+            rule.watched_addresses
+                .iter()
+                .chain(&rule.contracts)
+                .map(String::as_str)
+                .chain(conditions)
+        });
+        let webhooks = self
+            .subscriptions
+            .webhooks
+            .values()
+            .filter_map(|webhook| webhook.filter.as_ref()?.address.as_deref());
+        rules
+            .chain(webhooks)
+            .map(|address| address.to_lowercase())
+            .chain(self.address_books.addresses().map(str::to_string))
+            .collect()
+    }
+
+    fn forget_empty_chains(&mut self) {
+        let (blocks, transactions) = (&self.blocks, &self.transactions);
+        self.chain_ids.retain(|chain_id| {
+            blocks.keys().any(|(chain, _)| chain == chain_id)
+                || transactions.keys().any(|(chain, _)| chain == chain_id)
+        });
+    }
+
+    fn lookup_chains(&self, chain_id: Option<u64>) -> Box<dyn Iterator<Item = u64> + '_> {
+        match chain_id {
+            Some(chain_id) => Box::new(std::iter::once(chain_id)),
+            None => Box::new(self.chain_ids.iter().copied()),
+        }
+    }
+}
+
+/// `state.notified.jsonl` for `state.json` and `notified.jsonl`.
+fn sidecar_path(state_file: &Path, extension: &str) -> PathBuf {
+    state_file.with_extension(extension)
+}
+
+/// The default when `path` does not exist yet.
+fn read_state<T: DeserializeOwned + Default>(path: &Path) -> Result<T> {
+    match fs::read_to_string(path) {
+        Ok(content) => serde_json::from_str(&content)
+            .with_context(|| format!("Invalid state file {}", path.display())),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(T::default()),
+        Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    }
+}
+
+/// Writes `state` next to `path` and renames it over it, so a crash never
+/// leaves a truncated file behind.
+pub fn write_state(path: &Path, state: &impl Serialize) -> Result<()> {
+    replace_file(path, &serde_json::to_vec(state)?)
+}
+
+/// Replaces the file at `path` with `content` the way `write_state` does.
+pub fn replace_file(path: &Path, content: &[u8]) -> Result<()> {
+    let tmp_path: &Path = &path.with_extension("tmp");
+    fs::write(tmp_path, content)
+        .with_context(|| format!("Failed to write {}", tmp_path.display()))?;
+    fs::rename(tmp_path, path).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::notified::NotificationKey;
+    use crate::models::wei::Wei;
+    use std::env;
+
+    fn rule_input(channels: Vec<u64>) -> NotificationRuleInput {
+        NotificationRuleInput {
+            name: "whale".to_string(),
+            watched_addresses: vec!["0xABC".to_string()],
+            contracts: vec![],
+            min_value: Some(Wei(1000)),
+            event_kinds: vec![],
+            chain_id: None,
+            condition: None,
+            digest_window_secs: None,
+            channels,
+            notify: vec![],
+        }
+    }
+
+    #[test]
+    fn test_subscriptions_survive_restart() {
+        let path = env::temp_dir().join(format!("consumer-state-{}.json", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mut storage = Storage::with_state_file(&path).unwrap();
+        let webhook = storage
+            .add_webhook(
+                "http://localhost/hook".to_string(),
+                "s".to_string(),
+                None,
+                None,
+            )
+            .unwrap();
+        let rule = storage.add_rule(rule_input(vec![webhook.id])).unwrap();
+        storage.set_rule_paused(rule.id, true).unwrap();
+        let key = NotificationKey {
+            chain_id: 1,
+            hash: "0xabc".to_string(),
+            log_index: None,
+            rule_id: Some(rule.id),
+        };
+        storage.notified.claim([key.clone()], unix_now(), 60);
+        let notified_file = storage.notified_file().unwrap();
+        storage
+            .notified
+            .take_unsaved()
+            .append(&notified_file)
+            .unwrap();
+
+        let restored = Storage::with_state_file(&path).unwrap();
+        let restored_rule = &restored.subscriptions.rules[&rule.id];
+        assert_eq!(restored_rule.watched_addresses, vec!["0xabc"]);
+        assert!(restored_rule.paused);
+        assert_eq!(restored.subscriptions.webhooks[&webhook.id].secret, "s");
+        let mut restored_notified = restored.notified.clone();
+        assert!(restored_notified.claim([key], unix_now(), 60).is_empty());
+
+        // Ids keep counting after a restart.
+        let mut restored = restored;
+        let next = restored.add_rule(rule_input(vec![webhook.id])).unwrap();
+        assert_eq!(next.id, rule.id + 1);
+
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(notified_file).unwrap();
+    }
+
+    #[test]
+    fn test_rule_changes_that_fail_to_save_are_undone() {
+        // The directory does not exist, so every save fails.
+        let path = env::temp_dir()
+            .join(format!("consumer-missing-{}", std::process::id()))
+            .join("state.json");
+        let mut storage = Storage::with_state_file(&path).unwrap();
+        storage.notification_channels.insert("ops".to_string());
+        let input = NotificationRuleInput {
+            notify: vec!["ops".to_string()],
+            ..rule_input(vec![])
+        };
+        assert!(storage.add_rule(input.clone()).is_err());
+        assert!(storage.subscriptions.rules.is_empty());
+        assert_eq!(storage.subscriptions.next_rule_id, 1);
+
+        storage.state_file = None;
+        let rule = storage.add_rule(input).unwrap();
+        storage.state_file = Some(path);
+        assert!(storage.set_rule_paused(rule.id, true).is_err());
+        let update = NotificationRuleUpdate {
+            name: Some("renamed".to_string()),
+            ..Default::default()
+        };
+        assert!(storage.update_rule(rule.id, update).is_err());
+        assert!(storage.remove_rule(rule.id).is_err());
+        let kept = &storage.subscriptions.rules[&rule.id];
+        assert!(!kept.paused);
+        assert_eq!(kept.name, "whale");
+    }
+
+    #[test]
+    fn test_webhook_changes_that_fail_to_save_are_undone() {
+        // The directory does not exist, so every save fails.
+        let path = env::temp_dir()
+            .join(format!("consumer-missing-{}", std::process::id()))
+            .join("state.json");
+        let mut storage = Storage::with_state_file(&path).unwrap();
+        let add = |storage: &mut Storage| {
+            storage.add_webhook(
+                "http://localhost/hook".to_string(),
+                "s".to_string(),
+                None,
+                None,
+            )
+        };
+        assert!(add(&mut storage).is_err());
+        assert!(storage.subscriptions.webhooks.is_empty());
+        assert_eq!(storage.subscriptions.next_webhook_id, 1);
+
+        storage.state_file = None;
+        let webhook = add(&mut storage).unwrap();
+        storage.state_file = Some(path);
+        assert!(storage.remove_webhook(webhook.id).is_err());
+        assert!(storage.subscriptions.webhooks.contains_key(&webhook.id));
+    }
+
+    #[test]
+    fn test_rule_channels_must_exist() {
+        let mut storage = Storage::new();
+        let err = storage.add_rule(rule_input(vec![7])).unwrap_err();
+        assert!(err.to_string().contains("No webhook with id 7"), "{err}");
+        let err = storage
+            .add_rule(NotificationRuleInput {
+                notify: vec!["ops".to_string()],
+                ..rule_input(vec![])
+            })
+            .unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("No notification channel named \"ops\""),
+            "{err}"
+        );
+
+        let webhook = storage
+            .add_webhook(
+                "http://localhost/hook".to_string(),
+                "s".to_string(),
+                None,
+                None,
+            )
+            .unwrap();
+        storage.add_rule(rule_input(vec![webhook.id])).unwrap();
+        let err = storage.remove_webhook(webhook.id).unwrap_err();
+        assert!(err.to_string().contains("channel of rule 1"), "{err}");
+    }
+
+    #[test]
+    fn test_rule_condition_is_checked() {
+        let mut storage = Storage::new();
+        storage.address_books = [("treasury", vec!["0xABC".to_string()])]
+            .into_iter()
+            .collect();
+        let webhook = storage
+            .add_webhook(
+                "http://localhost/hook".to_string(),
+                "s".to_string(),
+                None,
+                None,
+            )
+            .unwrap();
+        let with_condition = |condition: &str| NotificationRuleInput {
+            condition: Some(condition.to_string()),
+            ..rule_input(vec![webhook.id])
+        };
+
+        let err = storage.add_rule(with_condition("value > > 1")).unwrap_err();
+        assert!(err.to_string().starts_with("Invalid condition"), "{err}");
+        let err = storage
+            .add_rule(with_condition("from in @pools"))
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("unknown address book @pools"),
+            "{err}"
+        );
+        assert!(storage.subscriptions.rules.is_empty());
+
+        let rule = storage
+            .add_rule(with_condition("from in @treasury"))
+            .unwrap();
+        let update = NotificationRuleUpdate {
+            condition: MaybeUndefined::Value("nonce <".to_string()),
+            ..Default::default()
+        };
+        assert!(storage.update_rule(rule.id, update).is_err());
+        let update = NotificationRuleUpdate {
+            condition: MaybeUndefined::Null,
+            ..Default::default()
+        };
+        let rule = storage.update_rule(rule.id, update).unwrap();
+        assert!(rule.condition.is_none());
     }
 }
@@ -1,6 +1,12 @@
-use async_graphql::*;
+use async_graphql::{dataloader::DataLoader, *};
 use serde::{Deserialize, Serialize};
 
+use super::block::Block;
+use super::event::Event;
+use super::wei::Wei;
+use crate::loader::BlockLoader;
+use crate::utils::{hex_to_dec, hex_to_u128};
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct TransactionData {
@@ -10,20 +16,76 @@ pub struct TransactionData {
     pub to: String,
     pub value: String,
     pub block_number: String,
+    pub nonce: String,
+    pub gas: String,
+    pub gas_price: Option<String>,
+    #[serde(default)]
+    pub max_fee_per_gas: Option<String>,
+    #[serde(default)]
+    pub max_priority_fee_per_gas: Option<String>,
+    pub input: String,
+    #[serde(rename = "type", default)]
+    pub transaction_type: Option<String>,
+    pub transaction_index: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Transaction {
+    pub chain_id: u64,
     pub hash: String,
     pub block_hash: String,
     pub from: String,
     pub to: String,
-    pub value: u64,
+    pub value: Wei,
     pub block_number: u64,
+    pub nonce: u64,
+    pub gas: u64,
+    pub gas_price: Option<u64>,
+    pub max_fee_per_gas: Option<u64>,
+    pub max_priority_fee_per_gas: Option<u64>,
+    pub input: String,
+    pub transaction_type: Option<u64>,
+    pub transaction_index: Option<u64>,
+}
+
+impl From<Event<TransactionData>> for Transaction {
+    fn from(event: Event<TransactionData>) -> Self {
+        let transaction = event.data;
+        Self {
+            chain_id: event.chain_id,
+            hash: transaction.hash,
+            block_hash: transaction.block_hash,
+            from: transaction.from,
+            to: transaction.to,
+            value: Wei(hex_to_u128(transaction.value)),
+            block_number: hex_to_dec(transaction.block_number),
+            nonce: hex_to_dec(transaction.nonce),
+            gas: hex_to_dec(transaction.gas),
+            gas_price: transaction.gas_price.map(hex_to_dec),
+            max_fee_per_gas: transaction.max_fee_per_gas.map(hex_to_dec),
+            max_priority_fee_per_gas: transaction.max_priority_fee_per_gas.map(hex_to_dec),
+            input: transaction.input,
+            transaction_type: transaction.transaction_type.map(hex_to_dec),
+            transaction_index: transaction.transaction_index.map(hex_to_dec),
+        }
+    }
+}
+
+impl Transaction {
+    /// Returns the 4-byte function selector of the call data, or `None` when
+    /// the transaction carries no call data (a plain value transfer).
+    pub fn method_selector(&self) -> Option<String> {
+        let data = self.input.strip_prefix("0x").unwrap_or(&self.input);
+        data.get(..8).map(|selector| format!("0x{selector}"))
+    }
 }
 
 #[Object]
 impl Transaction {
+    async fn chain_id(&self) -> u64 {
+        self.chain_id
+    }
+
     async fn hash(&self) -> &str {
         &self.hash
     }
@@ -40,11 +102,159 @@ impl Transaction {
         &self.to
     }
 
-    async fn value(&self) -> u64 {
+    async fn value(&self) -> Wei {
         self.value
     }
 
     async fn block_number(&self) -> u64 {
         self.block_number
     }
+
+    async fn nonce(&self) -> u64 {
+        self.nonce
+    }
+
+    /// Gas limit supplied by the sender.
+    async fn gas(&self) -> u64 {
+        self.gas
+    }
+
+    async fn gas_price(&self) -> Option<u64> {
+        self.gas_price
+    }
+
+    async fn max_fee_per_gas(&self) -> Option<u64> {
+        self.max_fee_per_gas
+    }
+
+    async fn max_priority_fee_per_gas(&self) -> Option<u64> {
+        self.max_priority_fee_per_gas
+    }
+
+    async fn input(&self) -> &str {
+        &self.input
+    }
+
+    /// First 4 bytes of the call data; `null` for plain value transfers.
+    #[graphql(name = "methodSelector")]
+    async fn graphql_method_selector(&self) -> Option<String> {
+        self.method_selector()
+    }
+
+    /// EIP-2718 transaction type (0 legacy, 1 access list, 2 EIP-1559, ...).
+    #[graphql(name = "type")]
+    async fn transaction_type(&self) -> Option<u64> {
+        self.transaction_type
+    }
+
+    async fn transaction_index(&self) -> Option<u64> {
+        self.transaction_index
+    }
+
+    async fn block(&self, ctx: &Context<'_>) -> Result<Option<Block>, Error> {
+        let loader = ctx.data::<DataLoader<BlockLoader>>()?;
+        Ok(loader
+            .load_one((self.chain_id, self.block_hash.clone()))
+            .await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transaction_from_eip1559_payload() {
+        let payload = r#"{ "chainId": 84532, "data": {
+            "hash": "0x01",
+            "nonce": "0x2a",
+            "blockHash": "0xabc",
+            "blockNumber": "0x10",
+            "transactionIndex": "0x3",
+            "from": "0xfrom",
+            "to": "0xto",
+            "value": "0x64",
+            "gasPrice": "0x3b9aca00",
+            "gas": "0x5208",
+            "input": "0xa9059cbb000000000000000000000000",
+            "v": "0x1",
+            "r": "0x0",
+            "s": "0x0",
+            "type": "0x2",
+            "maxPriorityFeePerGas": "0x1",
+            "maxFeePerGas": "0x77359400",
+            "chainId": "0x14a34"
+        } }"#;
+
+        let tx: Transaction = serde_json::from_str::<Event<TransactionData>>(payload)
+            .unwrap()
+            .into();
+
+        assert_eq!(tx.nonce, 42);
+        assert_eq!(tx.gas, 21000);
+        assert_eq!(tx.gas_price, Some(1_000_000_000));
+        assert_eq!(tx.max_fee_per_gas, Some(2_000_000_000));
+        assert_eq!(tx.max_priority_fee_per_gas, Some(1));
+        assert_eq!(tx.transaction_type, Some(2));
+        assert_eq!(tx.transaction_index, Some(3));
+        assert_eq!(tx.chain_id, 84532);
+        assert_eq!(tx.method_selector().as_deref(), Some("0xa9059cbb"));
+    }
+
+    #[test]
+    fn test_transaction_from_legacy_transfer() {
+        let payload = r#"{ "chainId": 84532, "data": {
+            "hash": "0x01",
+            "nonce": "0x0",
+            "blockHash": "0xabc",
+            "blockNumber": "0x10",
+            "transactionIndex": "0x0",
+            "from": "0xfrom",
+            "to": "0xto",
+            "value": "0x64",
+            "gasPrice": "0x1",
+            "gas": "0x5208",
+            "input": "0x",
+            "v": "0x1b",
+            "r": "0x0",
+            "s": "0x0"
+        } }"#;
+
+        let tx: Transaction = serde_json::from_str::<Event<TransactionData>>(payload)
+            .unwrap()
+            .into();
+
+        assert_eq!(tx.transaction_type, None);
+        assert_eq!(tx.max_fee_per_gas, None);
+        assert_eq!(tx.chain_id, 84532);
+        assert_eq!(tx.method_selector(), None);
+    }
+
+    #[test]
+    fn test_transaction_value_past_64_bits() {
+        let payload = r#"{ "chainId": 1, "data": {
+            "hash": "0x01",
+            "nonce": "0x0",
+            "blockHash": "0xabc",
+            "blockNumber": "0x10",
+            "transactionIndex": "0x0",
+            "from": "0xfrom",
+            "to": "0xto",
+            "value": "0x56bc75e2d63100000",
+            "gasPrice": "0x1",
+            "gas": "0x5208",
+            "input": "0x"
+        } }"#;
+
+        let tx: Transaction = serde_json::from_str::<Event<TransactionData>>(payload)
+            .unwrap()
+            .into();
+
+        // 100 ether.
+        assert_eq!(tx.value, Wei(100_000_000_000_000_000_000));
+        let json = serde_json::to_value(&tx).unwrap();
+        assert_eq!(json["value"], "100000000000000000000");
+        let tx: Transaction = serde_json::from_value(json).unwrap();
+        assert_eq!(tx.value, Wei(100_000_000_000_000_000_000));
+    }
 }
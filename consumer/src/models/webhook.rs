@@ -0,0 +1,197 @@
+use async_graphql::*;
+use filter_expr::{Record, Value};
+use serde::{Deserialize, Serialize};
+
+use super::block::Block;
+use super::digest::Digest;
+use super::transaction::Transaction;
+use super::wei::Wei;
+
+#[derive(Enum, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum EventKind {
+    Block,
+    Transaction,
+    /// Sent for rules with a digest window instead of their events.
+    Digest,
+}
+
+/// Which events a webhook receives. Every field that is set must match.
+#[derive(SimpleObject, InputObject, Debug, Clone, Default, Serialize, Deserialize)]
+#[graphql(input_name = "WebhookFilterInput")]
+pub struct WebhookFilter {
+    /// Sender or recipient of a transaction, or miner of a block.
+    pub address: Option<String>,
+    /// Smallest transaction value in wei. Blocks never match it.
+    pub min_value: Option<Wei>,
+    /// Empty means every kind.
+    #[graphql(default)]
+    pub event_kinds: Vec<EventKind>,
+    pub chain_id: Option<u64>,
+}
+
+#[derive(SimpleObject, Debug, Clone, Serialize, Deserialize)]
+pub struct Webhook {
+    pub id: u64,
+    pub url: String,
+    /// Key of the HMAC-SHA256 signature sent with every delivery.
+    #[graphql(skip)]
+    pub secret: String,
+    /// Events matching it are sent without a rule; `null` when the webhook
+    /// only serves as a rule channel.
+    pub filter: Option<WebhookFilter>,
+    /// Template the body is rendered with; `null` for the standard JSON
+    /// payload.
+    #[serde(default)]
+    pub template: Option<String>,
+}
+
+/// A block, transaction or digest as it is POSTed to webhooks.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", content = "data", rename_all = "camelCase")]
+pub enum WebhookEvent {
+    Block(Block),
+    Transaction(Transaction),
+    Digest(Digest),
+}
+
+/// Body POSTed to a webhook.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WebhookPayload<'a> {
+    #[serde(flatten)]
+    pub event: &'a WebhookEvent,
+    /// Rules that matched the event; empty when only the webhook's own
+    /// filter did.
+    pub rule_ids: &'a [u64],
+}
+
+impl WebhookEvent {
+    pub fn kind(&self) -> EventKind {
+        match self {
+            Self::Block(_) => EventKind::Block,
+            Self::Transaction(_) => EventKind::Transaction,
+            Self::Digest(_) => EventKind::Digest,
+        }
+    }
+
+    pub fn chain_id(&self) -> u64 {
+        match self {
+            Self::Block(block) => block.chain_id,
+            Self::Transaction(transaction) => transaction.chain_id,
+            Self::Digest(digest) => digest.chain_id,
+        }
+    }
+
+    /// Hash of the block or transaction, for the delivery log. Digests have
+    /// none.
+    pub fn hash(&self) -> &str {
+        match self {
+            Self::Block(block) => &block.hash,
+            Self::Transaction(transaction) => &transaction.hash,
+            Self::Digest(_) => "",
+        }
+    }
+}
+
+/// Fields for rule conditions, named as in the crawler's filters.
+impl Record for WebhookEvent {
+    fn field(&self, name: &str) -> Option<Value> {
+        let lowercase = |text: &str| Value::Str(text.to_lowercase());
+        match self {
+            Self::Block(block) => Some(match name {
+                "kind" => Value::from("block"),
+                "chain_id" => Value::from(block.chain_id),
+                "hash" | "block_hash" => lowercase(&block.hash),
+                "block_number" => Value::from(block.number),
+                "parent_hash" => lowercase(&block.parent_hash),
+                "miner" => lowercase(block.miner.as_deref()?),
+                "timestamp" => Value::from(block.timestamp),
+                "gas_used" => Value::from(block.gas_used),
+                "gas_limit" => Value::from(block.gas_limit),
+                "base_fee_per_gas" => Value::from(block.base_fee_per_gas?),
+                "transaction_count" => Value::from(block.transactions.len() as u64),
+                _ => return None,
+            }),
+            Self::Transaction(tx) => Some(match name {
+                "kind" => Value::from("transaction"),
+                "chain_id" => Value::from(tx.chain_id),
+                "hash" => lowercase(&tx.hash),
+                "block_number" => Value::from(tx.block_number),
+                "block_hash" => lowercase(&tx.block_hash),
+                "from" => lowercase(&tx.from),
+                "to" if tx.to.is_empty() => return None,
+                "to" => lowercase(&tx.to),
+                "value" => Value::Int(tx.value.0),
+                "nonce" => Value::from(tx.nonce),
+                "gas" => Value::from(tx.gas),
+                "gas_price" => Value::from(tx.gas_price?),
+                "max_fee_per_gas" => Value::from(tx.max_fee_per_gas?),
+                "max_priority_fee_per_gas" => Value::from(tx.max_priority_fee_per_gas?),
+                "input" => lowercase(&tx.input),
+                "selector" => lowercase(tx.input.get(..10).filter(|s| s.len() == 10)?),
+                "type" => Value::from(tx.transaction_type?),
+                _ => return None,
+            }),
+            Self::Digest(digest) => Some(match name {
+                "kind" => Value::from("digest"),
+                "chain_id" => Value::from(digest.chain_id),
+                _ => return None,
+            }),
+        }
+    }
+}
+
+impl WebhookFilter {
+    pub fn matches(&self, event: &WebhookEvent) -> bool {
+        if !self.event_kinds.is_empty() && !self.event_kinds.contains(&event.kind()) {
+            return false;
+        }
+        if self.chain_id.is_some_and(|id| id != event.chain_id()) {
+            return false;
+        }
+        match event {
+            WebhookEvent::Block(block) => {
+                self.min_value.is_none()
+                    && self.address.as_ref().is_none_or(|address| {
+                        block
+                            .miner
+                            .as_ref()
+                            .is_some_and(|miner| miner.eq_ignore_ascii_case(address))
+                    })
+            }
+            WebhookEvent::Transaction(transaction) => {
+                self.min_value.is_none_or(|min| transaction.value >= min)
+                    && self.address.as_ref().is_none_or(|address| {
+                        transaction.from.eq_ignore_ascii_case(address)
+                            || transaction.to.eq_ignore_ascii_case(address)
+                    })
+            }
+            WebhookEvent::Digest(_) => false,
+        }
+    }
+}
+
+#[derive(Enum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeliveryStatus {
+    Delivered,
+    Failed,
+}
+
+/// One event sent to one webhook, after its last attempt.
+#[derive(SimpleObject, Debug, Clone)]
+pub struct Delivery {
+    pub webhook_id: u64,
+    /// Rules the event was delivered for, see `WebhookPayload::rule_ids`.
+    pub rule_ids: Vec<u64>,
+    pub event_kind: EventKind,
+    pub chain_id: u64,
+    pub hash: String,
+    pub status: DeliveryStatus,
+    pub attempts: u32,
+    /// HTTP status of the last attempt, if the endpoint answered.
+    pub response_status: Option<u16>,
+    pub error: Option<String>,
+    /// Unix time in seconds of the last attempt.
+    pub timestamp: u64,
+}
@@ -0,0 +1,66 @@
+use async_graphql::*;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+
+/// An amount in wei. Amounts of 2^64 wei (about 18.4 ether) and more are
+/// common, so it is carried as a decimal string in GraphQL and JSON; plain
+/// integers are accepted as well.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Wei(pub u128);
+
+impl fmt::Display for Wei {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl From<u64> for Wei {
+    fn from(value: u64) -> Self {
+        Self(value.into())
+    }
+}
+
+impl Serialize for Wei {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Wei {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Int(u64),
+            Str(String),
+        }
+        match Repr::deserialize(deserializer)? {
+            Repr::Int(value) => Ok(value.into()),
+            Repr::Str(value) => value
+                .parse()
+                .map(Self)
+                .map_err(|_| de::Error::custom(format!("invalid wei amount `{value}`"))),
+        }
+    }
+}
+
+/// Wei as a decimal string, e.g. `"100000000000000000000"` for 100 ether.
+#[Scalar(name = "Wei")]
+impl ScalarType for Wei {
+    fn parse(value: Value) -> InputValueResult<Self> {
+        match &value {
+            Value::Number(n) => n.as_u64().map(Self::from).ok_or_else(|| {
+                InputValueError::custom("amounts past 2^64 wei must be given as strings")
+            }),
+            Value::String(s) => s
+                .parse()
+                .map(Self)
+                .map_err(|_| InputValueError::custom(format!("invalid wei amount `{s}`"))),
+            _ => Err(InputValueError::expected_type(value)),
+        }
+    }
+
+    fn to_value(&self) -> Value {
+        Value::String(self.to_string())
+    }
+}
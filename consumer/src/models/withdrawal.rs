@@ -0,0 +1,52 @@
+use async_graphql::*;
+use serde::{Deserialize, Serialize};
+
+use crate::utils::hex_to_dec;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WithdrawalData {
+    pub index: String,
+    pub validator_index: String,
+    pub address: String,
+    pub amount: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Withdrawal {
+    pub index: u64,
+    pub validator_index: u64,
+    pub address: String,
+    pub amount: u64,
+}
+
+impl From<WithdrawalData> for Withdrawal {
+    fn from(withdrawal: WithdrawalData) -> Self {
+        Self {
+            index: hex_to_dec(withdrawal.index),
+            validator_index: hex_to_dec(withdrawal.validator_index),
+            address: withdrawal.address,
+            amount: hex_to_dec(withdrawal.amount),
+        }
+    }
+}
+
+#[Object]
+impl Withdrawal {
+    async fn index(&self) -> u64 {
+        self.index
+    }
+
+    async fn validator_index(&self) -> u64 {
+        self.validator_index
+    }
+
+    async fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// Withdrawn amount in Gwei.
+    async fn amount(&self) -> u64 {
+        self.amount
+    }
+}
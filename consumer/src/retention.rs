@@ -0,0 +1,286 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+use tokio::time::interval;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info};
+
+use crate::config::RetentionConfig;
+use crate::metrics::{PRUNED_RECORDS, STORAGE_RECORDS};
+use crate::models::storage::Storage;
+use crate::utils::unix_now;
+
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// Per chain, the lowest block number that `config` keeps at `now`. Chains
+/// with nothing to prune are left out.
+fn cutoffs(storage: &Storage, config: &RetentionConfig, now: u64) -> BTreeMap<u64, u64> {
+    // Latest block and latest block that is too old, per chain.
+    let mut chains: BTreeMap<u64, (u64, Option<u64>)> = BTreeMap::new();
+    let oldest_kept = config
+        .max_age_days
+        .map(|days| now.saturating_sub(days.saturating_mul(SECS_PER_DAY)));
+    for block in storage.blocks.values() {
+        let (latest, expired) = chains.entry(block.chain_id).or_default();
+        *latest = (*latest).max(block.number);
+        if oldest_kept.is_some_and(|oldest| block.timestamp < oldest) {
+            *expired = Some(expired.map_or(block.number, |n| n.max(block.number)));
+        }
+    }
+    chains
+        .into_iter()
+        .filter_map(|(chain_id, (latest, expired))| {
+            let by_count = config
+                .max_blocks
+                .map(|max| (latest + 1).saturating_sub(max));
+            let by_age = expired.map(|number| number + 1);
+            let cutoff = by_count.max(by_age).filter(|&cutoff| cutoff > 0)?;
+            Some((chain_id, cutoff))
+        })
+        .collect()
+}
+
+/// Applies the retention policies once. Returns the number of blocks and
+/// transactions removed.
+pub fn prune(storage: &mut Storage, config: &RetentionConfig, now: u64) -> (usize, usize) {
+    let (mut blocks, mut transactions) = (0, 0);
+    for (chain_id, cutoff) in cutoffs(storage, config, now) {
+        let (chain_blocks, chain_transactions) = storage.prune_before(chain_id, cutoff);
+        blocks += chain_blocks;
+        transactions += chain_transactions;
+    }
+    if config.watched_only {
+        let watched = storage.watched_addresses();
+        // Nothing is watched before the first rule or webhook is added, which
+        // must not empty the storage.
+        if watched.is_empty() {
+            debug!("No watched addresses, keeping every transaction");
+        } else {
+            transactions += storage.prune_unwatched(&watched);
+        }
+    }
+    (blocks, transactions)
+}
+
+/// Prunes storage every `config.interval_secs` until `shutdown` is
+/// cancelled. Returns at once when no policy is set.
+pub async fn run(
+    storage: Arc<RwLock<Storage>>,
+    config: RetentionConfig,
+    shutdown: CancellationToken,
+) {
+    if !config.is_enabled() {
+        return;
+    }
+    let mut ticks = interval(Duration::from_secs(config.interval_secs));
+    loop {
+        tokio::select! {
+            _ = ticks.tick() => {}
+            _ = shutdown.cancelled() => break,
+        }
+        let mut storage = storage.write().await;
+        let (blocks, transactions) = prune(&mut storage, &config, unix_now());
+        if blocks == 0 && transactions == 0 {
+            continue;
+        }
+        PRUNED_RECORDS
+            .with_label_values(&["block"])
+            .inc_by(blocks as u64);
+        PRUNED_RECORDS
+            .with_label_values(&["transaction"])
+            .inc_by(transactions as u64);
+        STORAGE_RECORDS
+            .with_label_values(&["block"])
+            .set(storage.blocks.len() as i64);
+        STORAGE_RECORDS
+            .with_label_values(&["transaction"])
+            .set(storage.transactions.len() as i64);
+        debug!(blocks, transactions, "Pruned storage");
+    }
+    info!("Retention task stopped");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{
+        block::Block, rule::NotificationRuleInput, transaction::Transaction, wei::Wei,
+    };
+
+    fn block(chain_id: u64, number: u64, timestamp: u64, transactions: &[&str]) -> Block {
+        Block {
+            chain_id,
+            hash: format!("0xblock{number}"),
+            parent_hash: format!("0xblock{}", number.saturating_sub(1)),
+            number,
+            timestamp,
+            miner: None,
+            gas_used: 0,
+            gas_limit: 0,
+            base_fee_per_gas: None,
+            size: None,
+            extra_data: "0x".to_string(),
+            transactions: transactions.iter().map(|hash| hash.to_string()).collect(),
+            withdrawals: vec![],
+        }
+    }
+
+    fn transaction(chain_id: u64, hash: &str, block_number: u64, to: &str) -> Transaction {
+        Transaction {
+            chain_id,
+            hash: hash.to_string(),
+            block_hash: format!("0xblock{block_number}"),
+            from: "0xalice".to_string(),
+            to: to.to_string(),
+            value: Wei(1),
+            block_number,
+            nonce: 0,
+            gas: 21000,
+            gas_price: None,
+            max_fee_per_gas: None,
+            max_priority_fee_per_gas: None,
+            input: "0x".to_string(),
+            transaction_type: None,
+            transaction_index: None,
+        }
+    }
+
+    async fn storage() -> Storage {
+        let mut storage = Storage::new();
+        for number in 1..=4 {
+            let hash = format!("0xtx{number}");
+            storage
+                .add_block(block(1, number, number * SECS_PER_DAY, &[&hash]))
+                .await;
+            storage
+                .add_transaction(transaction(1, &hash, number, "0xbob"))
+                .await;
+        }
+        // Its block was never consumed.
+        storage
+            .add_transaction(transaction(1, "0xorphan", 1, "0xbob"))
+            .await;
+        storage.add_block(block(10, 7, 0, &[])).await;
+        storage
+    }
+
+    fn block_transactions(storage: &Storage, chain_id: u64, number: u64) -> Vec<String> {
+        storage.blocks[&(chain_id, format!("0xblock{number}"))]
+            .transactions
+            .clone()
+    }
+
+    #[tokio::test]
+    async fn test_keeps_last_blocks() {
+        let mut storage = storage().await;
+        // Listed by a kept block, but pruned with the block it was mined in.
+        storage
+            .blocks
+            .get_mut(&(1, "0xblock3".to_string()))
+            .unwrap()
+            .transactions
+            .push("0xorphan".to_string());
+        let config = RetentionConfig {
+            max_blocks: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(prune(&mut storage, &config, 0), (2, 3));
+        let mut numbers: Vec<u64> = storage.blocks.values().map(|b| b.number).collect();
+        numbers.sort();
+        assert_eq!(numbers, vec![3, 4, 7]);
+        assert!(storage.get_transaction(Some(1), "0xtx2").await.is_none());
+        assert!(storage.get_transaction(Some(1), "0xorphan").await.is_none());
+        assert!(storage.get_transaction(Some(1), "0xtx3").await.is_some());
+        assert_eq!(block_transactions(&storage, 1, 3), vec!["0xtx3"]);
+        // Nothing more to prune.
+        assert_eq!(prune(&mut storage, &config, 0), (0, 0));
+    }
+
+    #[tokio::test]
+    async fn test_keeps_recent_days() {
+        let mut storage = storage().await;
+        let config = RetentionConfig {
+            max_age_days: Some(2),
+            ..Default::default()
+        };
+        // Blocks 1 and 2 of chain 1 and the only block of chain 10 are too old.
+        assert_eq!(prune(&mut storage, &config, 5 * SECS_PER_DAY), (3, 3));
+        assert_eq!(storage.blocks.len(), 2);
+        assert_eq!(storage.chain_ids, [1].into());
+    }
+
+    #[tokio::test]
+    async fn test_keeps_watched_transactions() {
+        let mut storage = storage().await;
+        storage
+            .add_transaction(transaction(1, "0xwatched", 4, "0xTreasury"))
+            .await;
+        storage
+            .blocks
+            .get_mut(&(1, "0xblock4".to_string()))
+            .unwrap()
+            .transactions
+            .push("0xwatched".to_string());
+        let config = RetentionConfig {
+            watched_only: true,
+            ..Default::default()
+        };
+        // Nothing is watched yet.
+        assert_eq!(prune(&mut storage, &config, 0), (0, 0));
+        assert_eq!(storage.transactions.len(), 6);
+
+        storage.notification_channels.insert("ops".to_string());
+        storage
+            .add_rule(NotificationRuleInput {
+                name: "treasury".to_string(),
+                watched_addresses: vec!["0xtreasury".to_string()],
+                contracts: vec![],
+                min_value: None,
+                event_kinds: vec![],
+                chain_id: None,
+                condition: None,
+                digest_window_secs: None,
+                channels: vec![],
+                notify: vec!["ops".to_string()],
+            })
+            .unwrap();
+        assert_eq!(prune(&mut storage, &config, 0), (0, 5));
+        assert_eq!(storage.transactions.len(), 1);
+        assert_eq!(storage.blocks.len(), 5);
+        assert_eq!(block_transactions(&storage, 1, 4), vec!["0xwatched"]);
+        assert!(block_transactions(&storage, 1, 3).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_keeps_transactions_of_rule_conditions() {
+        let mut storage = storage().await;
+        storage
+            .add_transaction(transaction(1, "0xwatched", 4, "0xTreasury"))
+            .await;
+        storage.notification_channels.insert("ops".to_string());
+        storage
+            .add_rule(NotificationRuleInput {
+                name: "treasury".to_string(),
+                watched_addresses: vec![],
+                contracts: vec![],
+                min_value: None,
+                event_kinds: vec![],
+                chain_id: None,
+                condition: Some("to in [\"0xTREASURY\"] and value > 0".to_string()),
+                digest_window_secs: None,
+                channels: vec![],
+                notify: vec!["ops".to_string()],
+            })
+            .unwrap();
+        let config = RetentionConfig {
+            watched_only: true,
+            ..Default::default()
+        };
+        assert_eq!(prune(&mut storage, &config, 0), (0, 5));
+        assert_eq!(
+            storage.transactions.keys().collect::<Vec<_>>(),
+            vec![&(1, "0xwatched".to_string())]
+        );
+    }
+}
@@ -1,14 +1,11 @@
-use async_graphql::{
-    http::{playground_source, GraphQLPlaygroundConfig},
-    EmptyMutation, EmptySubscription, Schema,
-};
+use async_graphql::http::{playground_source, GraphQLPlaygroundConfig};
 use async_graphql_axum::{GraphQLRequest, GraphQLResponse};
 use axum::{
     extract::Extension,
     response::{Html, IntoResponse},
 };
 
-use crate::controller::QueryRoot;
+use crate::controller::AppSchema;
 
 pub(crate) async fn graphql_playground() -> impl IntoResponse {
     Html(playground_source(
@@ -17,7 +14,7 @@ pub(crate) async fn graphql_playground() -> impl IntoResponse {
 }
 
 pub(crate) async fn graphql_handler(
-    schema: Extension<Schema<QueryRoot, EmptyMutation, EmptySubscription>>,
+    schema: Extension<AppSchema>,
     req: GraphQLRequest,
 ) -> GraphQLResponse {
     let response = async move { schema.execute(req.into_inner()).await }.await;
@@ -0,0 +1,141 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+
+use crate::metrics::STORAGE_RECORDS;
+use crate::models::{
+    block::Block,
+    storage::{replace_file, Storage},
+    transaction::Transaction,
+};
+use crate::utils::unix_now;
+
+/// Where the consumer stood in a partition when a snapshot was taken: the
+/// offset of the next message to consume.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SavedOffset {
+    pub topic: String,
+    pub partition: i32,
+    pub offset: i64,
+}
+
+/// The consumed blocks and transactions together with the offsets they were
+/// consumed up to, so that a restart can skip replaying the topics.
+/// Webhooks, rules and digests have the state file for that.
+#[derive(Debug, Deserialize)]
+pub struct Snapshot {
+    /// Unix time in seconds.
+    pub taken_at: u64,
+    pub offsets: Vec<SavedOffset>,
+    pub blocks: Vec<Block>,
+    pub transactions: Vec<Transaction>,
+}
+
+/// A snapshot as it is written, borrowing the records from storage.
+#[derive(Serialize)]
+struct SnapshotRef<'a> {
+    taken_at: u64,
+    offsets: &'a [SavedOffset],
+    blocks: Vec<&'a Block>,
+    transactions: Vec<&'a Transaction>,
+}
+
+/// Serializes the records in `storage` with the `offsets` they were consumed
+/// up to, so that the file can be written once the storage lock is released.
+pub fn encode(storage: &Storage, offsets: &[SavedOffset]) -> Result<Vec<u8>> {
+    let snapshot = SnapshotRef {
+        taken_at: unix_now(),
+        offsets,
+        blocks: storage.blocks.values().collect(),
+        transactions: storage.transactions.values().collect(),
+    };
+    Ok(serde_json::to_vec(&snapshot)?)
+}
+
+/// Writes a snapshot made by `encode`, replacing the previous one in one
+/// step.
+pub fn write(path: &Path, content: &[u8]) -> Result<()> {
+    replace_file(path, content)
+}
+
+/// `None` when no snapshot was taken yet.
+pub fn read(path: &Path) -> Result<Option<Snapshot>> {
+    match fs::read(path) {
+        Ok(content) => serde_json::from_slice(&content)
+            .map(Some)
+            .with_context(|| format!("Invalid snapshot {}", path.display())),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    }
+}
+
+impl Snapshot {
+    /// Adds the records to `storage` and returns the offsets to resume from.
+    pub async fn restore(self, storage: &mut Storage) -> Vec<SavedOffset> {
+        for block in self.blocks {
+            storage.add_block(block).await;
+        }
+        for transaction in self.transactions {
+            storage.add_transaction(transaction).await;
+        }
+        STORAGE_RECORDS
+            .with_label_values(&["block"])
+            .set(storage.blocks.len() as i64);
+        STORAGE_RECORDS
+            .with_label_values(&["transaction"])
+            .set(storage.transactions.len() as i64);
+        self.offsets
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::wei::Wei;
+    use std::env;
+
+    #[tokio::test]
+    async fn test_snapshot_round_trip() {
+        let path = env::temp_dir().join(format!("consumer-snapshot-{}.json", std::process::id()));
+        let _ = fs::remove_file(&path);
+        assert!(read(&path).unwrap().is_none());
+
+        let mut storage = Storage::new();
+        storage
+            .add_transaction(Transaction {
+                chain_id: 10,
+                hash: "0xtx".to_string(),
+                block_hash: "0xblock".to_string(),
+                from: "0xalice".to_string(),
+                to: "0xbob".to_string(),
+                value: Wei(42),
+                block_number: 7,
+                nonce: 0,
+                gas: 21000,
+                gas_price: None,
+                max_fee_per_gas: None,
+                max_priority_fee_per_gas: None,
+                input: "0x".to_string(),
+                transaction_type: Some(2),
+                transaction_index: Some(0),
+            })
+            .await;
+        let offsets = vec![SavedOffset {
+            topic: "tx".to_string(),
+            partition: 0,
+            offset: 1234,
+        }];
+        write(&path, &encode(&storage, &offsets).unwrap()).unwrap();
+
+        let mut restored = Storage::new();
+        let snapshot = read(&path).unwrap().unwrap();
+        assert_eq!(snapshot.restore(&mut restored).await, offsets);
+        let transaction = restored.get_transaction(None, "0xtx").await.unwrap();
+        assert_eq!((transaction.chain_id, transaction.value), (10, Wei(42)));
+        assert!(restored.blocks.is_empty());
+
+        fs::remove_file(&path).unwrap();
+    }
+}
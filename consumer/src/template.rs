@@ -0,0 +1,418 @@
+use anyhow::{bail, Context as _, Result};
+use handlebars::{
+    handlebars_helper, no_escape, Context, Handlebars, Helper, HelperDef, RenderContext,
+    RenderError, ScopedJson,
+};
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::config::TemplateConfig;
+use crate::models::{rule::NotificationRule, webhook::WebhookEvent};
+
+/// Short text for chat messages and email bodies.
+const TEXT_TEMPLATE: &str = "\
+{{#if (eq kind \"transaction\")}}\
+{{ether value}} ETH from {{short from}} to {{short to}} in block {{block_number}}
+{{tx_url hash}}\
+{{else if (eq kind \"digest\")}}\
+{{events}} events: {{transactions}} transactions worth {{ether total_value}} ETH and {{blocks}} blocks
+{{#each top_counterparties}}\
+{{short address}}: {{events}} events, {{ether value}} ETH
+{{/each}}\
+{{else}}\
+Block {{number}} with {{len transactions}} transactions, mined by {{short miner}}
+{{block_url number}}\
+{{/if}}";
+
+/// The whole template context as a JSON document.
+const JSON_TEMPLATE: &str = "{{json this}}";
+
+/// Templates that exist without being configured; configured templates of the
+/// same name replace them.
+pub const BUILTIN_TEMPLATES: [&str; 2] = ["text", "json"];
+
+/// Block explorers of well known chains, overridable through `explorers`.
+const EXPLORERS: [(u64, &str); 8] = [
+    (1, "https://etherscan.io"),
+    (10, "https://optimistic.etherscan.io"),
+    (137, "https://polygonscan.com"),
+    (8453, "https://basescan.org"),
+    (42161, "https://arbiscan.io"),
+    (84532, "https://sepolia.basescan.org"),
+    (11155111, "https://sepolia.etherscan.io"),
+    (11155420, "https://sepolia-optimism.etherscan.io"),
+];
+
+/// Rules that matched an event, for the template context.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MatchedRules {
+    pub ids: Vec<u64>,
+    pub names: Vec<String>,
+}
+
+impl MatchedRules {
+    pub fn push(&mut self, rule: &NotificationRule) {
+        self.ids.push(rule.id);
+        self.names.push(rule.name.clone());
+    }
+}
+
+/// Named Handlebars templates that render blocks, transactions and digests
+/// into notification bodies.
+///
+/// A template sees the fields of the block, transaction or digest (`hash`,
+/// `from`, `value`, `number`, `events`, ...) next to `kind`, `rule_ids`, `rules` and `rule`, the
+/// rule names joined with commas. Besides the Handlebars built-ins it can use:
+/// - `{{ether value}}`/`{{gwei value}}`: wei in ether or gwei, with at most
+///   `digits=` (default 6) fractional digits
+/// - `{{short from}}`: an address or hash shortened to `0x1234…abcd`
+/// - `{{tx_url hash}}`, `{{address_url to}}`, `{{block_url number}}`: links
+///   to the event's chain explorer, empty for chains without one
+/// - `{{json value}}`: a value as JSON, for JSON documents
+///
+/// Nothing is HTML-escaped.
+pub struct Templates {
+    registry: Handlebars<'static>,
+    content_types: BTreeMap<String, String>,
+}
+
+/// Checks that `source` is a valid template.
+pub fn compile(source: &str) -> Result<()> {
+    handlebars::Template::compile(source)?;
+    Ok(())
+}
+
+impl Templates {
+    /// Registers the built-in and configured templates, with explorer links
+    /// for the chains in `explorers` (chain id to base URL) and the well
+    /// known ones.
+    pub fn new(
+        templates: &BTreeMap<String, TemplateConfig>,
+        explorers: &BTreeMap<String, String>,
+    ) -> Result<Self> {
+        let mut registry = Handlebars::new();
+        registry.register_escape_fn(no_escape);
+        registry.register_helper("ether", Box::new(ether));
+        registry.register_helper("gwei", Box::new(gwei));
+        registry.register_helper("short", Box::new(short));
+        registry.register_helper("json", Box::new(json));
+
+        let mut urls: BTreeMap<u64, String> = EXPLORERS
+            .iter()
+            .map(|&(chain_id, url)| (chain_id, url.to_string()))
+            .collect();
+        for (chain_id, url) in explorers {
+            let chain_id = chain_id
+                .parse()
+                .with_context(|| format!("Invalid chain id {chain_id:?} in explorers"))?;
+            urls.insert(chain_id, url.trim_end_matches('/').to_string());
+        }
+        for (name, path) in [
+            ("tx_url", "tx"),
+            ("address_url", "address"),
+            ("block_url", "block"),
+        ] {
+            let helper = ExplorerLink {
+                path,
+                urls: urls.clone(),
+            };
+            registry.register_helper(name, Box::new(helper));
+        }
+
+        let mut content_types = BTreeMap::new();
+        for (name, source, content_type) in [
+            ("text", TEXT_TEMPLATE, "text/plain; charset=utf-8"),
+            ("json", JSON_TEMPLATE, "application/json"),
+        ] {
+            registry.register_template_string(name, source)?;
+            content_types.insert(name.to_string(), content_type.to_string());
+        }
+        for (name, template) in templates {
+            registry
+                .register_template_string(name, &template.source)
+                .with_context(|| format!("Invalid template {name:?}"))?;
+            content_types.insert(name.clone(), template.content_type.clone());
+        }
+        Ok(Self {
+            registry,
+            content_types,
+        })
+    }
+
+    pub fn names(&self) -> BTreeSet<String> {
+        self.content_types.keys().cloned().collect()
+    }
+
+    pub fn content_type(&self, name: &str) -> Option<&str> {
+        self.content_types.get(name).map(String::as_str)
+    }
+
+    /// Renders the template called `name` for `event`.
+    pub fn render(&self, name: &str, event: &WebhookEvent, rules: &MatchedRules) -> Result<String> {
+        if !self.content_types.contains_key(name) {
+            bail!("No template named {name:?}");
+        }
+        Ok(self.registry.render(name, &context(event, rules))?)
+    }
+
+    /// Renders a template given as text, such as a channel's subject line.
+    pub fn render_inline(
+        &self,
+        source: &str,
+        event: &WebhookEvent,
+        rules: &MatchedRules,
+    ) -> Result<String> {
+        Ok(self
+            .registry
+            .render_template(source, &context(event, rules))?)
+    }
+}
+
+fn context(event: &WebhookEvent, rules: &MatchedRules) -> Value {
+    let (kind, data) = match event {
+        WebhookEvent::Block(block) => ("block", serde_json::to_value(block)),
+        WebhookEvent::Transaction(transaction) => {
+            ("transaction", serde_json::to_value(transaction))
+        }
+        WebhookEvent::Digest(digest) => ("digest", serde_json::to_value(digest)),
+    };
+    let mut context = match data.expect("events serialize to JSON") {
+        Value::Object(fields) => fields,
+        _ => Map::new(),
+    };
+    context.insert("kind".to_string(), json!(kind));
+    context.insert("rule_ids".to_string(), json!(rules.ids));
+    context.insert("rules".to_string(), json!(rules.names));
+    context.insert("rule".to_string(), json!(rules.names.join(", ")));
+    Value::Object(context)
+}
+
+/// Reads an amount given as a JSON number or a decimal or `0x` string.
+fn amount(value: &Value) -> Option<u128> {
+    match value {
+        Value::Number(n) => n.as_u64().map(u128::from),
+        Value::String(s) => match s.strip_prefix("0x") {
+            Some(hex) => u128::from_str_radix(hex, 16).ok(),
+            None => s.parse().ok(),
+        },
+        _ => None,
+    }
+}
+
+/// `amount` scaled down by `10^decimals`, with at most `digits` fractional
+/// digits and no trailing zeros.
+fn format_units(amount: u128, decimals: u32, digits: u64) -> String {
+    let unit = 10u128.pow(decimals);
+    let whole = amount / unit;
+    let fraction = format!("{:0width$}", amount % unit, width = decimals as usize);
+    let fraction = fraction[..fraction.len().min(digits as usize)].trim_end_matches('0');
+    if fraction.is_empty() {
+        whole.to_string()
+    } else {
+        format!("{whole}.{fraction}")
+    }
+}
+
+handlebars_helper!(ether: |value: Json, {digits: u64 = 6}| {
+    amount(value).map(|wei| format_units(wei, 18, digits)).unwrap_or_default()
+});
+
+handlebars_helper!(gwei: |value: Json, {digits: u64 = 6}| {
+    amount(value).map(|wei| format_units(wei, 9, digits)).unwrap_or_default()
+});
+
+handlebars_helper!(short: |value: Json| {
+    match value.as_str() {
+        Some(s) if s.len() > 12 && s.is_ascii() => format!("{}…{}", &s[..6], &s[s.len() - 4..]),
+        Some(s) => s.to_string(),
+        None => String::new(),
+    }
+});
+
+handlebars_helper!(json: |value: Json| value.to_string());
+
+/// Link to the explorer page of the event's chain for the parameter.
+struct ExplorerLink {
+    path: &'static str,
+    urls: BTreeMap<u64, String>,
+}
+
+impl HelperDef for ExplorerLink {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'rc>,
+        _: &'reg Handlebars<'reg>,
+        ctx: &'rc Context,
+        _: &mut RenderContext<'reg, 'rc>,
+    ) -> Result<ScopedJson<'rc>, RenderError> {
+        let base = ctx.data()["chain_id"]
+            .as_u64()
+            .and_then(|chain_id| self.urls.get(&chain_id));
+        let target = match h.param(0).map(|param| param.value()) {
+            Some(Value::String(s)) => s.clone(),
+            Some(Value::Number(n)) => n.to_string(),
+            _ => String::new(),
+        };
+        let link = match base {
+            Some(base) if !target.is_empty() => format!("{base}/{}/{target}", self.path),
+            _ => String::new(),
+        };
+        Ok(ScopedJson::Derived(Value::String(link)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{
+        block::Block,
+        digest::{Counterparty, Digest},
+        transaction::Transaction,
+        wei::Wei,
+    };
+
+    fn transaction(chain_id: u64) -> WebhookEvent {
+        WebhookEvent::Transaction(Transaction {
+            chain_id,
+            hash: "0x5c504ed432cb51138bcf09aa5e8a410dd4a1e204ef84bfed1be16dfba1b22060".to_string(),
+            block_hash: "0xblock".to_string(),
+            from: "0x4200000000000000000000000000000000000011".to_string(),
+            to: "0xd8da6bf26964af9d7eed9e03e53415d37aa96045".to_string(),
+            value: Wei(1_500_000_000_000_000_000),
+            block_number: 17166114,
+            nonce: 0,
+            gas: 21000,
+            gas_price: Some(1_000_000_000),
+            max_fee_per_gas: None,
+            max_priority_fee_per_gas: None,
+            input: "0x".to_string(),
+            transaction_type: None,
+            transaction_index: None,
+        })
+    }
+
+    fn rules() -> MatchedRules {
+        MatchedRules {
+            ids: vec![1, 2],
+            names: vec!["whale".to_string(), "vitalik".to_string()],
+        }
+    }
+
+    #[test]
+    fn test_helpers() {
+        let templates = Templates::new(
+            &BTreeMap::new(),
+            &[("1".to_string(), "https://eth.blockscout.com/".to_string())].into(),
+        )
+        .unwrap();
+        let render = |source: &str, chain_id: u64| {
+            templates
+                .render_inline(source, &transaction(chain_id), &rules())
+                .unwrap()
+        };
+
+        assert_eq!(render("{{ether value}} ETH", 1), "1.5 ETH");
+        assert_eq!(render("{{gwei gas_price}} gwei", 1), "1 gwei");
+        assert_eq!(
+            render("{{ether 1234567 digits=15}}", 1),
+            "0.000000000001234"
+        );
+        assert_eq!(render("{{ether \"0x0de0b6b3a7640000\"}}", 1), "1");
+        assert_eq!(render("{{ether \"100000000000000000000\"}}", 1), "100");
+        assert_eq!(render("{{short from}}", 1), "0x4200…0011");
+        assert_eq!(
+            render("{{rule}} {{json rule_ids}}", 1),
+            "whale, vitalik [1,2]"
+        );
+        assert_eq!(
+            render("{{address_url to}}", 1),
+            "https://eth.blockscout.com/address/0xd8da6bf26964af9d7eed9e03e53415d37aa96045"
+        );
+        assert_eq!(
+            render("{{block_url block_number}}", 8453),
+            "https://basescan.org/block/17166114"
+        );
+        // No explorer is known for this chain.
+        assert_eq!(render("[{{tx_url hash}}]", 999), "[]");
+    }
+
+    #[test]
+    fn test_builtin_and_configured_templates() {
+        let configured = [(
+            "slack".to_string(),
+            TemplateConfig {
+                source: r#"{"text": {{json (ether value)}}, "rules": {{json rules}}}"#.to_string(),
+                content_type: "application/json".to_string(),
+            },
+        )]
+        .into();
+        let templates = Templates::new(&configured, &BTreeMap::new()).unwrap();
+        assert_eq!(
+            templates.names(),
+            ["json", "slack", "text"].map(String::from).into()
+        );
+
+        let event = transaction(1);
+        assert_eq!(
+            templates.render("text", &event, &rules()).unwrap(),
+            "1.5 ETH from 0x4200…0011 to 0xd8da…6045 in block 17166114\n\
+             https://etherscan.io/tx/0x5c504ed432cb51138bcf09aa5e8a410dd4a1e204ef84bfed1be16dfba1b22060"
+        );
+        let document: Value =
+            serde_json::from_str(&templates.render("json", &event, &rules()).unwrap()).unwrap();
+        assert_eq!(document["kind"], "transaction");
+        assert_eq!(document["value"], "1500000000000000000");
+        assert_eq!(document["rule_ids"], json!([1, 2]));
+        let document: Value =
+            serde_json::from_str(&templates.render("slack", &event, &rules()).unwrap()).unwrap();
+        assert_eq!(
+            document,
+            json!({ "text": "1.5", "rules": ["whale", "vitalik"] })
+        );
+
+        let block = WebhookEvent::Block(Block {
+            chain_id: 1,
+            hash: "0xblock".to_string(),
+            parent_hash: "0xparent".to_string(),
+            number: 20000000,
+            timestamp: 0,
+            miner: Some("0x95222290dd7278aa3ddd389cc1e1d165cc4bafe5".to_string()),
+            gas_used: 0,
+            gas_limit: 0,
+            base_fee_per_gas: None,
+            size: None,
+            extra_data: "0x".to_string(),
+            transactions: vec!["0xa".to_string(), "0xb".to_string()],
+            withdrawals: vec![],
+        });
+        assert_eq!(
+            templates.render("text", &block, &rules()).unwrap(),
+            "Block 20000000 with 2 transactions, mined by 0x9522…afe5\n\
+             https://etherscan.io/block/20000000"
+        );
+
+        let digest = WebhookEvent::Digest(Digest {
+            rule_id: 1,
+            chain_id: 1,
+            started_at: 0,
+            ended_at: 60,
+            events: 3,
+            transactions: 3,
+            blocks: 0,
+            total_value: "25000000000000000000".to_string(),
+            top_counterparties: vec![Counterparty {
+                address: "0xd8da6bf26964af9d7eed9e03e53415d37aa96045".to_string(),
+                events: 2,
+                value: "20000000000000000000".to_string(),
+            }],
+        });
+        assert_eq!(
+            templates.render("text", &digest, &rules()).unwrap(),
+            "3 events: 3 transactions worth 25 ETH and 0 blocks\n\
+             0xd8da…6045: 2 events, 20 ETH\n"
+        );
+
+        assert!(templates.render("missing", &event, &rules()).is_err());
+        assert!(compile("{{#if kind}}").is_err());
+    }
+}
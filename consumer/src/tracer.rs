@@ -0,0 +1,216 @@
+use opentelemetry::{
+    global, propagation::TextMapPropagator, trace::TraceError, trace::TracerProvider as _, Context,
+    KeyValue,
+};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{Sampler, Tracer, TracerProvider};
+use opentelemetry_sdk::{runtime, trace as sdktrace, Resource};
+use opentelemetry_semantic_conventions::resource::SERVICE_NAME;
+use std::collections::HashMap;
+use std::env;
+use tracing::{info, warn};
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer};
+
+const TRACER_NAME: &str = "consumer";
+const DEFAULT_SERVICE_NAME: &str = "notification-consumer";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OtlpProtocol {
+    Grpc,
+    Http,
+}
+
+/// Span export settings, read from the standard `OTEL_*` variables.
+#[derive(Debug, Clone)]
+pub struct TracingConfig {
+    /// `false` when `OTEL_TRACES_EXPORTER=none`: logs are still written but
+    /// no spans leave the process.
+    pub enabled: bool,
+    /// Collector URL; the exporter's default for `protocol` when unset.
+    pub endpoint: Option<String>,
+    pub protocol: OtlpProtocol,
+    pub service_name: String,
+    pub resource_attributes: Vec<KeyValue>,
+    /// Share of new traces to record. Traces continued from a parent keep the
+    /// parent's decision.
+    pub sample_ratio: f64,
+}
+
+impl TracingConfig {
+    pub fn from_env() -> Self {
+        Self::from_vars(|key| env::var(key).ok())
+    }
+
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Self {
+        let protocol = match var("OTEL_EXPORTER_OTLP_PROTOCOL").as_deref() {
+            None | Some("grpc") => OtlpProtocol::Grpc,
+            Some("http/protobuf") => OtlpProtocol::Http,
+            Some(other) => panic!("Unsupported OTEL_EXPORTER_OTLP_PROTOCOL {}", other),
+        };
+        Self {
+            enabled: var("OTEL_TRACES_EXPORTER").is_none_or(|exporter| exporter != "none"),
+            endpoint: var("OTEL_EXPORTER_OTLP_ENDPOINT"),
+            protocol,
+            service_name: var("OTEL_SERVICE_NAME")
+                .unwrap_or_else(|| DEFAULT_SERVICE_NAME.to_string()),
+            resource_attributes: var("OTEL_RESOURCE_ATTRIBUTES")
+                .map(|attributes| parse_resource_attributes(&attributes))
+                .unwrap_or_default(),
+            sample_ratio: var("OTEL_TRACES_SAMPLER_ARG")
+                .and_then(|val| val.parse::<f64>().ok())
+                .unwrap_or(1.0),
+        }
+    }
+}
+
+/// Parses `key=value,key=value` pairs, skipping malformed entries.
+fn parse_resource_attributes(attributes: &str) -> Vec<KeyValue> {
+    attributes
+        .split(',')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(key, value)| KeyValue::new(key.trim().to_string(), value.trim().to_string()))
+        .collect()
+}
+
+pub fn init_tracer_provider(config: &TracingConfig) -> Result<Tracer, TraceError> {
+    let exporter = match config.protocol {
+        OtlpProtocol::Grpc => {
+            let mut exporter = opentelemetry_otlp::new_exporter().tonic();
+            if let Some(endpoint) = &config.endpoint {
+                exporter = exporter.with_endpoint(endpoint);
+            }
+            exporter.build_span_exporter()?
+        }
+        OtlpProtocol::Http => {
+            let mut exporter = opentelemetry_otlp::new_exporter().http();
+            if let Some(endpoint) = &config.endpoint {
+                exporter =
+                    exporter.with_endpoint(format!("{}/v1/traces", endpoint.trim_end_matches('/')));
+            }
+            exporter.build_span_exporter()?
+        }
+    };
+
+    let mut resource = vec![KeyValue::new(SERVICE_NAME, config.service_name.clone())];
+    resource.extend(config.resource_attributes.iter().cloned());
+    let provider = TracerProvider::builder()
+        .with_batch_exporter(exporter, runtime::Tokio)
+        .with_config(
+            sdktrace::Config::default()
+                .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+                    config.sample_ratio,
+                ))))
+                .with_resource(Resource::new(resource)),
+        )
+        .build();
+    let tracer = provider.tracer(TRACER_NAME);
+    global::set_tracer_provider(provider);
+    Ok(tracer)
+}
+
+/// Sets up span export per `config` and installs the global `tracing`
+/// subscriber. A broken exporter setup is logged instead of stopping startup.
+pub fn init_tracing(config: &TracingConfig) {
+    let (tracer, error) = if config.enabled {
+        match init_tracer_provider(config) {
+            Ok(tracer) => (Some(tracer), None),
+            Err(e) => (None, Some(e)),
+        }
+    } else {
+        (None, None)
+    };
+    init_subscriber(tracer);
+    match error {
+        Some(e) => warn!(error = %e, "Failed to set up span export, tracing disabled"),
+        None if !config.enabled => info!("Span export disabled"),
+        None => {}
+    }
+}
+
+/// Installs the global `tracing` subscriber: log lines filtered by `RUST_LOG`
+/// (default `info`), printed as JSON when `LOG_FORMAT=json`, and spans
+/// exported through `tracer` when span export is on.
+fn init_subscriber(tracer: Option<Tracer>) {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let fmt = match env::var("LOG_FORMAT").as_deref() {
+        Ok("json") => tracing_subscriber::fmt::layer()
+            .json()
+            .with_current_span(true)
+            .boxed(),
+        _ => tracing_subscriber::fmt::layer().boxed(),
+    };
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt)
+        .with(tracer.map(|tracer| tracing_opentelemetry::layer().with_tracer(tracer)))
+        .init();
+}
+
+/// Restores the W3C trace context the crawler put into the message headers,
+/// so spans started under it join the crawler's trace.
+pub fn extract_context(headers: &HashMap<String, String>) -> Context {
+    TraceContextPropagator::new().extract(headers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::trace::{TraceContextExt, TraceId};
+
+    #[test]
+    fn test_extract_context() {
+        let headers = HashMap::from([(
+            "traceparent".to_string(),
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01".to_string(),
+        )]);
+
+        let cx = extract_context(&headers);
+        let span_context = cx.span().span_context().clone();
+
+        assert!(span_context.is_remote());
+        assert_eq!(
+            span_context.trace_id(),
+            TraceId::from_hex("4bf92f3577b34da6a3ce929d0e0e4736").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_extract_context_without_headers() {
+        let cx = extract_context(&HashMap::new());
+        assert!(!cx.span().span_context().is_valid());
+    }
+
+    #[test]
+    fn test_tracing_config_from_vars() {
+        let vars = HashMap::from([
+            ("OTEL_EXPORTER_OTLP_PROTOCOL", "http/protobuf"),
+            ("OTEL_EXPORTER_OTLP_ENDPOINT", "http://collector:4318"),
+            (
+                "OTEL_RESOURCE_ATTRIBUTES",
+                "deployment.environment=dev, host.name=a,bad",
+            ),
+            ("OTEL_TRACES_SAMPLER_ARG", "0.25"),
+        ]);
+        let config = TracingConfig::from_vars(|key| vars.get(key).map(|v| v.to_string()));
+
+        assert!(config.enabled);
+        assert_eq!(config.protocol, OtlpProtocol::Http);
+        assert_eq!(config.endpoint.as_deref(), Some("http://collector:4318"));
+        assert_eq!(config.service_name, DEFAULT_SERVICE_NAME);
+        assert_eq!(
+            config.resource_attributes,
+            vec![
+                KeyValue::new("deployment.environment", "dev"),
+                KeyValue::new("host.name", "a"),
+            ]
+        );
+        assert_eq!(config.sample_ratio, 0.25);
+
+        let disabled = TracingConfig::from_vars(|key| {
+            (key == "OTEL_TRACES_EXPORTER").then(|| "none".to_string())
+        });
+        assert!(!disabled.enabled);
+        assert_eq!(disabled.protocol, OtlpProtocol::Grpc);
+    }
+}
@@ -1,3 +1,5 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
 pub fn hex_to_dec(hex_str: String) -> u64 {
     // Remove the "0x" prefix if it exists
     let hex_number = hex_str.strip_prefix("0x").unwrap_or(&hex_str);
@@ -5,3 +7,17 @@ pub fn hex_to_dec(hex_str: String) -> u64 {
     // Convert the hexadecimal string to a number, returning 0 on error
     u64::from_str_radix(hex_number, 16).unwrap_or(0)
 }
+
+/// Like `hex_to_dec`, for amounts in wei that do not fit 64 bits.
+pub fn hex_to_u128(hex_str: String) -> u128 {
+    let hex_number = hex_str.strip_prefix("0x").unwrap_or(&hex_str);
+    u128::from_str_radix(hex_number, 16).unwrap_or(0)
+}
+
+/// Current Unix time in seconds.
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
@@ -0,0 +1,1150 @@
+use crate::{
+    channel::Channel,
+    config::{ChannelConfig, WebhookConfig},
+    metrics::{
+        CHANNEL_NOTIFICATIONS, DUPLICATE_NOTIFICATIONS, WEBHOOK_ATTEMPTS, WEBHOOK_DELIVERIES,
+    },
+    models::{
+        notified::NotificationKey,
+        storage::{write_state, Storage},
+        webhook::{Delivery, DeliveryStatus, Webhook, WebhookEvent, WebhookPayload},
+    },
+    template::{MatchedRules, Templates},
+    utils::unix_now,
+};
+use hmac::{Hmac, Mac};
+use reqwest::{Client, StatusCode};
+use sha2::Sha256;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, RwLock};
+use tokio::task::{self, JoinSet};
+use tokio::time::{interval, sleep};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+pub const SIGNATURE_HEADER: &str = "X-Webhook-Signature";
+pub const WEBHOOK_ID_HEADER: &str = "X-Webhook-Id";
+
+/// Events waiting for the dispatcher; the Kafka consumer waits when it is full.
+pub const EVENT_QUEUE_SIZE: usize = 1024;
+
+/// How often digests whose window has ended are looked for, and changed
+/// digests saved.
+const DIGEST_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often notifications past the de-duplication window are forgotten.
+const NOTIFIED_EXPIRY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// `sha256=` followed by the hex HMAC-SHA256 of `body` keyed with `secret`.
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Whether a failed request is worth repeating: the endpoint could not be
+/// reached, timed out, is overloaded or failed on its side.
+fn is_retryable(status: Option<StatusCode>) -> bool {
+    status.is_none_or(|status| {
+        status.is_server_error()
+            || status == StatusCode::TOO_MANY_REQUESTS
+            || status == StatusCode::REQUEST_TIMEOUT
+    })
+}
+
+/// Runs `write` on the blocking pool, so that neither the storage lock nor a
+/// runtime thread is held while a file is written. Returns whether the write
+/// succeeded.
+async fn save(what: &str, write: impl FnOnce() -> anyhow::Result<()> + Send + 'static) -> bool {
+    let result = task::spawn_blocking(write)
+        .await
+        .map_err(anyhow::Error::from)
+        .and_then(|result| result);
+    if let Err(e) = &result {
+        warn!(error = %e, "Failed to save {what}");
+    }
+    result.is_ok()
+}
+
+/// Webhooks and notification channels to notify of one event.
+#[derive(Default)]
+struct Targets {
+    /// Each webhook with the rules that matched.
+    webhooks: Vec<(Webhook, MatchedRules)>,
+    /// Each channel name with the rules that matched.
+    channels: BTreeMap<String, MatchedRules>,
+}
+
+/// POSTs consumed blocks and transactions to the registered webhooks whose
+/// filter they match, and messages the notification channels of matching
+/// rules.
+///
+/// Each delivery runs on its own task, so a slow endpoint does not hold up
+/// the others. Failed requests are repeated with exponential backoff, and
+/// the outcome of every webhook delivery is kept in the storage's delivery
+/// log.
+///
+/// An event is notified at most once per rule: notifications are recorded,
+/// and saved to the state file, before they are sent, and events the
+/// crawler emits again within the de-duplication window are skipped.
+#[derive(Clone)]
+pub struct WebhookDispatcher {
+    client: Client,
+    storage: Arc<RwLock<Storage>>,
+    config: WebhookConfig,
+    templates: Arc<Templates>,
+    channels: Arc<BTreeMap<String, Channel>>,
+}
+
+impl WebhookDispatcher {
+    pub fn new(
+        config: &WebhookConfig,
+        channels: &[ChannelConfig],
+        templates: Arc<Templates>,
+        storage: Arc<RwLock<Storage>>,
+    ) -> anyhow::Result<Self> {
+        let timeout = Duration::from_millis(config.timeout_ms);
+        let client = Client::builder().timeout(timeout).build()?;
+        let channels = channels
+            .iter()
+            .map(|config| {
+                let channel = Channel::from_config(config, templates.clone(), &client, timeout)?;
+                Ok((channel.name.clone(), channel))
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Self {
+            client,
+            storage,
+            config: config.clone(),
+            templates,
+            channels: Arc::new(channels),
+        })
+    }
+
+    /// Dispatches events until every sender of `events` is dropped, then waits
+    /// for the deliveries in flight. Once `shutdown` is cancelled, deliveries
+    /// are not retried any more. Digests still collecting events when it stops
+    /// are saved, and sent once their window ends after a restart.
+    pub async fn run(self, mut events: mpsc::Receiver<WebhookEvent>, shutdown: CancellationToken) {
+        let mut deliveries = JoinSet::new();
+        let mut digest_check = interval(DIGEST_CHECK_INTERVAL);
+        let mut notified_expiry = interval(NOTIFIED_EXPIRY_INTERVAL);
+        loop {
+            tokio::select! {
+                event = events.recv() => {
+                    let Some(event) = event else {
+                        break;
+                    };
+                    let targets = self.targets(&event).await;
+                    self.dispatch(event, targets, &mut deliveries, &shutdown);
+                }
+                _ = digest_check.tick() => {
+                    self.send_digests(unix_now(), &mut deliveries, &shutdown).await;
+                }
+                _ = notified_expiry.tick() => {
+                    self.expire_notified(unix_now()).await;
+                }
+            }
+            // Reaps finished deliveries so the set does not grow unbounded.
+            while deliveries.try_join_next().is_some() {}
+        }
+        self.save_digests().await;
+        while deliveries.join_next().await.is_some() {}
+        info!("Webhook dispatcher stopped");
+    }
+
+    /// Starts a delivery of `event` to every target.
+    fn dispatch(
+        &self,
+        event: WebhookEvent,
+        targets: Targets,
+        deliveries: &mut JoinSet<()>,
+        shutdown: &CancellationToken,
+    ) {
+        if targets.webhooks.is_empty() && targets.channels.is_empty() {
+            return;
+        }
+        let event = Arc::new(event);
+        for (webhook, rules) in targets.webhooks {
+            let dispatcher = self.clone();
+            let event = event.clone();
+            let shutdown = shutdown.clone();
+            deliveries.spawn(async move {
+                dispatcher.deliver(&webhook, &event, rules, &shutdown).await;
+            });
+        }
+        for (channel, rules) in targets.channels {
+            let dispatcher = self.clone();
+            let event = event.clone();
+            let shutdown = shutdown.clone();
+            deliveries.spawn(async move {
+                dispatcher.notify(&channel, &event, &rules, &shutdown).await;
+            });
+        }
+    }
+
+    /// Sends the digests whose window has ended by `now` to the webhooks and
+    /// channels of their rule. Digests of deleted rules are dropped.
+    async fn send_digests(
+        &self,
+        now: u64,
+        deliveries: &mut JoinSet<()>,
+        shutdown: &CancellationToken,
+    ) {
+        let mut storage = self.storage.write().await;
+        let digests = storage.digests.take_due(now);
+        let changed = storage.digests.take_changed();
+        let subscriptions = &storage.subscriptions;
+        let mut sends = Vec::new();
+        for digest in digests {
+            let Some(rule) = subscriptions.rules.get(&digest.rule_id) else {
+                continue;
+            };
+            let mut rules = MatchedRules::default();
+            rules.push(rule);
+            let webhooks = rule
+                .channels
+                .iter()
+                .filter_map(|id| subscriptions.webhooks.get(id))
+                .map(|webhook| (webhook.clone(), rules.clone()))
+                .collect();
+            let channels = rule
+                .notify
+                .iter()
+                .map(|name| (name.clone(), rules.clone()))
+                .collect();
+            debug!(rule.id = rule.id, events = digest.events, "Sending digest");
+            sends.push((WebhookEvent::Digest(digest), Targets { webhooks, channels }));
+        }
+        let path = storage.digests_file();
+        drop(storage);
+        if let (Some(path), Some(changed)) = (path, changed) {
+            save("digests", move || write_state(&path, &changed)).await;
+        }
+        for (event, targets) in sends {
+            self.dispatch(event, targets, deliveries, shutdown);
+        }
+    }
+
+    /// Saves the digests if they changed since they were last saved.
+    async fn save_digests(&self) {
+        let mut storage = self.storage.write().await;
+        let changed = storage.digests.take_changed();
+        let path = storage.digests_file();
+        drop(storage);
+        if let (Some(path), Some(changed)) = (path, changed) {
+            save("digests", move || write_state(&path, &changed)).await;
+        }
+    }
+
+    /// Forgets the notifications sent before the de-duplication window and
+    /// rewrites the log file without them.
+    async fn expire_notified(&self, now: u64) {
+        let mut storage = self.storage.write().await;
+        let Some(entries) = storage.notified.expire(now, self.config.dedup_window_secs) else {
+            return;
+        };
+        let path = storage.notified_file();
+        drop(storage);
+        if let Some(path) = path {
+            save("sent notifications", move || entries.replace(&path)).await;
+        }
+    }
+
+    /// Webhooks and channels to notify of `event`, each with the rules that
+    /// matched it. A webhook or channel notified by several rules, or a
+    /// webhook matched by its own filter as well, still gets a single request.
+    /// Rules the event was already notified for are left out, and events of
+    /// rules with a digest window are added to their digest instead.
+    ///
+    /// The notifications are appended to their log file before this returns.
+    /// Events are handled one at a time, so the appends never overlap with
+    /// the log being rewritten by `expire_notified`. When the append fails
+    /// nothing is sent, and the notifications are released so that the event
+    /// is notified if it comes again.
+    async fn targets(&self, event: &WebhookEvent) -> Targets {
+        let mut storage = self.storage.write().await;
+        let targets = self.match_targets(&mut storage, event);
+        let unsaved = storage.notified.take_unsaved();
+        let path = storage.notified_file();
+        drop(storage);
+        let (Some(path), false) = (path, unsaved.is_empty()) else {
+            return targets;
+        };
+        let entries = unsaved.clone();
+        if !save("sent notifications", move || entries.append(&path)).await {
+            warn!(hash = event.hash(), "Not notifying event");
+            self.storage.write().await.notified.release(&unsaved);
+            return Targets::default();
+        }
+        targets
+    }
+
+    fn match_targets(&self, storage: &mut Storage, event: &WebhookEvent) -> Targets {
+        let subscriptions = &storage.subscriptions;
+        let filtered: Vec<u64> = subscriptions
+            .webhooks
+            .values()
+            .filter(|webhook| webhook.filter.as_ref().is_some_and(|f| f.matches(event)))
+            .map(|webhook| webhook.id)
+            .collect();
+        let rules: Vec<u64> = subscriptions
+            .rules
+            .values()
+            .filter(|rule| rule.matches(event, &storage.address_books))
+            .map(|rule| rule.id)
+            .collect();
+        if filtered.is_empty() && rules.is_empty() {
+            return Targets::default();
+        }
+
+        // Webhooks matched by their own filter share the key without a rule.
+        let keys = rules
+            .iter()
+            .map(|id| Some(*id))
+            .chain((!filtered.is_empty()).then_some(None))
+            .map(|rule_id| NotificationKey::new(event, rule_id));
+        let matched = rules.len() + usize::from(!filtered.is_empty());
+        let fresh: BTreeSet<Option<u64>> = storage
+            .notified
+            .claim(keys, unix_now(), self.config.dedup_window_secs)
+            .into_iter()
+            .map(|key| key.rule_id)
+            .collect();
+        if fresh.len() < matched {
+            DUPLICATE_NOTIFICATIONS.inc_by((matched - fresh.len()) as u64);
+            debug!(
+                hash = event.hash(),
+                skipped = matched - fresh.len(),
+                "Event was already notified"
+            );
+        }
+        if fresh.is_empty() {
+            return Targets::default();
+        }
+
+        let subscriptions = &storage.subscriptions;
+        let mut targets: BTreeMap<u64, MatchedRules> = BTreeMap::new();
+        if fresh.contains(&None) {
+            for id in filtered {
+                targets.insert(id, MatchedRules::default());
+            }
+        }
+        let mut channels: BTreeMap<String, MatchedRules> = BTreeMap::new();
+        for rule in rules
+            .iter()
+            .filter(|id| fresh.contains(&Some(**id)))
+            .filter_map(|id| subscriptions.rules.get(id))
+        {
+            if let Some(window) = rule.digest_window_secs {
+                storage.digests.add(rule, window, event, unix_now());
+                continue;
+            }
+            for channel in &rule.channels {
+                targets.entry(*channel).or_default().push(rule);
+            }
+            for name in &rule.notify {
+                channels.entry(name.clone()).or_default().push(rule);
+            }
+        }
+        let webhooks = targets
+            .into_iter()
+            .filter_map(|(id, rules)| {
+                let webhook = subscriptions.webhooks.get(&id)?;
+                Some((webhook.clone(), rules))
+            })
+            .collect();
+        Targets { webhooks, channels }
+    }
+
+    /// Sends the channel's message about `event`, retrying failures like
+    /// webhook deliveries.
+    async fn notify(
+        &self,
+        name: &str,
+        event: &WebhookEvent,
+        rules: &MatchedRules,
+        shutdown: &CancellationToken,
+    ) {
+        let Some(channel) = self.channels.get(name) else {
+            // Rules can only name configured channels, but the state file
+            // may predate a configuration change.
+            warn!(channel = name, "Rule names an unknown notification channel");
+            return;
+        };
+        let mut attempts = 0;
+        let result = match channel.message(event, rules) {
+            Ok(message) => loop {
+                attempts += 1;
+                let result = channel.send(&message).await;
+                let Err(error) = &result else {
+                    break result;
+                };
+                if attempts >= self.config.max_attempts {
+                    break result;
+                }
+                let backoff = self.config.backoff(attempts);
+                warn!(
+                    channel = name,
+                    attempt = attempts,
+                    error = %error,
+                    "Notification failed, retrying in {backoff:?}"
+                );
+                tokio::select! {
+                    _ = sleep(backoff) => {}
+                    _ = shutdown.cancelled() => break result,
+                }
+            },
+            Err(e) => Err(e.context("Failed to render template")),
+        };
+        match result {
+            Ok(()) => {
+                CHANNEL_NOTIFICATIONS
+                    .with_label_values(&[name, "delivered"])
+                    .inc();
+                debug!(channel = name, hash = event.hash(), "Notification sent");
+            }
+            Err(e) => {
+                CHANNEL_NOTIFICATIONS
+                    .with_label_values(&[name, "failed"])
+                    .inc();
+                warn!(
+                    channel = name,
+                    hash = event.hash(),
+                    attempts,
+                    error = %e,
+                    "Notification failed"
+                );
+            }
+        }
+    }
+
+    async fn deliver(
+        &self,
+        webhook: &Webhook,
+        event: &WebhookEvent,
+        rules: MatchedRules,
+        shutdown: &CancellationToken,
+    ) {
+        let (outcome, attempts, response_status, error) = match self.body(webhook, event, &rules) {
+            Ok((body, content_type)) => self.post(webhook, body, content_type, shutdown).await,
+            Err(e) => (
+                DeliveryStatus::Failed,
+                0,
+                None,
+                Some(format!("Failed to render template: {e:#}")),
+            ),
+        };
+
+        match outcome {
+            DeliveryStatus::Delivered => {
+                WEBHOOK_DELIVERIES.with_label_values(&["delivered"]).inc();
+                debug!(
+                    webhook.id = webhook.id,
+                    hash = event.hash(),
+                    "Webhook delivered"
+                );
+            }
+            DeliveryStatus::Failed => {
+                WEBHOOK_DELIVERIES.with_label_values(&["failed"]).inc();
+                warn!(
+                    webhook.id = webhook.id,
+                    hash = event.hash(),
+                    attempts,
+                    error = error.as_deref().unwrap_or_default(),
+                    "Webhook delivery failed"
+                );
+            }
+        }
+        let delivery = Delivery {
+            webhook_id: webhook.id,
+            rule_ids: rules.ids,
+            event_kind: event.kind(),
+            chain_id: event.chain_id(),
+            hash: event.hash().to_string(),
+            status: outcome,
+            attempts,
+            response_status,
+            error,
+            timestamp: unix_now(),
+        };
+        self.storage
+            .write()
+            .await
+            .record_delivery(delivery, self.config.delivery_log_size);
+    }
+
+    /// The webhook's template rendered for `event`, or the standard JSON
+    /// payload, with its content type.
+    fn body(
+        &self,
+        webhook: &Webhook,
+        event: &WebhookEvent,
+        rules: &MatchedRules,
+    ) -> anyhow::Result<(Vec<u8>, &str)> {
+        match &webhook.template {
+            Some(name) => {
+                let body = self.templates.render(name, event, rules)?;
+                let content_type = self.templates.content_type(name).unwrap_or_default();
+                Ok((body.into_bytes(), content_type))
+            }
+            None => {
+                let payload = WebhookPayload {
+                    event,
+                    rule_ids: &rules.ids,
+                };
+                let body = serde_json::to_vec(&payload).expect("events serialize to JSON");
+                Ok((body, "application/json"))
+            }
+        }
+    }
+
+    /// POSTs `body` until it is accepted, fails for good or `shutdown` is
+    /// cancelled. Returns the outcome, the number of attempts, the last
+    /// response status and the last error.
+    async fn post(
+        &self,
+        webhook: &Webhook,
+        body: Vec<u8>,
+        content_type: &str,
+        shutdown: &CancellationToken,
+    ) -> (DeliveryStatus, u32, Option<u16>, Option<String>) {
+        let signature = sign(&webhook.secret, &body);
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            let result = self
+                .client
+                .post(&webhook.url)
+                .header(reqwest::header::CONTENT_TYPE, content_type)
+                .header(WEBHOOK_ID_HEADER, webhook.id)
+                .header(SIGNATURE_HEADER, &signature)
+                .body(body.clone())
+                .send()
+                .await;
+            let (status, error) = match result {
+                Ok(response) if response.status().is_success() => {
+                    WEBHOOK_ATTEMPTS.with_label_values(&["ok"]).inc();
+                    return (
+                        DeliveryStatus::Delivered,
+                        attempts,
+                        Some(response.status().as_u16()),
+                        None,
+                    );
+                }
+                Ok(response) => (
+                    Some(response.status()),
+                    format!("endpoint answered {}", response.status()),
+                ),
+                Err(e) => (e.status(), e.to_string()),
+            };
+            WEBHOOK_ATTEMPTS.with_label_values(&["error"]).inc();
+            let response_status = status.map(|status| status.as_u16());
+            if !is_retryable(status) || attempts >= self.config.max_attempts {
+                return (
+                    DeliveryStatus::Failed,
+                    attempts,
+                    response_status,
+                    Some(error),
+                );
+            }
+            let backoff = self.config.backoff(attempts);
+            warn!(
+                webhook.id = webhook.id,
+                attempt = attempts,
+                error = %error,
+                "Webhook delivery failed, retrying in {backoff:?}"
+            );
+            tokio::select! {
+                _ = sleep(backoff) => {}
+                _ = shutdown.cancelled() => {
+                    return (DeliveryStatus::Failed, attempts, response_status, Some(error));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{
+        rule::NotificationRuleInput,
+        transaction::Transaction,
+        webhook::{EventKind, WebhookFilter},
+        wei::Wei,
+    };
+    use axum::{
+        body::Bytes, extract::State, http::HeaderMap, http::StatusCode as AxumStatus,
+        routing::post, Router, Server,
+    };
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const SECRET: &str = "s3cret";
+
+    fn transaction(value: u64) -> Transaction {
+        Transaction {
+            chain_id: 1,
+            hash: format!("0xtx{value}"),
+            block_hash: "0xblock".to_string(),
+            from: "0xAlice".to_string(),
+            to: "0xbob".to_string(),
+            value: value.into(),
+            block_number: 1,
+            nonce: 0,
+            gas: 21000,
+            gas_price: None,
+            max_fee_per_gas: None,
+            max_priority_fee_per_gas: None,
+            input: "0x".to_string(),
+            transaction_type: None,
+            transaction_index: None,
+        }
+    }
+
+    /// Local stand-in for a webhook endpoint. It answers 500 to the first
+    /// `failures` requests and 200 afterwards, and rejects bad signatures.
+    async fn stand_in(failures: usize) -> (String, Arc<AtomicUsize>) {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let app = Router::new()
+            .route(
+                "/hook",
+                post(
+                    move |State(requests): State<Arc<AtomicUsize>>,
+                          headers: HeaderMap,
+                          body: Bytes| async move {
+                        let signature = headers[SIGNATURE_HEADER].to_str().unwrap();
+                        if signature != sign(SECRET, &body) {
+                            return AxumStatus::UNAUTHORIZED;
+                        }
+                        if requests.fetch_add(1, Ordering::SeqCst) < failures {
+                            AxumStatus::INTERNAL_SERVER_ERROR
+                        } else {
+                            AxumStatus::OK
+                        }
+                    },
+                ),
+            )
+            .with_state(requests.clone());
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            Server::from_tcp(listener)
+                .unwrap()
+                .serve(app.into_make_service()),
+        );
+        (format!("http://{addr}/hook"), requests)
+    }
+
+    fn dispatcher(storage: Arc<RwLock<Storage>>) -> WebhookDispatcher {
+        let config = WebhookConfig {
+            max_attempts: 3,
+            initial_backoff_ms: 1,
+            max_backoff_ms: 5,
+            ..Default::default()
+        };
+        WebhookDispatcher::new(&config, &[], templates(), storage).unwrap()
+    }
+
+    fn templates() -> Arc<Templates> {
+        let short = crate::config::TemplateConfig {
+            source: "{{value}} wei from {{from}}".to_string(),
+            content_type: "text/plain".to_string(),
+        };
+        let templates = [("short".to_string(), short)].into();
+        Arc::new(Templates::new(&templates, &BTreeMap::new()).unwrap())
+    }
+
+    type Captured = Arc<std::sync::Mutex<Vec<(String, String)>>>;
+
+    /// Stand-in recording the content type and body of every request.
+    async fn capture() -> (String, Captured) {
+        let captured = Captured::default();
+        let app = Router::new()
+            .route(
+                "/capture",
+                post(
+                    |State(captured): State<Captured>, headers: HeaderMap, body: String| async move {
+                        let content_type = headers["content-type"].to_str().unwrap().to_string();
+                        captured.lock().unwrap().push((content_type, body));
+                    },
+                ),
+            )
+            .with_state(captured.clone());
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/capture", listener.local_addr().unwrap());
+        tokio::spawn(
+            Server::from_tcp(listener)
+                .unwrap()
+                .serve(app.into_make_service()),
+        );
+        (url, captured)
+    }
+
+    async fn dispatch(storage: Arc<RwLock<Storage>>, events: Vec<WebhookEvent>) {
+        let (sender, receiver) = mpsc::channel(EVENT_QUEUE_SIZE);
+        for event in events {
+            sender.send(event).await.unwrap();
+        }
+        drop(sender);
+        dispatcher(storage)
+            .run(receiver, CancellationToken::new())
+            .await;
+    }
+
+    #[test]
+    fn test_filter_matches() {
+        let filter = WebhookFilter {
+            address: Some("0xalice".to_string()),
+            min_value: Some(Wei(100)),
+            event_kinds: vec![EventKind::Transaction],
+            chain_id: None,
+        };
+        assert!(filter.matches(&WebhookEvent::Transaction(transaction(100))));
+        assert!(!filter.matches(&WebhookEvent::Transaction(transaction(99))));
+
+        let mut other = transaction(100);
+        other.from = "0xcarol".to_string();
+        assert!(!filter.matches(&WebhookEvent::Transaction(other)));
+
+        // Thresholds and values past 64 bits.
+        let hundred_ether = Wei(100_000_000_000_000_000_000);
+        let filter = WebhookFilter {
+            min_value: Some(hundred_ether),
+            ..filter
+        };
+        let mut whale = transaction(0);
+        whale.value = hundred_ether;
+        assert!(filter.matches(&WebhookEvent::Transaction(whale.clone())));
+        whale.value.0 -= 1;
+        assert!(!filter.matches(&WebhookEvent::Transaction(whale)));
+    }
+
+    #[tokio::test]
+    async fn test_delivers_signed_events_with_retries() {
+        let (url, requests) = stand_in(2).await;
+        let storage = Arc::new(RwLock::new(Storage::new()));
+        let webhook = storage
+            .write()
+            .await
+            .add_webhook(
+                url,
+                SECRET.to_string(),
+                Some(WebhookFilter {
+                    min_value: Some(Wei(50)),
+                    ..Default::default()
+                }),
+                None,
+            )
+            .unwrap();
+
+        dispatch(
+            storage.clone(),
+            vec![
+                WebhookEvent::Transaction(transaction(10)),
+                WebhookEvent::Transaction(transaction(100)),
+            ],
+        )
+        .await;
+
+        // Only the second transaction matches; it succeeds on the third try.
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+        let storage = storage.read().await;
+        assert_eq!(storage.deliveries.len(), 1);
+        let delivery = &storage.deliveries[0];
+        assert_eq!(delivery.webhook_id, webhook.id);
+        assert_eq!(delivery.hash, "0xtx100");
+        assert_eq!(delivery.status, DeliveryStatus::Delivered);
+        assert_eq!(delivery.attempts, 3);
+        assert_eq!(delivery.response_status, Some(200));
+    }
+
+    #[tokio::test]
+    async fn test_gives_up_after_max_attempts() {
+        let (url, requests) = stand_in(usize::MAX).await;
+        let storage = Arc::new(RwLock::new(Storage::new()));
+        storage
+            .write()
+            .await
+            .add_webhook(
+                url,
+                SECRET.to_string(),
+                Some(WebhookFilter::default()),
+                None,
+            )
+            .unwrap();
+
+        dispatch(
+            storage.clone(),
+            vec![WebhookEvent::Transaction(transaction(1))],
+        )
+        .await;
+
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+        let delivery = storage.read().await.deliveries[0].clone();
+        assert_eq!(delivery.status, DeliveryStatus::Failed);
+        assert_eq!(delivery.response_status, Some(500));
+    }
+
+    #[tokio::test]
+    async fn test_bad_signature_is_not_retried() {
+        let (url, requests) = stand_in(0).await;
+        let storage = Arc::new(RwLock::new(Storage::new()));
+        storage
+            .write()
+            .await
+            .add_webhook(
+                url,
+                "wrong".to_string(),
+                Some(WebhookFilter::default()),
+                None,
+            )
+            .unwrap();
+
+        dispatch(
+            storage.clone(),
+            vec![WebhookEvent::Transaction(transaction(1))],
+        )
+        .await;
+
+        assert_eq!(requests.load(Ordering::SeqCst), 0);
+        let delivery = storage.read().await.deliveries[0].clone();
+        assert_eq!(delivery.attempts, 1);
+        assert_eq!(delivery.response_status, Some(401));
+    }
+
+    #[tokio::test]
+    async fn test_delivers_once_per_webhook_for_matching_rules() {
+        let (url, requests) = stand_in(0).await;
+        let storage = Arc::new(RwLock::new(Storage::new()));
+        {
+            let mut storage = storage.write().await;
+            let webhook = storage
+                .add_webhook(url, SECRET.to_string(), None, None)
+                .unwrap();
+            for (name, min_value) in [("small", 1), ("large", 1000), ("any", 0)] {
+                storage
+                    .add_rule(NotificationRuleInput {
+                        name: name.to_string(),
+                        watched_addresses: vec!["0xalice".to_string()],
+                        contracts: vec![],
+                        min_value: Some(Wei(min_value)),
+                        event_kinds: vec![],
+                        chain_id: None,
+                        condition: None,
+                        digest_window_secs: None,
+                        channels: vec![webhook.id],
+                        notify: vec![],
+                    })
+                    .unwrap();
+            }
+            storage.set_rule_paused(3, true).unwrap();
+        }
+
+        dispatch(
+            storage.clone(),
+            vec![WebhookEvent::Transaction(transaction(10))],
+        )
+        .await;
+
+        // Only the unpaused rule with a low enough threshold matches.
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+        assert_eq!(storage.read().await.deliveries[0].rule_ids, vec![1]);
+    }
+
+    #[tokio::test]
+    async fn test_notifies_each_event_once_per_rule() {
+        let (url, requests) = stand_in(0).await;
+        let storage = Arc::new(RwLock::new(Storage::new()));
+        let rule = |name: &str, channels| NotificationRuleInput {
+            name: name.to_string(),
+            watched_addresses: vec!["0xalice".to_string()],
+            contracts: vec![],
+            min_value: None,
+            event_kinds: vec![],
+            chain_id: None,
+            condition: None,
+            digest_window_secs: None,
+            channels,
+            notify: vec![],
+        };
+        {
+            let mut storage = storage.write().await;
+            let webhook = storage
+                .add_webhook(url.clone(), SECRET.to_string(), None, None)
+                .unwrap();
+            storage.add_rule(rule("whale", vec![webhook.id])).unwrap();
+            storage
+                .add_webhook(
+                    url,
+                    SECRET.to_string(),
+                    Some(WebhookFilter::default()),
+                    None,
+                )
+                .unwrap();
+        }
+
+        // The crawler emits the transaction again, and the consumer reads it
+        // again after a restart.
+        let event = || WebhookEvent::Transaction(transaction(7));
+        dispatch(storage.clone(), vec![event(), event()]).await;
+        dispatch(storage.clone(), vec![event()]).await;
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+
+        // A rule added since is still notified.
+        {
+            let mut storage = storage.write().await;
+            storage.add_rule(rule("alice", vec![1])).unwrap();
+        }
+        dispatch(storage.clone(), vec![event()]).await;
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+        assert_eq!(storage.read().await.deliveries[2].rule_ids, vec![2]);
+    }
+
+    #[tokio::test]
+    async fn test_does_not_notify_unsaved_notifications() {
+        let (url, requests) = stand_in(0).await;
+        let path = std::env::temp_dir().join(format!(
+            "consumer-notified-state-{}.json",
+            std::process::id()
+        ));
+        let storage = Arc::new(RwLock::new(Storage::with_state_file(&path).unwrap()));
+        let notified_file = {
+            let mut storage = storage.write().await;
+            storage
+                .add_webhook(
+                    url,
+                    SECRET.to_string(),
+                    Some(WebhookFilter::default()),
+                    None,
+                )
+                .unwrap();
+            storage.notified_file().unwrap()
+        };
+
+        // A directory in place of the log file makes the append fail.
+        std::fs::create_dir(&notified_file).unwrap();
+        let event = || WebhookEvent::Transaction(transaction(7));
+        dispatch(storage.clone(), vec![event()]).await;
+        assert_eq!(requests.load(Ordering::SeqCst), 0);
+
+        // Once the log can be written, the event comes again and is sent.
+        std::fs::remove_dir(&notified_file).unwrap();
+        dispatch(storage.clone(), vec![event()]).await;
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+        for file in [path, notified_file] {
+            std::fs::remove_file(file).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sends_digests() {
+        let (url, captured) = capture().await;
+        let storage = Arc::new(RwLock::new(Storage::new()));
+        {
+            let mut storage = storage.write().await;
+            let webhook = storage
+                .add_webhook(url, SECRET.to_string(), None, None)
+                .unwrap();
+            storage
+                .add_rule(NotificationRuleInput {
+                    name: "alice".to_string(),
+                    watched_addresses: vec!["0xalice".to_string()],
+                    contracts: vec![],
+                    min_value: None,
+                    event_kinds: vec![],
+                    chain_id: None,
+                    condition: None,
+                    digest_window_secs: Some(60),
+                    channels: vec![webhook.id],
+                    notify: vec![],
+                })
+                .unwrap();
+        }
+
+        let mut second = transaction(5);
+        second.hash = "0xother".to_string();
+        dispatch(
+            storage.clone(),
+            vec![
+                WebhookEvent::Transaction(transaction(7)),
+                WebhookEvent::Transaction(second),
+            ],
+        )
+        .await;
+        assert!(captured.lock().unwrap().is_empty());
+
+        let mut deliveries = JoinSet::new();
+        let dispatcher = dispatcher(storage.clone());
+        let shutdown = CancellationToken::new();
+        dispatcher
+            .send_digests(unix_now(), &mut deliveries, &shutdown)
+            .await;
+        assert!(deliveries.is_empty());
+        dispatcher
+            .send_digests(unix_now() + 60, &mut deliveries, &shutdown)
+            .await;
+        while deliveries.join_next().await.is_some() {}
+        assert_eq!(
+            storage.read().await.deliveries[0].event_kind,
+            EventKind::Digest
+        );
+
+        let captured = captured.lock().unwrap();
+        assert_eq!(captured.len(), 1);
+        let body: serde_json::Value = serde_json::from_str(&captured[0].1).unwrap();
+        assert_eq!(body["event"], "digest");
+        assert_eq!(body["ruleIds"], serde_json::json!([1]));
+        assert_eq!(body["data"]["transactions"], 2);
+        assert_eq!(body["data"]["total_value"], "12");
+        assert_eq!(body["data"]["top_counterparties"][0]["address"], "0xbob");
+    }
+
+    #[tokio::test]
+    async fn test_saves_digests_when_stopping() {
+        let path = std::env::temp_dir().join(format!(
+            "consumer-digests-state-{}.json",
+            std::process::id()
+        ));
+        let storage = Arc::new(RwLock::new(Storage::with_state_file(&path).unwrap()));
+        {
+            let mut storage = storage.write().await;
+            storage.notification_channels.insert("ops".to_string());
+            storage
+                .add_rule(NotificationRuleInput {
+                    name: "alice".to_string(),
+                    watched_addresses: vec!["0xalice".to_string()],
+                    contracts: vec![],
+                    min_value: None,
+                    event_kinds: vec![],
+                    chain_id: None,
+                    condition: None,
+                    digest_window_secs: Some(3600),
+                    channels: vec![],
+                    notify: vec!["ops".to_string()],
+                })
+                .unwrap();
+        }
+        // Stops right after the event, possibly before the digest timer
+        // fires again.
+        dispatch(
+            storage.clone(),
+            vec![WebhookEvent::Transaction(transaction(7))],
+        )
+        .await;
+
+        let mut restored = Storage::with_state_file(&path).unwrap();
+        let due = restored.digests.take_due(u64::MAX);
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].transactions, 1);
+
+        let storage = storage.read().await;
+        for file in [
+            Some(path.clone()),
+            storage.notified_file(),
+            storage.digests_file(),
+        ]
+        .into_iter()
+        .flatten()
+        {
+            std::fs::remove_file(file).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_renders_webhook_template() {
+        let (url, captured) = capture().await;
+        let storage = Arc::new(RwLock::new(Storage::new()));
+        {
+            let mut storage = storage.write().await;
+            storage.templates = templates().names();
+            let err = storage
+                .add_webhook(
+                    url.clone(),
+                    SECRET.to_string(),
+                    None,
+                    Some("long".to_string()),
+                )
+                .unwrap_err();
+            assert!(err.to_string().contains("No template named"), "{err}");
+            storage
+                .add_webhook(
+                    url,
+                    SECRET.to_string(),
+                    Some(WebhookFilter::default()),
+                    Some("short".to_string()),
+                )
+                .unwrap();
+        }
+
+        dispatch(
+            storage.clone(),
+            vec![WebhookEvent::Transaction(transaction(7))],
+        )
+        .await;
+
+        assert_eq!(
+            *captured.lock().unwrap(),
+            vec![("text/plain".to_string(), "7 wei from 0xAlice".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_notifies_rule_channels() {
+        let (url, captured) = capture().await;
+        let channels = [ChannelConfig {
+            name: "ops".to_string(),
+            subject: "{{rule}}".to_string(),
+            template: "short".to_string(),
+            email: None,
+            chat: Some(crate::config::ChatConfig {
+                url,
+                format: crate::config::ChatFormat::Slack,
+            }),
+            telegram: None,
+        }];
+        let storage = Arc::new(RwLock::new(Storage::new()));
+        {
+            let mut storage = storage.write().await;
+            storage.notification_channels.insert("ops".to_string());
+            for name in ["whale", "alice"] {
+                storage
+                    .add_rule(NotificationRuleInput {
+                        name: name.to_string(),
+                        watched_addresses: vec!["0xalice".to_string()],
+                        contracts: vec![],
+                        min_value: None,
+                        event_kinds: vec![],
+                        chain_id: None,
+                        condition: None,
+                        digest_window_secs: None,
+                        channels: vec![],
+                        notify: vec!["ops".to_string()],
+                    })
+                    .unwrap();
+            }
+        }
+
+        let (sender, receiver) = mpsc::channel(EVENT_QUEUE_SIZE);
+        sender
+            .send(WebhookEvent::Transaction(transaction(7)))
+            .await
+            .unwrap();
+        drop(sender);
+        WebhookDispatcher::new(&WebhookConfig::default(), &channels, templates(), storage)
+            .unwrap()
+            .run(receiver, CancellationToken::new())
+            .await;
+
+        // One message for both rules.
+        let captured = captured.lock().unwrap();
+        assert_eq!(captured.len(), 1);
+        let body: serde_json::Value = serde_json::from_str(&captured[0].1).unwrap();
+        assert_eq!(body["text"], "*whale, alice*\n7 wei from 0xAlice");
+    }
+}
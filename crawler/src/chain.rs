@@ -0,0 +1,138 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Deserializer};
+use std::env::var;
+
+/// Settings for one chain crawled by this process.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChainConfig {
+    pub name: String,
+    /// RPC endpoints, each `http(s)://`, `ws(s)://` or `ipc://<socket path>`.
+    /// A single URL or a list; requests are spread over all healthy ones.
+    #[serde(alias = "http_provider", deserialize_with = "one_or_many")]
+    pub provider_url: Vec<String>,
+    pub from_block: u64,
+    #[serde(default)]
+    pub topic_prefix: String,
+}
+
+fn one_or_many<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(url) => vec![url],
+        OneOrMany::Many(urls) => urls,
+    })
+}
+
+fn split_urls(urls: &str) -> Vec<String> {
+    urls.split(',')
+        .map(str::trim)
+        .filter(|url| !url.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// Reads the chains to crawl from the environment, if any are set there.
+///
+/// When `CHAINS` is set (e.g. `CHAINS=mainnet,base`), every listed chain is
+/// configured through `<NAME>_HTTP_PROVIDER`, `<NAME>_FROM_BLOCK` and the
+/// optional `<NAME>_TOPIC_PREFIX`. Otherwise, if `HTTP_PROVIDER` is set, a
+/// single chain is built from it and `FROM_BLOCK` with no topic prefix.
+/// Provider variables may hold several comma separated URLs.
+pub fn load_env_chain_configs() -> Result<Option<Vec<ChainConfig>>> {
+    if let Ok(chains) = var("CHAINS") {
+        return chains
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(load_chain_config)
+            .collect::<Result<_>>()
+            .map(Some);
+    }
+    match var("HTTP_PROVIDER") {
+        Ok(http_provider) => Ok(Some(vec![ChainConfig {
+            name: "default".to_string(),
+            provider_url: split_urls(&http_provider),
+            from_block: parse_from_block("FROM_BLOCK")?,
+            topic_prefix: String::new(),
+        }])),
+        Err(_) => Ok(None),
+    }
+}
+
+fn load_chain_config(name: &str) -> Result<ChainConfig> {
+    let prefix = name.to_uppercase();
+    let http_provider_key = format!("{prefix}_HTTP_PROVIDER");
+
+    Ok(ChainConfig {
+        name: name.to_string(),
+        provider_url: split_urls(
+            &var(&http_provider_key).with_context(|| format!("{http_provider_key} not set"))?,
+        ),
+        from_block: parse_from_block(&format!("{prefix}_FROM_BLOCK"))?,
+        topic_prefix: var(format!("{prefix}_TOPIC_PREFIX")).unwrap_or_default(),
+    })
+}
+
+fn parse_from_block(key: &str) -> Result<u64> {
+    var(key)
+        .with_context(|| format!("{key} not set"))?
+        .parse::<u64>()
+        .with_context(|| format!("Invalid {key}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn test_load_chain_configs() {
+        env::set_var("CHAINS", "testa, testb");
+        env::set_var("TESTA_HTTP_PROVIDER", "http://localhost:8545");
+        env::set_var("TESTA_FROM_BLOCK", "10");
+        env::set_var("TESTA_TOPIC_PREFIX", "a_");
+        env::set_var(
+            "TESTB_HTTP_PROVIDER",
+            "http://localhost:8546, ws://localhost:8547",
+        );
+        env::set_var("TESTB_FROM_BLOCK", "20");
+
+        let chains = load_env_chain_configs().unwrap().unwrap();
+        env::remove_var("CHAINS");
+
+        assert_eq!(
+            chains,
+            vec![
+                ChainConfig {
+                    name: "testa".to_string(),
+                    provider_url: vec!["http://localhost:8545".to_string()],
+                    from_block: 10,
+                    topic_prefix: "a_".to_string(),
+                },
+                ChainConfig {
+                    name: "testb".to_string(),
+                    provider_url: vec![
+                        "http://localhost:8546".to_string(),
+                        "ws://localhost:8547".to_string(),
+                    ],
+                    from_block: 20,
+                    topic_prefix: String::new(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_load_chain_config_fail_missing_env() {
+        env::set_var("TESTC_HTTP_PROVIDER", "http://localhost:8545");
+        env::remove_var("TESTC_FROM_BLOCK");
+        let err = load_chain_config("testc").unwrap_err();
+        assert_eq!(err.to_string(), "TESTC_FROM_BLOCK not set");
+    }
+}
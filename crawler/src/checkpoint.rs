@@ -0,0 +1,158 @@
+use crate::metrics::GAP_BLOCKS;
+use anyhow::{Context, Result};
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Parses the JSON file at `path`, or returns the default when it does not
+/// exist yet.
+fn read_json<T: DeserializeOwned + Default>(path: &Path) -> Result<T> {
+    match fs::read_to_string(path) {
+        Ok(content) => serde_json::from_str(&content)
+            .with_context(|| format!("Invalid file {}", path.display())),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(T::default()),
+        Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    }
+}
+
+/// Writes `value` next to `path` and renames it over it, so a crash never
+/// leaves a truncated file behind.
+fn write_json(path: &Path, value: &impl Serialize) -> Result<()> {
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, serde_json::to_vec(value)?)
+        .with_context(|| format!("Failed to write {}", tmp_path.display()))?;
+    fs::rename(&tmp_path, path).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Last fully processed block of every chain, kept in a JSON file so that a
+/// restarted crawler resumes after it instead of at `from_block`.
+pub struct Checkpoint {
+    path: PathBuf,
+    blocks: Mutex<BTreeMap<u64, u64>>,
+}
+
+impl Checkpoint {
+    /// Reads the checkpoint file, starting empty when it does not exist yet.
+    pub fn load(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let blocks = read_json(&path).context("Failed to load checkpoint")?;
+        Ok(Self {
+            path,
+            blocks: Mutex::new(blocks),
+        })
+    }
+
+    pub fn get(&self, chain_id: u64) -> Option<u64> {
+        self.blocks.lock().unwrap().get(&chain_id).copied()
+    }
+
+    /// Records `block_number` for `chain_id` and rewrites the file.
+    pub fn save(&self, chain_id: u64, block_number: u64) -> Result<()> {
+        let mut blocks = self.blocks.lock().unwrap();
+        blocks.insert(chain_id, block_number);
+        write_json(&self.path, &*blocks)
+    }
+}
+
+/// Blocks of every chain that could not be crawled even after retrying,
+/// kept in a JSON file until a `--recrawl-gaps` run fills them in.
+pub struct GapList {
+    path: PathBuf,
+    gaps: Mutex<BTreeMap<u64, BTreeSet<u64>>>,
+}
+
+impl GapList {
+    /// Reads the gap file, starting empty when it does not exist yet.
+    pub fn load(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let gaps: BTreeMap<u64, BTreeSet<u64>> =
+            read_json(&path).context("Failed to load gap list")?;
+        for (chain_id, blocks) in &gaps {
+            GAP_BLOCKS
+                .with_label_values(&[&chain_id.to_string()])
+                .set(blocks.len() as i64);
+        }
+        Ok(Self {
+            path,
+            gaps: Mutex::new(gaps),
+        })
+    }
+
+    /// Missing blocks of `chain_id`, lowest first.
+    pub fn get(&self, chain_id: u64) -> Vec<u64> {
+        self.gaps
+            .lock()
+            .unwrap()
+            .get(&chain_id)
+            .map(|blocks| blocks.iter().copied().collect())
+            .unwrap_or_default()
+    }
+
+    pub fn add(&self, chain_id: u64, block_number: u64) -> Result<()> {
+        self.update(chain_id, |blocks| blocks.insert(block_number))
+    }
+
+    pub fn remove(&self, chain_id: u64, block_number: u64) -> Result<()> {
+        self.update(chain_id, |blocks| blocks.remove(&block_number))
+    }
+
+    /// Applies `change` to the gaps of `chain_id` and rewrites the file if
+    /// it reports a change.
+    fn update(&self, chain_id: u64, change: impl FnOnce(&mut BTreeSet<u64>) -> bool) -> Result<()> {
+        let mut gaps = self.gaps.lock().unwrap();
+        let blocks = gaps.entry(chain_id).or_default();
+        if !change(blocks) {
+            return Ok(());
+        }
+        GAP_BLOCKS
+            .with_label_values(&[&chain_id.to_string()])
+            .set(blocks.len() as i64);
+        if blocks.is_empty() {
+            gaps.remove(&chain_id);
+        }
+        write_json(&self.path, &*gaps)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn test_checkpoint_round_trip() {
+        let path = env::temp_dir().join(format!("crawler-checkpoint-{}.json", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let checkpoint = Checkpoint::load(&path).unwrap();
+        assert_eq!(checkpoint.get(1), None);
+        checkpoint.save(1, 100).unwrap();
+        checkpoint.save(8453, 7).unwrap();
+        checkpoint.save(1, 101).unwrap();
+
+        let checkpoint = Checkpoint::load(&path).unwrap();
+        assert_eq!(checkpoint.get(1), Some(101));
+        assert_eq!(checkpoint.get(8453), Some(7));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_gap_list_round_trip() {
+        let path = env::temp_dir().join(format!("crawler-gaps-{}.json", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let gaps = GapList::load(&path).unwrap();
+        gaps.add(1, 30).unwrap();
+        gaps.add(1, 10).unwrap();
+        gaps.add(8453, 7).unwrap();
+        gaps.remove(8453, 7).unwrap();
+
+        let gaps = GapList::load(&path).unwrap();
+        assert_eq!(gaps.get(1), vec![10, 30]);
+        assert!(gaps.get(8453).is_empty());
+        fs::remove_file(&path).unwrap();
+    }
+}
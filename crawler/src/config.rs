@@ -0,0 +1,535 @@
+use crate::chain::{load_env_chain_configs, ChainConfig};
+use crate::provider::Endpoint;
+use anyhow::{bail, Context, Result};
+use clap::Parser;
+use filter_expr::{quote, AddressBooks, Filter};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use tokio::time::Duration;
+
+const DEFAULT_CONFIG_FILE: &str = "crawler.toml";
+
+/// Command line flags. Most can also be set through the environment variable
+/// named in their help, and both take precedence over the config file.
+#[derive(Parser, Debug, Default)]
+pub struct Args {
+    /// TOML config file, `crawler.toml` is read when present
+    #[arg(long, env = "CONFIG_FILE")]
+    pub config: Option<PathBuf>,
+
+    /// Publish only transactions matching this expression
+    #[arg(long, env = "TX_FILTER")]
+    pub tx_filter: Option<Filter>,
+
+    /// Publish only blocks matching this expression
+    #[arg(long, env = "BLOCK_FILTER")]
+    pub block_filter: Option<Filter>,
+
+    /// Publish only transactions whose hash contains this, on top of
+    /// `--tx-filter`
+    #[arg(long)]
+    pub tx_hash_filter: Option<String>,
+
+    /// Publish only blocks whose hash contains this, on top of
+    /// `--block-filter`
+    #[arg(long)]
+    pub block_hash_filter: Option<String>,
+
+    /// Comma separated Kafka brokers
+    #[arg(long, env = "KAFKA_BROKER_HOST", value_delimiter = ',')]
+    pub kafka_broker_host: Option<Vec<String>>,
+
+    #[arg(long, env = "KAFKA_TX_TOPIC")]
+    pub kafka_tx_topic: Option<String>,
+
+    #[arg(long, env = "KAFKA_BLOCK_TOPIC")]
+    pub kafka_block_topic: Option<String>,
+
+    #[arg(long, env = "KAFKA_PENDING_TOPIC")]
+    pub kafka_pending_topic: Option<String>,
+
+    #[arg(long, env = "HEALTH_ADDR")]
+    pub health_addr: Option<SocketAddr>,
+
+    #[arg(long, env = "MAX_RPC_SILENCE_SECS")]
+    pub max_rpc_silence_secs: Option<u64>,
+
+    #[arg(long, env = "MAX_HEAD_DISTANCE")]
+    pub max_head_distance: Option<u64>,
+
+    /// Seconds between health checks of every RPC endpoint
+    #[arg(long, env = "RPC_HEALTH_CHECK_SECS")]
+    pub rpc_health_check_secs: Option<u64>,
+
+    /// Blocks an RPC endpoint may lag behind the highest head
+    #[arg(long, env = "RPC_MAX_LAG")]
+    pub rpc_max_lag: Option<u64>,
+
+    /// Calls per second allowed to each RPC endpoint
+    #[arg(long, env = "REQUESTS_PER_SECOND")]
+    pub requests_per_second: Option<f64>,
+
+    /// Compute units per second allowed to each RPC endpoint
+    #[arg(long, env = "COMPUTE_UNITS_PER_SECOND")]
+    pub compute_units_per_second: Option<f64>,
+
+    /// JSON file recording the last processed block of every chain
+    #[arg(long, env = "CHECKPOINT_FILE")]
+    pub checkpoint_file: Option<PathBuf>,
+
+    /// JSON file listing blocks that failed to crawl after every retry
+    #[arg(long, env = "GAP_FILE")]
+    pub gap_file: Option<PathBuf>,
+
+    /// Attempts per RPC call before a block is recorded as a gap
+    #[arg(long, env = "RETRY_MAX_ATTEMPTS")]
+    pub retry_max_attempts: Option<u32>,
+
+    /// Crawl only the blocks in the gap file, then exit
+    #[arg(long, env = "RECRAWL_GAPS")]
+    pub recrawl_gaps: bool,
+
+    /// Also publish pending transactions until stopped
+    #[arg(long, env = "MEMPOOL")]
+    pub mempool: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KafkaConfig {
+    pub hosts: Vec<String>,
+    /// Topic names before each chain's `topic_prefix` is applied.
+    pub tx_topic: String,
+    pub block_topic: String,
+    /// Pending transactions and their later `mined`, `replaced` or
+    /// `dropped` events.
+    pub pending_topic: String,
+}
+
+impl Default for KafkaConfig {
+    fn default() -> Self {
+        Self {
+            hosts: Vec::new(),
+            tx_topic: "tx".to_string(),
+            block_topic: "block".to_string(),
+            pending_topic: "pending".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HealthConfig {
+    pub addr: SocketAddr,
+    /// Liveness fails once a chain has had no successful RPC call for this long.
+    pub max_rpc_silence_secs: u64,
+    /// Readiness fails while a chain is further than this behind the head.
+    pub max_head_distance: u64,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self {
+            addr: SocketAddr::from(([0, 0, 0, 0], 8080)),
+            max_rpc_silence_secs: 120,
+            max_head_distance: 100,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RpcConfig {
+    /// Seconds between `eth_blockNumber` probes of every endpoint.
+    pub health_check_secs: u64,
+    /// An endpoint further than this behind the highest head of its chain
+    /// is taken out of rotation until it catches up.
+    pub max_lag: u64,
+    /// A call not answered within this many seconds counts as failed and is
+    /// retried on the next endpoint.
+    pub request_timeout_secs: u64,
+    /// Calls per second sent to each endpoint while it does not complain.
+    pub requests_per_second: f64,
+    /// Compute unit budget per second of each endpoint, for providers that
+    /// bill by method. Only requests are counted when unset.
+    pub compute_units_per_second: Option<f64>,
+}
+
+impl Default for RpcConfig {
+    fn default() -> Self {
+        Self {
+            health_check_secs: 10,
+            max_lag: 5,
+            request_timeout_secs: 30,
+            requests_per_second: 25.0,
+            compute_units_per_second: None,
+        }
+    }
+}
+
+/// Expressions deciding which blocks and transactions are published, see
+/// the `filter-expr` crate. Everything is published when unset.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FilterConfig {
+    pub transaction: Option<Filter>,
+    pub block: Option<Filter>,
+    /// Named address sets, referenced as `@name` in the expressions.
+    pub address_books: BTreeMap<String, Vec<String>>,
+}
+
+/// `filter` narrowed down to records whose hash contains `hash`.
+/// Fails when the extra parentheses push `filter` past the nesting limit.
+fn and_hash_contains(filter: Option<&Filter>, hash: &str) -> Result<Filter> {
+    let hash_filter = format!("hash contains {}", quote(hash));
+    let source = match filter {
+        Some(filter) => format!("({filter}) and {hash_filter}"),
+        None => hash_filter,
+    };
+    Ok(source.parse()?)
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetryConfig {
+    /// Attempts per block or transaction fetch, the first one included.
+    pub max_attempts: u32,
+    /// Wait before the second attempt, doubled for every further one.
+    pub initial_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_backoff_ms: 500,
+            max_backoff_ms: 30_000,
+        }
+    }
+}
+
+impl RetryConfig {
+    /// Wait before retrying after `failures` failed attempts.
+    pub fn backoff(&self, failures: u32) -> Duration {
+        let factor = 2u64.saturating_pow(failures.saturating_sub(1));
+        Duration::from_millis(
+            self.initial_backoff_ms
+                .saturating_mul(factor)
+                .min(self.max_backoff_ms),
+        )
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MempoolConfig {
+    pub enabled: bool,
+    /// How often `txpool_content` is polled and published pending
+    /// transactions are looked up again.
+    pub poll_interval_ms: u64,
+    /// Pending transactions followed at once per chain; newer ones are not
+    /// published while the limit is reached.
+    pub max_tracked: usize,
+    /// A transaction still pending after this long is reported dropped.
+    pub max_pending_secs: u64,
+}
+
+impl Default for MempoolConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            poll_interval_ms: 1000,
+            max_tracked: 10_000,
+            max_pending_secs: 3600,
+        }
+    }
+}
+
+/// Crawler settings, merged from the config file, the environment and the
+/// command line in increasing order of precedence.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Where progress is recorded so a restart resumes after the last
+    /// processed block. Every run starts at `from_block` when unset.
+    pub checkpoint_file: Option<PathBuf>,
+    /// Where blocks that failed after every retry are listed for a later
+    /// `--recrawl-gaps` run. They are only logged when unset.
+    pub gap_file: Option<PathBuf>,
+    pub kafka: KafkaConfig,
+    pub health: HealthConfig,
+    pub rpc: RpcConfig,
+    pub retry: RetryConfig,
+    pub mempool: MempoolConfig,
+    pub filter: FilterConfig,
+    pub chains: Vec<ChainConfig>,
+}
+
+impl Config {
+    /// Builds and validates the configuration, so that every mistake is
+    /// reported before the crawler connects to anything.
+    pub fn load(args: &Args) -> Result<Self> {
+        let mut config = match &args.config {
+            Some(path) => Self::from_file(path)?,
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => {
+                Self::from_file(Path::new(DEFAULT_CONFIG_FILE))?
+            }
+            None => Self::default(),
+        };
+        if let Some(chains) = load_env_chain_configs()? {
+            config.chains = chains;
+        }
+        config.apply_args(args)?;
+        config.validate()?;
+        if args.recrawl_gaps && config.gap_file.is_none() {
+            bail!("--recrawl-gaps needs gap_file, GAP_FILE or --gap-file");
+        }
+        Ok(config)
+    }
+
+    fn from_file(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+        toml::from_str(&content).with_context(|| format!("Invalid config file {}", path.display()))
+    }
+
+    fn apply_args(&mut self, args: &Args) -> Result<()> {
+        if let Some(hosts) = &args.kafka_broker_host {
+            self.kafka.hosts = hosts.clone();
+        }
+        if let Some(topic) = &args.kafka_tx_topic {
+            self.kafka.tx_topic = topic.clone();
+        }
+        if let Some(topic) = &args.kafka_block_topic {
+            self.kafka.block_topic = topic.clone();
+        }
+        if let Some(topic) = &args.kafka_pending_topic {
+            self.kafka.pending_topic = topic.clone();
+        }
+        if args.mempool {
+            self.mempool.enabled = true;
+        }
+        if let Some(addr) = args.health_addr {
+            self.health.addr = addr;
+        }
+        if let Some(secs) = args.max_rpc_silence_secs {
+            self.health.max_rpc_silence_secs = secs;
+        }
+        if let Some(distance) = args.max_head_distance {
+            self.health.max_head_distance = distance;
+        }
+        if let Some(secs) = args.rpc_health_check_secs {
+            self.rpc.health_check_secs = secs;
+        }
+        if let Some(lag) = args.rpc_max_lag {
+            self.rpc.max_lag = lag;
+        }
+        if let Some(rate) = args.requests_per_second {
+            self.rpc.requests_per_second = rate;
+        }
+        if let Some(rate) = args.compute_units_per_second {
+            self.rpc.compute_units_per_second = Some(rate);
+        }
+        if let Some(path) = &args.checkpoint_file {
+            self.checkpoint_file = Some(path.clone());
+        }
+        if let Some(path) = &args.gap_file {
+            self.gap_file = Some(path.clone());
+        }
+        if let Some(attempts) = args.retry_max_attempts {
+            self.retry.max_attempts = attempts;
+        }
+        if let Some(filter) = &args.tx_filter {
+            self.filter.transaction = Some(filter.clone());
+        }
+        if let Some(filter) = &args.block_filter {
+            self.filter.block = Some(filter.clone());
+        }
+        if let Some(hash) = &args.tx_hash_filter {
+            let filter = and_hash_contains(self.filter.transaction.as_ref(), hash)
+                .context("Invalid --tx-hash-filter")?;
+            self.filter.transaction = Some(filter);
+        }
+        if let Some(hash) = &args.block_hash_filter {
+            let filter = and_hash_contains(self.filter.block.as_ref(), hash)
+                .context("Invalid --block-hash-filter")?;
+            self.filter.block = Some(filter);
+        }
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.kafka.hosts.iter().all(|host| host.trim().is_empty()) {
+            bail!("No Kafka hosts: set kafka.hosts, KAFKA_BROKER_HOST or --kafka-broker-host");
+        }
+        if self.kafka.tx_topic.is_empty()
+            || self.kafka.block_topic.is_empty()
+            || self.kafka.pending_topic.is_empty()
+        {
+            bail!("Kafka topic names must not be empty");
+        }
+        if self.chains.is_empty() {
+            bail!("No chains to crawl: add [[chains]] to the config file or set CHAINS or HTTP_PROVIDER");
+        }
+        if self.mempool.enabled && self.mempool.poll_interval_ms == 0 {
+            bail!("mempool.poll_interval_ms must be greater than zero");
+        }
+        if self.retry.max_attempts == 0 {
+            bail!("retry.max_attempts must be at least 1");
+        }
+        let books: AddressBooks = self.filter.address_books.clone().into_iter().collect();
+        for (name, filter) in [
+            ("filter.transaction", &self.filter.transaction),
+            ("filter.block", &self.filter.block),
+        ] {
+            if let Some(Err(e)) = filter.as_ref().map(|f| f.check_address_books(&books)) {
+                bail!("Invalid {name}: {e}");
+            }
+        }
+        let positive = |rate: f64| rate > 0.0;
+        if !positive(self.rpc.requests_per_second)
+            || !self.rpc.compute_units_per_second.is_none_or(positive)
+        {
+            bail!("rpc.requests_per_second and rpc.compute_units_per_second must be positive");
+        }
+        if self.rpc.health_check_secs == 0 {
+            bail!("rpc.health_check_secs must be greater than zero");
+        }
+        if self.rpc.request_timeout_secs == 0 {
+            bail!("rpc.request_timeout_secs must be greater than zero");
+        }
+        let mut names = HashSet::new();
+        for chain in &self.chains {
+            if !names.insert(chain.name.as_str()) {
+                bail!("Chain {} is configured twice", chain.name);
+            }
+            if chain.provider_url.is_empty() {
+                bail!("Chain {} has no provider_url", chain.name);
+            }
+            for url in &chain.provider_url {
+                url.parse::<Endpoint>().with_context(|| {
+                    format!("Invalid provider_url {url:?} for chain {}", chain.name)
+                })?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+        [kafka]
+        hosts = ["localhost:9092"]
+
+        [[chains]]
+        name = "sepolia"
+        provider_url = ["http://localhost:8545", "ws://localhost:8546"]
+        from_block = 10
+        topic_prefix = "sepolia_"
+    "#;
+
+    #[test]
+    fn test_config_from_toml_with_overrides() {
+        let mut config: Config = toml::from_str(CONFIG).unwrap();
+        assert_eq!(config.rpc.requests_per_second, 25.0);
+        assert_eq!(config.kafka.tx_topic, "tx");
+        assert_eq!(config.chains[0].topic_prefix, "sepolia_");
+        assert_eq!(config.chains[0].provider_url.len(), 2);
+        assert_eq!(config.health.max_head_distance, 100);
+        // Configs written before the rename keep working.
+        let legacy: Config =
+            toml::from_str(&CONFIG.replace("provider_url", "http_provider")).unwrap();
+        assert_eq!(legacy.chains, config.chains);
+
+        config
+            .apply_args(&Args {
+                kafka_broker_host: Some(vec!["kafka:9092".to_string()]),
+                requests_per_second: Some(100.0),
+                ..Default::default()
+            })
+            .unwrap();
+        config.validate().unwrap();
+
+        assert_eq!(config.kafka.hosts, vec!["kafka:9092"]);
+        assert_eq!(config.rpc.requests_per_second, 100.0);
+    }
+
+    #[test]
+    fn test_config_validation_errors() {
+        let config = Config::default();
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("KAFKA_BROKER_HOST"), "{err}");
+
+        let mut config: Config = toml::from_str(CONFIG).unwrap();
+        config.chains[0].provider_url[1] = "not a url".to_string();
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("Invalid provider_url"), "{err}");
+
+        let err = toml::from_str::<Config>("delay = 1")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("unknown field `delay`"), "{err}");
+    }
+
+    #[test]
+    fn test_retry_backoff_doubles_up_to_max() {
+        let retry = RetryConfig {
+            max_attempts: 10,
+            initial_backoff_ms: 100,
+            max_backoff_ms: 1000,
+        };
+        let backoffs: Vec<_> = (1..=5).map(|n| retry.backoff(n).as_millis()).collect();
+        assert_eq!(backoffs, vec![100, 200, 400, 800, 1000]);
+    }
+
+    #[test]
+    fn test_filter_config() {
+        let mut config: Config = toml::from_str(&format!(
+            "{CONFIG}
+            [filter]
+            transaction = 'from in @treasury and value > 10 ether'
+
+            [filter.address_books]
+            treasury = ['0xAA']
+            "
+        ))
+        .unwrap();
+        config
+            .apply_args(&Args {
+                tx_hash_filter: Some("ab\"c".to_string()),
+                ..Default::default()
+            })
+            .unwrap();
+        config.validate().unwrap();
+        assert_eq!(
+            config.filter.transaction.as_ref().unwrap().source(),
+            r#"(from in @treasury and value > 10 ether) and hash contains "ab\"c""#
+        );
+
+        let err = toml::from_str::<Config>("[filter]\nblock = 'miner =='")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("expected a field or value"), "{err}");
+
+        config.filter.block = Some("miner in @pools".parse().unwrap());
+        let err = config.validate().unwrap_err().to_string();
+        assert_eq!(err, "Invalid filter.block: unknown address book @pools");
+
+        let nested = format!("{}miner == 0x01{}", "(".repeat(64), ")".repeat(64));
+        config.filter.block = Some(nested.parse().unwrap());
+        let err = config
+            .apply_args(&Args {
+                block_hash_filter: Some("ab".to_string()),
+                ..Default::default()
+            })
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Invalid --block-hash-filter");
+    }
+}
@@ -1,200 +1,488 @@
 use crate::{
-    kafka::KafkaProducer,
-    tracer::{end_span, start_span},
-    utils::{filter_block, filter_transaction},
+    chain::ChainConfig,
+    checkpoint::{Checkpoint, GapList},
+    config::{KafkaConfig, RetryConfig},
+    filter::FilterOption,
+    health::ChainStatus,
+    kafka::{Event, KafkaProducer},
+    metrics::{
+        BLOCKS_PUBLISHED, KAFKA_SEND_FAILURES, RPC_ERRORS, RPC_LATENCY, RPC_RETRIES,
+        TRANSACTIONS_PUBLISHED,
+    },
+    tracer::inject_context,
 };
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 
 use ethers::{
     prelude::{
-        providers::{JsonRpcClient, Middleware, Provider},
+        providers::{JsonRpcClient, Middleware, Provider, ProviderError},
         types::H256,
     },
     types::{Block, Transaction},
 };
-use futures::{stream, StreamExt};
-use std::env;
+use futures::{pin_mut, stream, Future, StreamExt};
+use std::collections::HashSet;
 use std::sync::Arc;
-use tokio::time::{sleep, Duration};
+use tokio::time::sleep;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, field, info, info_span, warn, Instrument, Span};
 
 const BUFFER_SIZE: usize = 10;
 
-pub struct FilterOption {
-    pub tx_hash_filter: Option<String>,
-    pub block_hash_filter: Option<String>,
-}
-
 pub struct Crawler<T: JsonRpcClient> {
     provider: Arc<Provider<T>>,
+    chain_id: u64,
     from_block: u64,
     kafka_producer: Arc<KafkaProducer>,
     tsx_topic: Arc<String>,
     block_topic: Arc<String>,
-    delay_time: u64,
     filter_options: Arc<FilterOption>,
+    status: Arc<ChainStatus>,
+    checkpoint: Option<Arc<Checkpoint>>,
+    gaps: Option<Arc<GapList>>,
+    recrawl_gaps: bool,
+    retry: Arc<RetryConfig>,
+}
+
+/// Work item of the transaction stage. `BlockDone` follows the last
+/// transaction of its block, so the ordered stream only yields it once the
+/// whole block has been processed. A block that was fetched but could not be
+/// published is followed by `BlockFailed` instead.
+enum TxItem {
+    Transaction(H256),
+    BlockDone,
+    BlockFailed,
+}
+
+enum Processed {
+    Transaction(Box<Transaction>),
+    /// Fetched, but publishing it failed, which leaves its block incomplete.
+    TransactionUnpublished(Box<Transaction>, u64),
+    TransactionFailed(u64),
+    BlockDone(u64),
+    BlockFailed(u64),
+}
+
+/// Calls `fetch` until it returns something, waiting longer after every
+/// error or empty result, and gives up after `retry.max_attempts` calls.
+async fn fetch_with_retry<R, F>(
+    method: &str,
+    retry: &RetryConfig,
+    fetch: impl Fn() -> F,
+) -> Option<R>
+where
+    F: Future<Output = Result<Option<R>, ProviderError>>,
+{
+    for attempt in 1..=retry.max_attempts {
+        if attempt > 1 {
+            RPC_RETRIES.with_label_values(&[method]).inc();
+            sleep(retry.backoff(attempt - 1)).await;
+        }
+        let timer = RPC_LATENCY.with_label_values(&[method]).start_timer();
+        let result = fetch().await;
+        timer.observe_duration();
+        match result {
+            Ok(Some(value)) => return Some(value),
+            Ok(None) => warn!(rpc.method = method, attempt, "Not found"),
+            Err(err) => {
+                RPC_ERRORS.with_label_values(&[method]).inc();
+                warn!(rpc.method = method, attempt, error = %err, "RPC call failed");
+            }
+        }
+    }
+    error!(
+        rpc.method = method,
+        attempts = retry.max_attempts,
+        "Giving up after every attempt failed"
+    );
+    None
 }
 
+#[tracing::instrument(skip(provider, retry))]
 async fn fetch_block(
     provider: &Arc<Provider<impl JsonRpcClient>>,
     block_number: u64,
+    retry: &RetryConfig,
 ) -> Option<Block<H256>> {
-    // println!("Fetching block {}", block_number);
-    let span = start_span("fetch_block");
-    let maybe_block = provider.get_block(block_number).await;
-    end_span(span);
-    match maybe_block {
-        Ok(Some(block)) => Some(block),
-        Ok(None) => {
-            println!("Block number {} not found.", block_number);
-            None
-        }
-        Err(err) => {
-            println!("Error fetching block {}: {}", block_number, err);
-            None
-        }
-    }
+    fetch_with_retry("eth_getBlockByNumber", retry, || {
+        provider.get_block(block_number)
+    })
+    .await
 }
 
+#[tracing::instrument(skip(provider, retry), fields(tx.hash = ?tx))]
 async fn fetch_transaction(
     provider: &Arc<Provider<impl JsonRpcClient>>,
     tx: H256,
+    retry: &RetryConfig,
 ) -> Option<Transaction> {
-    // println!("Fetching transaction {}", tx);
-    let span = start_span("fetch_transaction");
-    let maybe_transaction = provider.get_transaction(tx).await;
-    end_span(span);
-    match maybe_transaction {
-        Ok(Some(transaction)) => Some(transaction),
-        Ok(None) => {
-            println!("Tx {} not found.", tx);
-            None
-        }
-        Err(err) => {
-            println!("Error fetching tx {}: {}", tx, err);
-            None
-        }
-    }
+    fetch_with_retry("eth_getTransactionByHash", retry, || {
+        provider.get_transaction(tx)
+    })
+    .await
 }
 
 impl<T: JsonRpcClient> Crawler<T> {
     pub fn new(
         provider: Arc<Provider<T>>,
-        from_block: u64,
-        delay_time: u64,
+        chain_id: u64,
+        chain: &ChainConfig,
+        kafka: &KafkaConfig,
         filter_options: FilterOption,
-    ) -> Self {
-        let hosts: Vec<String> = env::var("KAFKA_BROKER_HOST")
-            .expect("KAFKA_BROKER_HOST not set")
-            .split(',')
-            .map(|s| s.to_string())
-            .collect();
-        let tsx_topic = env::var("KAFKA_TX_TOPIC").expect("KAFKA_TX_TOPIC not set");
-        let block_topic = env::var("KAFKA_BLOCK_TOPIC").expect("KAFKA_BLOCK_TOPIC not set");
-        let kafka_producer = Arc::new(KafkaProducer::new(hosts).expect("Setup Kafka error"));
-
-        Self {
+    ) -> Result<Self> {
+        let kafka_producer = Arc::new(
+            KafkaProducer::new(kafka.hosts.clone()).context("Failed to connect to Kafka")?,
+        );
+        let topic_prefix = &chain.topic_prefix;
+
+        Ok(Self {
             provider,
-            from_block,
+            chain_id,
+            from_block: chain.from_block,
             kafka_producer,
-            tsx_topic: Arc::new(tsx_topic),
-            block_topic: Arc::new(block_topic),
-            delay_time,
+            tsx_topic: Arc::new(format!("{topic_prefix}{}", kafka.tx_topic)),
+            block_topic: Arc::new(format!("{topic_prefix}{}", kafka.block_topic)),
             filter_options: Arc::new(filter_options),
+            status: Arc::new(ChainStatus::new(chain_id)),
+            checkpoint: None,
+            gaps: None,
+            recrawl_gaps: false,
+            retry: Arc::new(RetryConfig::default()),
+        })
+    }
+
+    pub fn with_retry(mut self, retry: RetryConfig) -> Self {
+        self.retry = Arc::new(retry);
+        self
+    }
+
+    /// Lists blocks that still fail after every retry in `gaps`. With
+    /// `recrawl` set, only the blocks already listed there are crawled, and
+    /// each is removed once it succeeds.
+    pub fn with_gaps(mut self, gaps: Arc<GapList>, recrawl: bool) -> Self {
+        self.gaps = Some(gaps);
+        self.recrawl_gaps = recrawl;
+        self
+    }
+
+    /// Resumes after the block recorded in `checkpoint`, if that is past
+    /// `from_block`, and records progress there while crawling.
+    pub fn with_checkpoint(mut self, checkpoint: Arc<Checkpoint>) -> Self {
+        if let Some(block_number) = checkpoint.get(self.chain_id) {
+            self.from_block = self.from_block.max(block_number + 1);
         }
+        self.checkpoint = Some(checkpoint);
+        self
     }
 
-    pub async fn get_transactions(self) -> Result<Vec<Transaction>> {
-        let span = start_span("get_transactions");
+    /// Progress of this crawler, for the health endpoints.
+    pub fn status(&self) -> Arc<ChainStatus> {
+        Arc::clone(&self.status)
+    }
+
+    /// Crawls from `from_block` to the current head, or only the listed gaps
+    /// when re-crawling them. Once `shutdown` is cancelled no new blocks are
+    /// started; blocks already in the pipeline are finished and published,
+    /// and the checkpoint is updated for them.
+    ///
+    /// A block whose fetch, or any of whose transaction fetches, still fails
+    /// after every retry is added to the gap list instead of being dropped,
+    /// and so is a block whose own message or any of whose transaction
+    /// messages could not be published. Without a gap list the checkpoint
+    /// stops short of the first such block, so that the next run starts
+    /// over from it.
+    pub async fn get_transactions(self, shutdown: CancellationToken) -> Result<Vec<Transaction>> {
         let Crawler {
             provider,
+            chain_id,
             from_block,
             kafka_producer,
             tsx_topic,
             block_topic,
-            delay_time,
             filter_options,
-            ..
+            status,
+            checkpoint,
+            gaps,
+            recrawl_gaps,
+            retry,
         } = self;
 
-        let to_block = provider.get_block_number().await?.as_u64();
-
-        let address_transactions: Vec<Transaction> = stream::iter(from_block..=to_block)
+        let crawl_span = info_span!(
+            "get_transactions",
+            chain.id = chain_id,
+            block.from = from_block,
+            block.to = field::Empty,
+        );
+        let chain_label = chain_id.to_string();
+        let to_block = provider
+            .get_block_number()
+            .instrument(crawl_span.clone())
+            .await
+            .inspect_err(|_| RPC_ERRORS.with_label_values(&["eth_blockNumber"]).inc())?
+            .as_u64();
+        status.record_rpc_success();
+        status.set_head_block(to_block);
+        crawl_span.record("block.to", to_block);
+        let blocks: Box<dyn Iterator<Item = u64> + Send> = match (&gaps, recrawl_gaps) {
+            (Some(gaps), true) => {
+                let blocks = gaps.get(chain_id);
+                crawl_span.in_scope(|| {
+                    info!(chain.id = chain_id, gaps = blocks.len(), "Re-crawling gaps")
+                });
+                Box::new(blocks.into_iter())
+            }
+            _ => {
+                crawl_span.in_scope(|| {
+                    info!(chain.id = chain_id, from_block, to_block, "Crawling blocks")
+                });
+                Box::new(from_block..=to_block)
+            }
+        };
+
+        let processed = stream::iter(blocks)
+            .take_until(shutdown.cancelled())
             .map(|block_number| {
+                let chain_label = chain_label.as_str();
                 let block_topic_clone = Arc::clone(&block_topic);
                 let provider_clone = Arc::clone(&provider);
                 let kafka_producer_clone = Arc::clone(&kafka_producer);
                 let filter_options_clone = Arc::clone(&filter_options);
+                let status_clone = Arc::clone(&status);
+                let retry_clone = Arc::clone(&retry);
+                let block_span =
+                    info_span!(parent: &crawl_span, "process_block", block.number = block_number);
                 async move {
-                    sleep(Duration::from_millis(delay_time)).await;
-                    match fetch_block(&provider_clone, block_number).await {
+                    match fetch_block(&provider_clone, block_number, &retry_clone).await {
                         Some(block) => {
-                            if filter_block(&block, &filter_options_clone.block_hash_filter) {
-                                let span = start_span("kafka_send_message");
-                                if let Err(e) =
-                                    &kafka_producer_clone.send_message(&block_topic_clone, &block)
-                                {
-                                    eprintln!("Failed to send message: {:?}", e);
+                            status_clone.record_rpc_success();
+                            status_clone.set_current_block(block_number);
+                            if filter_options_clone.accepts_block(&block, chain_id) {
+                                let send_span = info_span!(
+                                    "kafka_send_message",
+                                    messaging.destination.name = %block_topic_clone,
+                                );
+                                let _enter = send_span.enter();
+                                let event = Event {
+                                    chain_id,
+                                    headers: inject_context(&send_span),
+                                    data: &block,
+                                };
+                                let sent =
+                                    kafka_producer_clone.send_message(&block_topic_clone, &event);
+                                if let Err(e) = &sent {
+                                    KAFKA_SEND_FAILURES
+                                        .with_label_values(&[block_topic_clone.as_str()])
+                                        .inc();
+                                    error!(
+                                        block.number = block_number,
+                                        topic = %block_topic_clone,
+                                        error = ?e,
+                                        "Failed to send block"
+                                    );
+                                } else {
+                                    BLOCKS_PUBLISHED.with_label_values(&[chain_label]).inc();
+                                    debug!(
+                                        block.number = block_number,
+                                        block.hash = ?block.hash,
+                                        topic = %block_topic_clone,
+                                        "Sent block"
+                                    );
                                 }
-                                end_span(span);
-                                println!("Sent block {:?}", block.hash);
+                                (Some((block.transactions, sent.is_ok())), block_number, Span::current())
                             } else {
-                                println!("Skip block {:?}", block.hash);
+                                debug!(block.number = block_number, block.hash = ?block.hash, "Skip block");
+                                (Some((block.transactions, true)), block_number, Span::current())
                             }
-                            block.transactions
-                        }
-                        None => {
-                            println!("Block number {} not found.", block_number);
-                            Vec::new()
                         }
+                        None => (None, block_number, Span::current()),
                     }
                 }
+                .instrument(block_span)
             })
             .buffered(BUFFER_SIZE)
-            .flat_map(stream::iter)
-            .map(|tx| {
+            .flat_map(|(fetched, block_number, block_span)| {
+                let items: Vec<_> = match fetched {
+                    Some((transactions, published)) => transactions
+                        .into_iter()
+                        .map(TxItem::Transaction)
+                        .chain([if published {
+                            TxItem::BlockDone
+                        } else {
+                            TxItem::BlockFailed
+                        }])
+                        .collect(),
+                    None => vec![TxItem::BlockFailed],
+                };
+                stream::iter(items.into_iter().map(move |item| (item, block_number, block_span.clone())))
+            })
+            .map(|(item, block_number, block_span)| {
+                let chain_label = chain_label.as_str();
                 let tsx_topic_clone = Arc::clone(&tsx_topic);
                 let provider_clone = Arc::clone(&provider);
                 let kafka_producer_clone = Arc::clone(&kafka_producer);
                 let filter_options_clone = Arc::clone(&filter_options);
+                let status_clone = Arc::clone(&status);
+                let retry_clone = Arc::clone(&retry);
+                let tx_span = match &item {
+                    TxItem::Transaction(tx) => info_span!(
+                        parent: &block_span,
+                        "process_transaction",
+                        block.number = block_number,
+                        tx.hash = ?tx,
+                    ),
+                    TxItem::BlockDone | TxItem::BlockFailed => Span::none(),
+                };
                 async move {
-                    sleep(Duration::from_millis(delay_time)).await;
-                    let transaction = fetch_transaction(&provider_clone, tx).await;
-                    if let Some(tx) = &transaction {
-                        if filter_transaction(tx, &filter_options_clone.tx_hash_filter) {
-                            if let Err(e) = &kafka_producer_clone.send_message(&tsx_topic_clone, tx)
-                            {
-                                eprintln!("Failed to send message: {:?}", e);
-                            }
-                            println!("Sent tx {:?}", tx.hash);
+                    let tx = match item {
+                        TxItem::Transaction(tx) => tx,
+                        TxItem::BlockDone => return Processed::BlockDone(block_number),
+                        TxItem::BlockFailed => return Processed::BlockFailed(block_number),
+                    };
+                    let Some(transaction) = fetch_transaction(&provider_clone, tx, &retry_clone).await
+                    else {
+                        return Processed::TransactionFailed(block_number);
+                    };
+                    let tx = &transaction;
+                    status_clone.record_rpc_success();
+                    if filter_options_clone.accepts_transaction(tx, chain_id) {
+                        let send_span = info_span!(
+                            "kafka_send_message",
+                            messaging.destination.name = %tsx_topic_clone,
+                        );
+                        let _enter = send_span.enter();
+                        let event = Event {
+                            chain_id,
+                            headers: inject_context(&send_span),
+                            data: tx,
+                        };
+                        if let Err(e) =
+                            &kafka_producer_clone.send_message(&tsx_topic_clone, &event)
+                        {
+                            KAFKA_SEND_FAILURES
+                                .with_label_values(&[tsx_topic_clone.as_str()])
+                                .inc();
+                            error!(
+                                tx.hash = ?tx.hash,
+                                topic = %tsx_topic_clone,
+                                error = ?e,
+                                "Failed to send transaction"
+                            );
+                            return Processed::TransactionUnpublished(
+                                Box::new(transaction),
+                                block_number,
+                            );
                         } else {
-                            println!("Skip tx {:?}", tx.hash);
+                            TRANSACTIONS_PUBLISHED
+                                .with_label_values(&[chain_label])
+                                .inc();
+                            debug!(tx.hash = ?tx.hash, topic = %tsx_topic_clone, "Sent transaction");
                         }
+                    } else {
+                        debug!(tx.hash = ?tx.hash, "Skip transaction");
                     }
-                    transaction
+                    Processed::Transaction(Box::new(transaction))
                 }
+                .instrument(tx_span)
             })
-            .buffered(BUFFER_SIZE)
-            .filter_map(|tx| async { tx })
-            .collect()
-            .await;
+            .buffered(BUFFER_SIZE);
+        pin_mut!(processed);
+
+        let mut address_transactions = Vec::new();
+        let mut last_block = None;
+        let mut incomplete_blocks = HashSet::new();
+        let mut gap_count = 0;
+        let mut checkpoint_held = false;
+        while let Some(item) = processed.next().await {
+            let (block_number, complete) = match item {
+                Processed::Transaction(transaction) => {
+                    address_transactions.push(*transaction);
+                    continue;
+                }
+                Processed::TransactionUnpublished(transaction, block_number) => {
+                    address_transactions.push(*transaction);
+                    incomplete_blocks.insert(block_number);
+                    continue;
+                }
+                Processed::TransactionFailed(block_number) => {
+                    incomplete_blocks.insert(block_number);
+                    continue;
+                }
+                Processed::BlockDone(block_number) => {
+                    (block_number, !incomplete_blocks.remove(&block_number))
+                }
+                Processed::BlockFailed(block_number) => {
+                    incomplete_blocks.remove(&block_number);
+                    (block_number, false)
+                }
+            };
+            last_block = Some(block_number);
+            if !complete {
+                gap_count += 1;
+                checkpoint_held |= gaps.is_none();
+            }
+            record_gap(&gaps, chain_id, block_number, complete);
+            if let (Some(checkpoint), false, false) = (&checkpoint, recrawl_gaps, checkpoint_held) {
+                if let Err(e) = checkpoint.save(chain_id, block_number) {
+                    error!(block.number = block_number, error = %e, "Failed to save checkpoint");
+                }
+            }
+        }
 
-        end_span(span);
+        crawl_span.in_scope(|| {
+            info!(
+                chain.id = chain_id,
+                last_block,
+                transactions = address_transactions.len(),
+                gaps = gap_count,
+                interrupted = shutdown.is_cancelled(),
+                "Finished crawling"
+            )
+        });
         Ok(address_transactions)
     }
 }
 
+/// Adds an incomplete block to the gap list, or removes a complete one that
+/// a re-crawl has filled in.
+fn record_gap(gaps: &Option<Arc<GapList>>, chain_id: u64, block_number: u64, complete: bool) {
+    let Some(gaps) = gaps else {
+        if !complete {
+            error!(
+                block.number = block_number,
+                "Block is incomplete and no gap file is set, the checkpoint stays before it"
+            );
+        }
+        return;
+    };
+    let result = if complete {
+        gaps.remove(chain_id, block_number)
+    } else {
+        warn!(block.number = block_number, "Recording block as a gap");
+        gaps.add(chain_id, block_number)
+    };
+    if let Err(e) = result {
+        error!(block.number = block_number, error = %e, "Failed to update gap list");
+    }
+}
+
 #[cfg(test)]
 mod tests {
 
     use super::*;
     use crate::mock::{get_mock, setup_provider};
-    use dotenv::dotenv;
     use ethers::prelude::{
-        providers::MockProvider,
+        providers::{JsonRpcError, MockProvider, MockResponse},
         types::{Block, Transaction, H256},
     };
-    use std::env;
+
+    fn no_backoff(max_attempts: u32) -> RetryConfig {
+        RetryConfig {
+            max_attempts,
+            initial_backoff_ms: 0,
+            max_backoff_ms: 0,
+        }
+    }
 
     #[tokio::test]
     async fn test_fetch_block() -> Result<()> {
@@ -203,7 +491,37 @@ mod tests {
         let block: Block<H256> = Block::default();
         mock_provider.push(block)?;
 
-        let block = fetch_block(&setup_provider(mock_provider), 1).await;
+        let block = fetch_block(&setup_provider(mock_provider), 1, &no_backoff(1)).await;
+        assert_eq!(block, Some(Block::default()));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_fetch_block_error_is_counted() {
+        let errors = RPC_ERRORS.with_label_values(&["eth_getBlockByNumber"]);
+        let before = errors.get();
+
+        // An empty mock answers every request with an error.
+        let block = fetch_block(&setup_provider(MockProvider::new()), 1, &no_backoff(3)).await;
+
+        assert_eq!(block, None);
+        assert!(errors.get() >= before + 3);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_block_retries_until_found() -> Result<()> {
+        // Responses are popped from the back: an error, then nothing, then
+        // the block.
+        let mock_provider = MockProvider::new();
+        mock_provider.push(Block::<H256>::default())?;
+        mock_provider.push(Option::<Block<H256>>::None)?;
+        mock_provider.push_response(MockResponse::Error(JsonRpcError {
+            code: -32000,
+            message: "header not found".to_string(),
+            data: None,
+        }));
+
+        let block = fetch_block(&setup_provider(mock_provider), 1, &no_backoff(3)).await;
         assert_eq!(block, Some(Block::default()));
         Ok(())
     }
@@ -220,7 +538,8 @@ mod tests {
 
         mock_provider.push(transaction)?;
 
-        let transaction = fetch_transaction(&setup_provider(mock_provider), tx).await;
+        let transaction =
+            fetch_transaction(&setup_provider(mock_provider), tx, &no_backoff(1)).await;
 
         Ok(transaction)
     }
@@ -235,66 +554,53 @@ mod tests {
         Ok(())
     }
 
+    fn test_chain() -> ChainConfig {
+        ChainConfig {
+            name: "test".to_string(),
+            provider_url: vec!["http://localhost:8545".to_string()],
+            from_block: 1,
+            topic_prefix: String::new(),
+        }
+    }
+
     #[tokio::test]
     #[ignore = "This test requires the Kafka service"]
     async fn test_crawler_success() -> Result<()> {
         let mock_provider = get_mock()?;
 
-        dotenv().ok();
-        env::set_var(
-            "KAFKA_BROKER_HOST",
-            "localhost:9092,localhost:9093,localhost:9094",
-        );
-        env::set_var("KAFKA_TX_TOPIC", "tx");
-        env::set_var("KAFKA_BLOCK_TOPIC", "block");
         let _crawler = Crawler::new(
             setup_provider(mock_provider),
             1,
-            0,
-            FilterOption {
-                tx_hash_filter: None,
-                block_hash_filter: None,
+            &test_chain(),
+            &KafkaConfig {
+                hosts: vec![
+                    "localhost:9092".to_string(),
+                    "localhost:9093".to_string(),
+                    "localhost:9094".to_string(),
+                ],
+                ..Default::default()
             },
-        );
+            FilterOption::default(),
+        )?;
 
         Ok(())
     }
 
     #[tokio::test]
-    #[should_panic]
-    async fn test_crawler_fail_missing_env() {
-        let mock_provider = get_mock().unwrap();
-
-        env::remove_var("KAFKA_BROKER_HOST");
-        env::remove_var("KAFKA_TX_TOPIC");
-        env::remove_var("KAFKA_BLOCK_TOPIC");
-        Crawler::new(
-            setup_provider(mock_provider),
-            1,
-            0,
-            FilterOption {
-                tx_hash_filter: None,
-                block_hash_filter: None,
-            },
-        );
-    }
-
-    #[tokio::test]
-    #[should_panic]
-    async fn test_crawler_fail_wrong_env() {
+    async fn test_crawler_fail_wrong_kafka_host() {
         let mock_provider = get_mock().unwrap();
 
-        env::set_var("KAFKA_BROKER_HOST", "invalid_url");
-        env::set_var("KAFKA_TX_TOPIC", "invalid_url");
-        env::set_var("KAFKA_BLOCK_TOPIC", "invalid_url");
-        Crawler::new(
+        let result = Crawler::new(
             setup_provider(mock_provider),
             1,
-            0,
-            FilterOption {
-                tx_hash_filter: None,
-                block_hash_filter: None,
+            &test_chain(),
+            &KafkaConfig {
+                hosts: vec!["invalid_url".to_string()],
+                ..Default::default()
             },
+            FilterOption::default(),
         );
+        let error = result.err().expect("an unreachable broker is an error");
+        assert_eq!(error.to_string(), "Failed to connect to Kafka");
     }
 }
@@ -0,0 +1,285 @@
+use crate::metrics::{
+    RPC_ENDPOINT_HEAD, RPC_ENDPOINT_HEALTHY, RPC_ENDPOINT_LATENCY, RPC_ENDPOINT_REQUESTS,
+};
+use async_trait::async_trait;
+use ethers::prelude::{
+    providers::{JsonRpcClient, Provider, ProviderError, RpcError},
+    types::U64,
+};
+use futures::future::join_all;
+use serde::{de::DeserializeOwned, Serialize};
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::time::{interval, timeout, Duration, MissedTickBehavior};
+use tracing::{debug, warn};
+
+#[derive(Debug)]
+struct Member<C> {
+    label: String,
+    client: C,
+    healthy: AtomicBool,
+}
+
+impl<C> Member<C> {
+    fn set_healthy(&self, healthy: bool) {
+        self.healthy.store(healthy, Ordering::Relaxed);
+        RPC_ENDPOINT_HEALTHY
+            .with_label_values(&[&self.label])
+            .set(healthy as i64);
+    }
+}
+
+/// Spreads requests over several endpoints of the same chain.
+///
+/// Requests go round-robin to the healthy endpoints. A request that fails in
+/// transport or times out is retried on the next endpoint and takes the
+/// failing one out of rotation until the next health check, which also drops
+/// endpoints lagging more than `max_lag` blocks behind the highest head. When
+/// no endpoint is healthy, all of them are tried anyway. A JSON-RPC error
+/// response, such as a reverted call, would be the same on every endpoint,
+/// so it is returned as it is.
+#[derive(Debug)]
+pub struct FailoverClient<C> {
+    members: Vec<Member<C>>,
+    next: AtomicUsize,
+    max_lag: u64,
+    request_timeout: Duration,
+}
+
+impl<C: JsonRpcClient> FailoverClient<C>
+where
+    C::Error: Into<ProviderError>,
+{
+    /// Builds a client from labelled endpoints; `clients` must not be empty.
+    pub fn new(clients: Vec<(String, C)>, max_lag: u64, request_timeout: Duration) -> Self {
+        assert!(!clients.is_empty(), "FailoverClient needs an endpoint");
+        let members = clients
+            .into_iter()
+            .map(|(label, client)| {
+                let member = Member {
+                    label,
+                    client,
+                    healthy: AtomicBool::new(true),
+                };
+                member.set_healthy(true);
+                member
+            })
+            .collect();
+        Self {
+            members,
+            next: AtomicUsize::new(0),
+            max_lag,
+            request_timeout,
+        }
+    }
+
+    /// Healthy endpoints in round-robin order, followed by the unhealthy
+    /// ones as a last resort.
+    fn candidates(&self) -> impl Iterator<Item = &Member<C>> {
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let len = self.members.len();
+        let ordered = (0..len).map(move |i| &self.members[(start + i) % len]);
+        let (healthy, unhealthy): (Vec<_>, Vec<_>) =
+            ordered.partition(|member| member.healthy.load(Ordering::Relaxed));
+        healthy.into_iter().chain(unhealthy)
+    }
+
+    /// Asks every endpoint for its latest block and updates which ones are
+    /// in rotation. Endpoints that fail to answer within `deadline` or lag
+    /// more than `max_lag` blocks behind the highest head are skipped.
+    pub async fn check_health(&self, deadline: Duration) {
+        let heads = join_all(self.members.iter().map(|member| async move {
+            let result: Result<U64, ProviderError> =
+                match timeout(deadline, member.client.request("eth_blockNumber", ())).await {
+                    Ok(result) => result.map_err(Into::into),
+                    Err(_) => Err(ProviderError::CustomError("timed out".to_string())),
+                };
+            match result {
+                Ok(head) => {
+                    let head = head.as_u64();
+                    RPC_ENDPOINT_HEAD
+                        .with_label_values(&[&member.label])
+                        .set(head as i64);
+                    Some(head)
+                }
+                Err(e) => {
+                    warn!(
+                        rpc.endpoint = %member.label,
+                        error = %e,
+                        "RPC endpoint health check failed"
+                    );
+                    None
+                }
+            }
+        }))
+        .await;
+
+        let highest = heads.iter().flatten().copied().max().unwrap_or_default();
+        for (member, head) in self.members.iter().zip(heads) {
+            let healthy = head.is_some_and(|head| head + self.max_lag >= highest);
+            if !healthy && head.is_some() {
+                warn!(
+                    rpc.endpoint = %member.label,
+                    rpc.head = head,
+                    rpc.highest_head = highest,
+                    "RPC endpoint lags behind, skipping it"
+                );
+            }
+            member.set_healthy(healthy);
+        }
+    }
+}
+
+#[async_trait]
+impl<C: JsonRpcClient> JsonRpcClient for FailoverClient<C>
+where
+    C::Error: Into<ProviderError>,
+{
+    type Error = ProviderError;
+
+    async fn request<T, R>(&self, method: &str, params: T) -> Result<R, ProviderError>
+    where
+        T: Debug + Serialize + Send + Sync,
+        R: DeserializeOwned + Send,
+    {
+        let params = serde_json::to_value(params)?;
+        let mut last_error = None;
+        for member in self.candidates() {
+            let timer = RPC_ENDPOINT_LATENCY
+                .with_label_values(&[&member.label])
+                .start_timer();
+            let result =
+                match timeout(self.request_timeout, member.client.request(method, &params)).await {
+                    Ok(result) => result.map_err(Into::into),
+                    Err(_) => Err(ProviderError::CustomError("timed out".to_string())),
+                };
+            timer.observe_duration();
+            match result {
+                Ok(response) => {
+                    RPC_ENDPOINT_REQUESTS
+                        .with_label_values(&[&member.label, "ok"])
+                        .inc();
+                    return Ok(response);
+                }
+                Err(e) => {
+                    if e.as_error_response().is_some() {
+                        RPC_ENDPOINT_REQUESTS
+                            .with_label_values(&[&member.label, "rpc_error"])
+                            .inc();
+                        return Err(e);
+                    }
+                    RPC_ENDPOINT_REQUESTS
+                        .with_label_values(&[&member.label, "error"])
+                        .inc();
+                    warn!(
+                        rpc.endpoint = %member.label,
+                        rpc.method = method,
+                        error = %e,
+                        "RPC request failed, trying next endpoint"
+                    );
+                    member.set_healthy(false);
+                    last_error = Some(e);
+                }
+            }
+        }
+        Err(last_error.expect("FailoverClient has at least one endpoint"))
+    }
+}
+
+/// Runs `check_health` on the provider's endpoints every `every` until the
+/// task is dropped.
+pub async fn run_health_checks<C>(provider: Arc<Provider<FailoverClient<C>>>, every: Duration)
+where
+    C: JsonRpcClient,
+    C::Error: Into<ProviderError>,
+{
+    let client: &FailoverClient<C> = (*provider).as_ref();
+    let mut ticks = interval(every);
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        ticks.tick().await;
+        client.check_health(every).await;
+        debug!("RPC endpoint health check done");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::prelude::providers::{JsonRpcError, Middleware, MockProvider, MockResponse};
+
+    fn client(members: Vec<(&str, MockProvider)>) -> FailoverClient<MockProvider> {
+        FailoverClient::new(
+            members
+                .into_iter()
+                .map(|(label, mock)| (label.to_string(), mock))
+                .collect(),
+            5,
+            Duration::from_secs(1),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_failover_on_error() {
+        // The first endpoint has no responses queued, so every call fails.
+        let healthy = MockProvider::new();
+        healthy.push(U64::from(7)).unwrap();
+        let provider = Provider::new(client(vec![
+            ("failing", MockProvider::new()),
+            ("healthy", healthy),
+        ]));
+
+        assert_eq!(provider.get_block_number().await.unwrap(), U64::from(7));
+        assert!(!provider.as_ref().members[0].healthy.load(Ordering::Relaxed));
+        assert!(provider.as_ref().members[1].healthy.load(Ordering::Relaxed));
+    }
+
+    #[tokio::test]
+    async fn test_error_response_is_returned() {
+        let rejecting = MockProvider::new();
+        rejecting.push_response(MockResponse::Error(JsonRpcError {
+            code: 3,
+            message: "execution reverted".to_string(),
+            data: None,
+        }));
+        let other = MockProvider::new();
+        other.push(U64::from(7)).unwrap();
+        let failover = client(vec![("rejecting", rejecting), ("other", other)]);
+
+        let error = failover
+            .request::<_, U64>("eth_call", ())
+            .await
+            .unwrap_err();
+        assert_eq!(error.as_error_response().unwrap().code, 3);
+        assert!(failover.members[0].healthy.load(Ordering::Relaxed));
+        // The other endpoint was not asked.
+        let next: U64 = failover.request("eth_blockNumber", ()).await.unwrap();
+        assert_eq!(next.as_u64(), 7);
+    }
+
+    #[tokio::test]
+    async fn test_round_robin_and_lag() {
+        let a = MockProvider::new();
+        let b = MockProvider::new();
+        let failover = client(vec![("a", a.clone()), ("b", b.clone())]);
+
+        a.push(U64::from(1)).unwrap();
+        b.push(U64::from(2)).unwrap();
+        let first: U64 = failover.request("eth_blockNumber", ()).await.unwrap();
+        let second: U64 = failover.request("eth_blockNumber", ()).await.unwrap();
+        assert_eq!((first.as_u64(), second.as_u64()), (1, 2));
+
+        // `a` is 10 blocks behind `b`, more than the allowed lag of 5.
+        a.push(U64::from(90)).unwrap();
+        b.push(U64::from(100)).unwrap();
+        failover.check_health(Duration::from_secs(1)).await;
+        assert!(!failover.members[0].healthy.load(Ordering::Relaxed));
+
+        b.push(U64::from(3)).unwrap();
+        b.push(U64::from(4)).unwrap();
+        let first: U64 = failover.request("eth_blockNumber", ()).await.unwrap();
+        let second: U64 = failover.request("eth_blockNumber", ()).await.unwrap();
+        assert_eq!((first.as_u64(), second.as_u64()), (4, 3));
+    }
+}
@@ -0,0 +1,176 @@
+use crate::config::FilterConfig;
+use ethers::types::{Block, Transaction, H256, U256};
+use filter_expr::{AddressBooks, Filter, Record, Value};
+
+/// Decides which blocks and transactions are published, see `FilterConfig`.
+#[derive(Clone, Default)]
+pub struct FilterOption {
+    pub transaction: Option<Filter>,
+    pub block: Option<Filter>,
+    pub address_books: AddressBooks,
+}
+
+impl From<&FilterConfig> for FilterOption {
+    fn from(config: &FilterConfig) -> Self {
+        Self {
+            transaction: config.transaction.clone(),
+            block: config.block.clone(),
+            address_books: config.address_books.clone().into_iter().collect(),
+        }
+    }
+}
+
+impl FilterOption {
+    pub fn accepts_transaction(&self, transaction: &Transaction, chain_id: u64) -> bool {
+        self.transaction.as_ref().is_none_or(|filter| {
+            filter.matches(
+                &TransactionRecord {
+                    transaction,
+                    chain_id,
+                },
+                &self.address_books,
+            )
+        })
+    }
+
+    pub fn accepts_block(&self, block: &Block<H256>, chain_id: u64) -> bool {
+        self.block.as_ref().is_none_or(|filter| {
+            filter.matches(&BlockRecord { block, chain_id }, &self.address_books)
+        })
+    }
+}
+
+/// Quantities beyond `u128` are clamped; no real balance gets there.
+fn quantity(value: U256) -> Value {
+    if value > U256::from(u128::MAX) {
+        Value::Int(u128::MAX)
+    } else {
+        Value::Int(value.as_u128())
+    }
+}
+
+/// Full lowercase `0x` form of an address or hash.
+fn hex(value: impl std::fmt::Debug) -> Value {
+    Value::Str(format!("{value:?}"))
+}
+
+struct TransactionRecord<'a> {
+    transaction: &'a Transaction,
+    chain_id: u64,
+}
+
+impl Record for TransactionRecord<'_> {
+    fn field(&self, name: &str) -> Option<Value> {
+        let tx = self.transaction;
+        Some(match name {
+            "kind" => Value::from("transaction"),
+            "chain_id" => Value::from(self.chain_id),
+            "hash" => hex(tx.hash),
+            "block_number" => Value::from(tx.block_number?.as_u64()),
+            "block_hash" => hex(tx.block_hash?),
+            "from" => hex(tx.from),
+            "to" => hex(tx.to?),
+            "value" => quantity(tx.value),
+            "nonce" => quantity(tx.nonce),
+            "gas" => quantity(tx.gas),
+            "gas_price" => quantity(tx.gas_price?),
+            "max_fee_per_gas" => quantity(tx.max_fee_per_gas?),
+            "max_priority_fee_per_gas" => quantity(tx.max_priority_fee_per_gas?),
+            "input" => Value::Str(tx.input.to_string()),
+            "selector" => Value::Str(tx.input.get(..4).map(|s| format!("0x{}", hex_bytes(s)))?),
+            "type" => Value::from(tx.transaction_type?.as_u64()),
+            _ => return None,
+        })
+    }
+}
+
+fn hex_bytes(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+struct BlockRecord<'a> {
+    block: &'a Block<H256>,
+    chain_id: u64,
+}
+
+impl Record for BlockRecord<'_> {
+    fn field(&self, name: &str) -> Option<Value> {
+        let block = self.block;
+        Some(match name {
+            "kind" => Value::from("block"),
+            "chain_id" => Value::from(self.chain_id),
+            "hash" | "block_hash" => hex(block.hash?),
+            "block_number" => Value::from(block.number?.as_u64()),
+            "parent_hash" => hex(block.parent_hash),
+            "miner" => hex(block.author?),
+            "timestamp" => quantity(block.timestamp),
+            "gas_used" => quantity(block.gas_used),
+            "gas_limit" => quantity(block.gas_limit),
+            "base_fee_per_gas" => quantity(block.base_fee_per_gas?),
+            "transaction_count" => Value::from(block.transactions.len() as u64),
+            _ => return None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::types::{Address, Bytes};
+
+    fn transfer(to: Address, ether: u64) -> Transaction {
+        Transaction {
+            hash: H256::repeat_byte(0xab),
+            from: Address::repeat_byte(0xaa),
+            to: Some(to),
+            value: U256::exp10(18) * ether,
+            input: Bytes::from(vec![0xa9, 0x05, 0x9c, 0xbb, 0x00]),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_accepts_transaction() {
+        let options = FilterOption {
+            transaction: Some(
+                "from in @treasury and value > 10 ether and to not in @allowlist \
+                 and selector == 0xa9059cbb"
+                    .parse()
+                    .unwrap(),
+            ),
+            block: None,
+            address_books: [
+                (
+                    "treasury",
+                    vec![format!("{:?}", Address::repeat_byte(0xaa))],
+                ),
+                (
+                    "allowlist",
+                    vec![format!("{:?}", Address::repeat_byte(0xbb))],
+                ),
+            ]
+            .into_iter()
+            .collect(),
+        };
+
+        assert!(options.accepts_transaction(&transfer(Address::repeat_byte(0xcc), 11), 1));
+        assert!(!options.accepts_transaction(&transfer(Address::repeat_byte(0xbb), 11), 1));
+        assert!(!options.accepts_transaction(&transfer(Address::repeat_byte(0xcc), 10), 1));
+        // Blocks are not filtered without a block expression.
+        assert!(options.accepts_block(&Block::default(), 1));
+    }
+
+    #[test]
+    fn test_hash_filter() {
+        let tx = transfer(Address::zero(), 1);
+        let accepts = |source: &str| {
+            FilterOption {
+                transaction: Some(source.parse().unwrap()),
+                ..Default::default()
+            }
+            .accepts_transaction(&tx, 1)
+        };
+        assert!(accepts("hash contains 'abab'"));
+        assert!(!accepts("hash contains 'cdcd'"));
+    }
+}
@@ -0,0 +1,187 @@
+use axum::{extract::Extension, http::StatusCode, routing::get, Json, Router, Server};
+use serde::Serialize;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::metrics::{metrics_handler, HEAD_DISTANCE};
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Crawl progress of one chain, updated by its `Crawler` as it runs.
+pub struct ChainStatus {
+    chain_id: u64,
+    head_block: AtomicU64,
+    current_block: AtomicU64,
+    last_rpc_success: AtomicU64,
+}
+
+impl ChainStatus {
+    pub fn new(chain_id: u64) -> Self {
+        Self {
+            chain_id,
+            head_block: AtomicU64::new(0),
+            current_block: AtomicU64::new(0),
+            // Count startup as a success so a fresh instance is not reported stuck.
+            last_rpc_success: AtomicU64::new(now()),
+        }
+    }
+
+    pub fn set_head_block(&self, block_number: u64) {
+        self.head_block.fetch_max(block_number, Ordering::Relaxed);
+        self.update_head_distance_metric();
+    }
+
+    pub fn set_current_block(&self, block_number: u64) {
+        self.current_block
+            .fetch_max(block_number, Ordering::Relaxed);
+        self.update_head_distance_metric();
+    }
+
+    fn update_head_distance_metric(&self) {
+        HEAD_DISTANCE
+            .with_label_values(&[&self.chain_id.to_string()])
+            .set(self.head_distance() as i64);
+    }
+
+    pub fn record_rpc_success(&self) {
+        self.last_rpc_success.store(now(), Ordering::Relaxed);
+    }
+
+    pub fn head_distance(&self) -> u64 {
+        self.head_block
+            .load(Ordering::Relaxed)
+            .saturating_sub(self.current_block.load(Ordering::Relaxed))
+    }
+
+    fn report(&self) -> ChainReport {
+        let last_rpc_success = self.last_rpc_success.load(Ordering::Relaxed);
+        ChainReport {
+            chain_id: self.chain_id,
+            head_block: self.head_block.load(Ordering::Relaxed),
+            current_block: self.current_block.load(Ordering::Relaxed),
+            head_distance: self.head_distance(),
+            last_rpc_success,
+            seconds_since_rpc_success: now().saturating_sub(last_rpc_success),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ChainReport {
+    chain_id: u64,
+    head_block: u64,
+    current_block: u64,
+    head_distance: u64,
+    last_rpc_success: u64,
+    seconds_since_rpc_success: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HealthReport {
+    status: &'static str,
+    chains: Vec<ChainReport>,
+}
+
+pub struct HealthState {
+    pub chains: Vec<Arc<ChainStatus>>,
+    /// Liveness fails once a chain has had no successful RPC call for this long.
+    pub max_rpc_silence_secs: u64,
+    /// Readiness fails while a chain is further than this behind the head.
+    pub max_head_distance: u64,
+}
+
+impl HealthState {
+    fn check(&self, healthy: impl Fn(&ChainReport) -> bool) -> (StatusCode, Json<HealthReport>) {
+        let chains = self
+            .chains
+            .iter()
+            .map(|chain| chain.report())
+            .collect::<Vec<_>>();
+        let (code, status) = if chains.iter().all(healthy) {
+            (StatusCode::OK, "ok")
+        } else {
+            (StatusCode::SERVICE_UNAVAILABLE, "unavailable")
+        };
+        (code, Json(HealthReport { status, chains }))
+    }
+}
+
+pub(crate) async fn liveness(
+    state: Extension<Arc<HealthState>>,
+) -> (StatusCode, Json<HealthReport>) {
+    state.check(|chain| chain.seconds_since_rpc_success <= state.max_rpc_silence_secs)
+}
+
+pub(crate) async fn readiness(
+    state: Extension<Arc<HealthState>>,
+) -> (StatusCode, Json<HealthReport>) {
+    state.check(|chain| {
+        chain.seconds_since_rpc_success <= state.max_rpc_silence_secs
+            && chain.head_distance <= state.max_head_distance
+    })
+}
+
+pub async fn serve(addr: SocketAddr, state: Arc<HealthState>) {
+    let app = Router::new()
+        .route("/healthz", get(liveness))
+        .route("/readyz", get(readiness))
+        .route("/metrics", get(metrics_handler))
+        .layer(Extension(state));
+
+    if let Err(e) = Server::bind(&addr).serve(app.into_make_service()).await {
+        tracing::error!(error = %e, "Health server stopped");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(chain: Arc<ChainStatus>) -> Extension<Arc<HealthState>> {
+        Extension(Arc::new(HealthState {
+            chains: vec![chain],
+            max_rpc_silence_secs: 60,
+            max_head_distance: 10,
+        }))
+    }
+
+    #[tokio::test]
+    async fn test_readiness_follows_head_distance() {
+        let chain = Arc::new(ChainStatus::new(1));
+        chain.set_head_block(100);
+        chain.set_current_block(50);
+
+        let (code, report) = readiness(state(chain.clone())).await;
+        assert_eq!(code, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(report.chains[0].head_distance, 50);
+
+        chain.set_current_block(95);
+        let (code, _) = readiness(state(chain.clone())).await;
+        assert_eq!(code, StatusCode::OK);
+
+        let (code, _) = liveness(state(chain)).await;
+        assert_eq!(code, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_liveness_fails_without_rpc_success() {
+        let chain = Arc::new(ChainStatus::new(1));
+        chain.last_rpc_success.store(now() - 120, Ordering::Relaxed);
+
+        let (code, report) = liveness(state(chain.clone())).await;
+        assert_eq!(code, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(report.status, "unavailable");
+
+        chain.record_rpc_success();
+        let (code, _) = liveness(state(chain)).await;
+        assert_eq!(code, StatusCode::OK);
+    }
+}
@@ -1,9 +1,24 @@
 use kafka::producer::{Producer, Record, RequiredAcks};
 use kafka::Error;
 use serde::Serialize;
+use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
+/// Envelope published for every block and transaction, tagging the payload
+/// with the chain it was crawled from.
+///
+/// The Kafka protocol version spoken by the `kafka` crate has no record
+/// headers, so message headers such as the W3C trace context travel here.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Event<'a, T: Serialize> {
+    pub chain_id: u64,
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub headers: HashMap<String, String>,
+    pub data: &'a T,
+}
+
 pub struct KafkaProducer {
     producer: Arc<Mutex<Producer>>,
 }
@@ -20,6 +35,8 @@ impl KafkaProducer {
         })
     }
 
+    /// Sends synchronously and waits for the broker's ack, so nothing is left
+    /// buffered in the producer once this returns.
     pub fn send_message(&self, topic: &str, payload: &impl Serialize) -> Result<(), Error> {
         let mut producer = self.producer.lock().unwrap();
         let buffer = serde_json::to_string(payload).unwrap();
@@ -27,3 +44,28 @@ impl KafkaProducer {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_event_envelope() {
+        let payload = json!({ "hash": "0x01" });
+        let event = Event {
+            chain_id: 84532,
+            headers: HashMap::from([("traceparent".to_string(), "00-01-02-01".to_string())]),
+            data: &payload,
+        };
+
+        assert_eq!(
+            serde_json::to_value(&event).unwrap(),
+            json!({
+                "chainId": 84532,
+                "headers": { "traceparent": "00-01-02-01" },
+                "data": { "hash": "0x01" }
+            })
+        );
+    }
+}
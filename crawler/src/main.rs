@@ -1,73 +1,200 @@
+pub mod chain;
+pub mod checkpoint;
+pub mod config;
 pub mod crawler;
+pub mod failover;
+pub mod filter;
+pub mod health;
 pub mod kafka;
+pub mod mempool;
+pub mod metrics;
 #[cfg(test)]
 pub mod mock;
+pub mod provider;
+pub mod ratelimit;
 pub mod tracer;
-pub mod utils;
+use crate::checkpoint::{Checkpoint, GapList};
+use crate::config::{Args, Config, RpcConfig};
 use crate::crawler::Crawler;
-use anyhow::Result;
+use anyhow::{bail, Result};
 use clap::Parser;
-use crawler::FilterOption;
 use dotenv::dotenv;
-use ethers::prelude::providers::Http;
-use ethers::prelude::{
-    providers::{JsonRpcClient, Provider},
-    HttpRateLimitRetryPolicy, RetryClient,
-};
+use ethers::prelude::providers::{Middleware, Provider};
+use failover::{run_health_checks, FailoverClient};
+use filter::FilterOption;
+use futures::future::join_all;
+use health::HealthState;
+use mempool::MempoolWatcher;
 use opentelemetry::global;
-use std::env::var;
-use std::str::FromStr;
+use provider::{Endpoint, Transport};
+use ratelimit::RateLimited;
+use std::process;
 use std::sync::Arc;
-use tracer::init_tracer_provider;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::time::Duration;
+use tokio_util::sync::CancellationToken;
+use tracer::{init_tracing, TracingConfig};
+use tracing::{error, info, warn};
 
-fn get_provider() -> Result<Provider<impl JsonRpcClient>> {
-    let http_provider = var("HTTP_PROVIDER").expect("HTTP_PROVIDER not set");
-
-    Ok(Provider::new(RetryClient::new(
-        Http::from_str(&http_provider)?,
-        Box::new(HttpRateLimitRetryPolicy),
-        10,
-        500,
+/// Connects to every URL, choosing HTTP, WebSocket or IPC from its scheme,
+/// and paces each endpoint to its own request budget. Endpoints that cannot
+/// be reached are left out as long as one connects.
+async fn get_provider(
+    urls: &[String],
+    rpc: &RpcConfig,
+) -> Result<Provider<FailoverClient<RateLimited<Transport>>>> {
+    let mut clients = Vec::new();
+    for url in urls {
+        let endpoint = url.parse::<Endpoint>()?;
+        match Transport::connect(&endpoint).await {
+            Ok(transport) => clients.push((
+                endpoint.label(),
+                RateLimited::new(endpoint.label(), transport, rpc),
+            )),
+            Err(e) => {
+                warn!(
+                    rpc.endpoint = %endpoint.label(),
+                    error = %e,
+                    "Failed to connect to RPC endpoint"
+                )
+            }
+        }
+    }
+    if clients.is_empty() {
+        bail!("None of the RPC endpoints could be reached");
+    }
+    Ok(Provider::new(FailoverClient::new(
+        clients,
+        rpc.max_lag,
+        Duration::from_secs(rpc.request_timeout_secs),
     )))
 }
 
-#[derive(Parser, Debug)]
-struct Args {
-    #[arg(long)]
-    tx_hash_filter: Option<String>,
-
-    #[arg(long)]
-    block_hash_filter: Option<String>,
+/// Cancels `shutdown` on SIGINT or SIGTERM so the crawlers can drain.
+async fn cancel_on_signal(shutdown: CancellationToken) {
+    let mut terminate = signal(SignalKind::terminate()).expect("Failed to listen for SIGTERM");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate.recv() => {}
+    }
+    info!("Shutdown requested, finishing blocks in flight");
+    shutdown.cancel();
 }
 
 #[tokio::main]
 async fn main() {
     dotenv().ok();
     let args = Args::parse();
-    init_tracer_provider().expect("Failed to initialize tracer provider.");
-
-    let _tracer = global::tracer("tracing-jaeger");
-
-    let provider = Arc::new(get_provider().unwrap());
-    let from_block = var("FROM_BLOCK")
-        .expect("FROM_BLOCK not set")
-        .parse::<u64>()
-        .expect("Invalid FROM_BLOCK");
-    let delay_time = var("DELAY_TIME")
-        .ok()
-        .and_then(|val| val.parse::<u64>().ok())
-        .unwrap_or(1000);
-
-    let crawler = Crawler::new(
-        provider,
-        from_block,
-        delay_time,
-        FilterOption {
-            tx_hash_filter: args.tx_hash_filter,
-            block_hash_filter: args.block_hash_filter,
-        },
-    );
-    let _ = crawler.get_transactions().await;
+    init_tracing(&TracingConfig::from_env());
+    let config = Config::load(&args).unwrap_or_else(|e| {
+        error!("Invalid configuration: {e:#}");
+        process::exit(1);
+    });
+
+    let filter_options = FilterOption::from(&config.filter);
+
+    let checkpoint = config.checkpoint_file.as_ref().map(|path| {
+        Arc::new(Checkpoint::load(path).unwrap_or_else(|e| {
+            error!("{e:#}");
+            process::exit(1);
+        }))
+    });
+
+    let gaps = config.gap_file.as_ref().map(|path| {
+        Arc::new(GapList::load(path).unwrap_or_else(|e| {
+            error!("{e:#}");
+            process::exit(1);
+        }))
+    });
+
+    let mut crawlers = Vec::new();
+    let mut watchers = Vec::new();
+    for chain in &config.chains {
+        let provider = Arc::new(
+            get_provider(&chain.provider_url, &config.rpc)
+                .await
+                .unwrap_or_else(|e| {
+                    error!(chain.name = %chain.name, error = %e, "Failed to connect to provider");
+                    process::exit(1);
+                }),
+        );
+        let chain_id = provider
+            .get_chainid()
+            .await
+            .unwrap_or_else(|e| {
+                error!(chain.name = %chain.name, error = %e, "Failed to fetch chain id");
+                process::exit(1);
+            })
+            .as_u64();
+        info!(chain.name = %chain.name, chain.id = chain_id, "Crawling chain");
+        tokio::spawn(run_health_checks(
+            Arc::clone(&provider),
+            Duration::from_secs(config.rpc.health_check_secs),
+        ));
+
+        if config.mempool.enabled {
+            watchers.push(
+                MempoolWatcher::new(
+                    Arc::clone(&provider),
+                    chain_id,
+                    chain,
+                    &config.kafka,
+                    &config.mempool,
+                    filter_options.clone(),
+                )
+                .await
+                .unwrap_or_else(|e| {
+                    error!(chain.name = %chain.name, "{e:#}");
+                    process::exit(1);
+                }),
+            );
+        }
+
+        let crawler = Crawler::new(
+            provider,
+            chain_id,
+            chain,
+            &config.kafka,
+            filter_options.clone(),
+        )
+        .unwrap_or_else(|e| {
+            error!(chain.name = %chain.name, "{e:#}");
+            process::exit(1);
+        });
+        let crawler = crawler.with_retry(config.retry.clone());
+        let crawler = match &checkpoint {
+            Some(checkpoint) => crawler.with_checkpoint(Arc::clone(checkpoint)),
+            None => crawler,
+        };
+        crawlers.push(match &gaps {
+            Some(gaps) => crawler.with_gaps(Arc::clone(gaps), args.recrawl_gaps),
+            None => crawler,
+        });
+    }
+
+    let health_state = Arc::new(HealthState {
+        chains: crawlers.iter().map(Crawler::status).collect(),
+        max_rpc_silence_secs: config.health.max_rpc_silence_secs,
+        max_head_distance: config.health.max_head_distance,
+    });
+    tokio::spawn(health::serve(config.health.addr, health_state));
+
+    let shutdown = CancellationToken::new();
+    tokio::spawn(cancel_on_signal(shutdown.clone()));
+
+    let crawls = crawlers
+        .into_iter()
+        .map(|crawler| crawler.get_transactions(shutdown.clone()));
+    // Watchers only return once shutdown is requested.
+    let watches = watchers
+        .into_iter()
+        .map(|watcher| watcher.run(shutdown.clone()));
+    let (crawled, _) = tokio::join!(join_all(crawls), join_all(watches));
+    for result in crawled {
+        if let Err(e) = result {
+            error!(error = %e, "Crawler stopped");
+        }
+    }
 
     global::shutdown_tracer_provider();
 }
@@ -75,24 +202,36 @@ async fn main() {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use dotenv::dotenv;
-    use std::env;
-
-    #[test]
-    fn test_get_provider_success() {
-        dotenv().ok();
-        env::set_var("HTTP_PROVIDER", "http://localhost:8545");
-        let provider = get_provider();
+
+    #[tokio::test]
+    async fn test_get_provider_success() {
+        let provider = get_provider(
+            &["http://localhost:8545".to_string()],
+            &RpcConfig::default(),
+        )
+        .await;
         assert!(
             provider.is_ok(),
             "Provider should be successfully created with valid HTTP_PROVIDER."
         );
     }
 
-    #[test]
-    #[should_panic(expected = "HTTP_PROVIDER not set")]
-    fn test_get_provider_fail_missing_env() {
-        env::remove_var("HTTP_PROVIDER");
-        let _ = get_provider();
+    #[tokio::test]
+    async fn test_get_provider_fail_invalid_url() {
+        let provider = get_provider(&["not a url".to_string()], &RpcConfig::default()).await;
+        assert!(provider.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_get_provider_fail_unreachable_ws() {
+        let provider = get_provider(&["ws://127.0.0.1:1".to_string()], &RpcConfig::default()).await;
+        assert!(provider.is_err());
+
+        let urls = [
+            "ws://127.0.0.1:1".to_string(),
+            "http://localhost:8545".to_string(),
+        ];
+        let provider = get_provider(&urls, &RpcConfig::default()).await;
+        assert!(provider.is_ok(), "Unreachable endpoints are skipped");
     }
 }
@@ -0,0 +1,546 @@
+use crate::{
+    chain::ChainConfig,
+    config::{KafkaConfig, MempoolConfig},
+    filter::FilterOption,
+    kafka::{Event, KafkaProducer},
+    metrics::{KAFKA_SEND_FAILURES, PENDING_EVENTS_PUBLISHED, PENDING_TRACKED},
+    provider::{Endpoint, Transport},
+    tracer::inject_context,
+};
+use anyhow::{Context, Result};
+use ethers::prelude::{
+    providers::{JsonRpcClient, Middleware, Provider, ProviderError},
+    types::{Address, Transaction, H256, U256},
+};
+use futures::{future, stream, stream::FuturesUnordered, StreamExt};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::task::JoinHandle;
+use tokio::time::{interval, sleep, Duration, MissedTickBehavior};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, info_span, warn};
+
+/// Checks in a row a transaction must be unknown before it counts as
+/// dropped. Endpoints behind a load balancer do not share one mempool, so a
+/// single miss proves little.
+const DROP_AFTER_MISSES: u32 = 3;
+
+/// Tracked transactions looked up at the same time, and likewise hashes from
+/// a subscription fetched at the same time.
+const LOOKUP_CONCURRENCY: usize = 16;
+
+/// Hashes from a subscription waiting to be fetched. Past this many, new
+/// hashes are dropped until the fetches catch up.
+const MAX_QUEUED_HASHES: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PendingStatus {
+    /// Broadcast and waiting in the mempool.
+    Pending,
+    /// Included in a block.
+    Mined,
+    /// Its nonce was used by another transaction of the same sender.
+    Replaced,
+    /// Gone from the mempool without being mined.
+    Dropped,
+}
+
+impl PendingStatus {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Mined => "mined",
+            Self::Replaced => "replaced",
+            Self::Dropped => "dropped",
+        }
+    }
+}
+
+/// Payload published on the pending topic. The first event of a transaction
+/// carries it in full, later ones only its hash and new status.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PendingEvent<'a> {
+    pub status: PendingStatus,
+    pub hash: H256,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transaction: Option<&'a Transaction>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub block_number: Option<u64>,
+}
+
+/// A published pending transaction whose fate is not known yet.
+#[derive(Debug, Clone)]
+struct Tracked {
+    from: Address,
+    nonce: U256,
+    seen_at: Instant,
+    misses: u32,
+}
+
+/// Outcome of looking a tracked transaction up again.
+#[derive(Debug, PartialEq)]
+enum Lookup {
+    StillPending,
+    Mined(u64),
+    Replaced,
+    Missing,
+}
+
+async fn lookup(provider: &Provider<impl JsonRpcClient>, hash: H256, tracked: &Tracked) -> Lookup {
+    match provider.get_transaction(hash).await {
+        Ok(Some(tx)) => match tx.block_number {
+            Some(block_number) => Lookup::Mined(block_number.as_u64()),
+            None => Lookup::StillPending,
+        },
+        Ok(None) => match provider.get_transaction_count(tracked.from, None).await {
+            Ok(count) if count > tracked.nonce => Lookup::Replaced,
+            Ok(_) => Lookup::Missing,
+            Err(e) => {
+                debug!(tx.hash = ?hash, error = %e, "Failed to fetch sender nonce");
+                Lookup::StillPending
+            }
+        },
+        Err(e) => {
+            debug!(tx.hash = ?hash, error = %e, "Failed to look up pending transaction");
+            Lookup::StillPending
+        }
+    }
+}
+
+/// A tracked transaction as it was when the check started, with what the
+/// lookup found.
+type Checked = (H256, Tracked, Lookup);
+
+/// Looks each of `tracked` up again, `LOOKUP_CONCURRENCY` at a time.
+async fn lookup_all(
+    provider: Arc<Provider<impl JsonRpcClient>>,
+    tracked: Vec<(H256, Tracked)>,
+) -> Vec<Checked> {
+    stream::iter(tracked)
+        .map(|(hash, tracked)| {
+            let provider = &provider;
+            async move {
+                let found = lookup(provider, hash, &tracked).await;
+                (hash, tracked, found)
+            }
+        })
+        .buffer_unordered(LOOKUP_CONCURRENCY)
+        .collect()
+        .await
+}
+
+/// Fetches a hash announced by the subscription.
+async fn fetch_pending<T: JsonRpcClient>(
+    provider: Arc<Provider<T>>,
+    hash: H256,
+) -> (H256, Result<Option<Transaction>, ProviderError>) {
+    (hash, provider.get_transaction(hash).await)
+}
+
+/// Waits for the running check, if any, and clears it once it is done.
+async fn check_finished(check: &mut Option<JoinHandle<Vec<Checked>>>) -> Vec<Checked> {
+    let Some(handle) = check else {
+        return future::pending().await;
+    };
+    let result = handle.await;
+    *check = None;
+    result.unwrap_or_else(|e| {
+        error!(error = %e, "Pending transaction check failed");
+        Vec::new()
+    })
+}
+
+/// Watches the mempool of one chain and publishes the pending transactions
+/// that pass the transaction filter, followed by a `mined`, `replaced` or
+/// `dropped` event once each of them leaves the mempool.
+///
+/// Pending hashes come from an `eth_subscribe` subscription when the chain
+/// has a WebSocket or IPC endpoint, and from polling `txpool_content`
+/// otherwise. Every other request goes through the shared provider.
+/// Subscribed hashes are fetched `LOOKUP_CONCURRENCY` at a time, and tracked
+/// transactions are looked up again on a separate task, so new pending
+/// transactions keep being handled meanwhile.
+pub struct MempoolWatcher<T: JsonRpcClient> {
+    provider: Arc<Provider<T>>,
+    subscriber: Option<Provider<Transport>>,
+    chain_id: u64,
+    kafka_producer: KafkaProducer,
+    topic: String,
+    filter_options: FilterOption,
+    config: MempoolConfig,
+    tracked: HashMap<H256, Tracked>,
+    /// Hashes seen in `txpool_content` that did not pass the filter, so they
+    /// are not looked at again on every poll. Only hashes still in the pool
+    /// are kept, and a subscription never adds any.
+    ignored: HashSet<H256>,
+}
+
+impl<T: JsonRpcClient + 'static> MempoolWatcher<T> {
+    /// Subscribes through the first WebSocket or IPC endpoint of `chain`,
+    /// if any connects.
+    pub async fn new(
+        provider: Arc<Provider<T>>,
+        chain_id: u64,
+        chain: &ChainConfig,
+        kafka: &KafkaConfig,
+        config: &MempoolConfig,
+        filter_options: FilterOption,
+    ) -> Result<Self> {
+        let kafka_producer =
+            KafkaProducer::new(kafka.hosts.clone()).context("Failed to connect to Kafka")?;
+        let mut subscriber = None;
+        for endpoint in chain.provider_url.iter().filter_map(|url| url.parse().ok()) {
+            if matches!(endpoint, Endpoint::Http(_)) {
+                continue;
+            }
+            match Transport::connect(&endpoint).await {
+                Ok(transport) => {
+                    subscriber = Some(Provider::new(transport));
+                    break;
+                }
+                Err(e) => warn!(
+                    rpc.endpoint = %endpoint.label(),
+                    error = %e,
+                    "Failed to connect for pending transaction subscription"
+                ),
+            }
+        }
+
+        Ok(Self {
+            provider,
+            subscriber,
+            chain_id,
+            kafka_producer,
+            topic: format!("{}{}", chain.topic_prefix, kafka.pending_topic),
+            filter_options,
+            config: config.clone(),
+            tracked: HashMap::new(),
+            ignored: HashSet::new(),
+        })
+    }
+
+    /// Watches until `shutdown` is cancelled.
+    pub async fn run(mut self, shutdown: CancellationToken) {
+        let poll_interval = Duration::from_millis(self.config.poll_interval_ms);
+        let mut ticks = interval(poll_interval);
+        ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut check = None;
+
+        let Some(subscriber) = self.subscriber.take() else {
+            info!(
+                chain.id = self.chain_id,
+                "Watching mempool by polling txpool_content"
+            );
+            loop {
+                tokio::select! {
+                    _ = shutdown.cancelled() => return,
+                    _ = ticks.tick() => {
+                        self.poll_txpool().await;
+                        self.start_check(&mut check);
+                    }
+                    checked = check_finished(&mut check) => self.on_checked(checked),
+                }
+            }
+        };
+
+        info!(
+            chain.id = self.chain_id,
+            "Watching mempool through a subscription"
+        );
+        let mut queued = VecDeque::new();
+        let mut fetches = FuturesUnordered::new();
+        while !shutdown.is_cancelled() {
+            let mut hashes = match subscriber.subscribe_pending_txs().await {
+                Ok(hashes) => hashes,
+                Err(e) => {
+                    error!(
+                        chain.id = self.chain_id,
+                        error = %e,
+                        "Failed to subscribe to pending transactions"
+                    );
+                    tokio::select! {
+                        _ = shutdown.cancelled() => break,
+                        _ = sleep(poll_interval) => continue,
+                    }
+                }
+            };
+            loop {
+                while fetches.len() < LOOKUP_CONCURRENCY {
+                    let Some(hash) = queued.pop_front() else {
+                        break;
+                    };
+                    fetches.push(fetch_pending(Arc::clone(&self.provider), hash));
+                }
+                tokio::select! {
+                    _ = shutdown.cancelled() => break,
+                    hash = hashes.next() => match hash {
+                        Some(hash) => self.queue_hash(&mut queued, hash),
+                        None => {
+                            warn!(
+                                chain.id = self.chain_id,
+                                "Pending transaction subscription ended, resubscribing"
+                            );
+                            break;
+                        }
+                    },
+                    Some((hash, fetched)) = fetches.next(), if !fetches.is_empty() => {
+                        self.on_fetched(hash, fetched);
+                    }
+                    _ = ticks.tick() => self.start_check(&mut check),
+                    checked = check_finished(&mut check) => self.on_checked(checked),
+                }
+            }
+        }
+    }
+
+    /// Queues a subscribed hash to be fetched, unless it is tracked already
+    /// or too many are queued.
+    fn queue_hash(&self, queued: &mut VecDeque<H256>, hash: H256) {
+        if self.tracked.contains_key(&hash) {
+            return;
+        }
+        if queued.len() >= MAX_QUEUED_HASHES {
+            debug!(tx.hash = ?hash, "Dropping pending transaction hash");
+            return;
+        }
+        queued.push_back(hash);
+        if queued.len() == MAX_QUEUED_HASHES {
+            warn!(
+                chain.id = self.chain_id,
+                "Too many pending transaction hashes queued, dropping new ones"
+            );
+        }
+    }
+
+    fn on_fetched(&mut self, hash: H256, fetched: Result<Option<Transaction>, ProviderError>) {
+        match fetched {
+            Ok(Some(tx)) if tx.block_number.is_none() && !self.tracked.contains_key(&hash) => {
+                self.on_pending(tx);
+            }
+            Ok(_) => {}
+            Err(e) => debug!(tx.hash = ?hash, error = %e, "Failed to fetch pending transaction"),
+        }
+    }
+
+    async fn poll_txpool(&mut self) {
+        let content = match self.provider.txpool_content().await {
+            Ok(content) => content,
+            Err(e) => {
+                warn!(chain.id = self.chain_id, error = %e, "Failed to poll txpool_content");
+                return;
+            }
+        };
+        let pending: Vec<Transaction> = content
+            .pending
+            .into_values()
+            .flat_map(|by_nonce| by_nonce.into_values())
+            .collect();
+        let current: HashSet<H256> = pending.iter().map(|tx| tx.hash).collect();
+        self.ignored.retain(|hash| current.contains(hash));
+        for tx in pending {
+            let hash = tx.hash;
+            if !self.tracked.contains_key(&hash)
+                && !self.ignored.contains(&hash)
+                && !self.on_pending(tx)
+            {
+                self.ignored.insert(hash);
+            }
+        }
+    }
+
+    /// Publishes and tracks `tx` if it passes the filter. Returns whether it
+    /// did.
+    fn on_pending(&mut self, tx: Transaction) -> bool {
+        if !self.filter_options.accepts_transaction(&tx, self.chain_id) {
+            return false;
+        }
+        if self.tracked.len() >= self.config.max_tracked {
+            warn!(tx.hash = ?tx.hash, "Too many pending transactions tracked, skipping");
+            return true;
+        }
+        self.publish(PendingEvent {
+            status: PendingStatus::Pending,
+            hash: tx.hash,
+            transaction: Some(&tx),
+            block_number: None,
+        });
+        self.tracked.insert(
+            tx.hash,
+            Tracked {
+                from: tx.from,
+                nonce: tx.nonce,
+                seen_at: Instant::now(),
+                misses: 0,
+            },
+        );
+        self.update_tracked_metric();
+        true
+    }
+
+    /// Starts looking every tracked transaction up again on its own task,
+    /// unless the previous check is still running.
+    fn start_check(&self, check: &mut Option<JoinHandle<Vec<Checked>>>) {
+        if check.is_some() || self.tracked.is_empty() {
+            return;
+        }
+        let tracked = self
+            .tracked
+            .iter()
+            .map(|(hash, tracked)| (*hash, tracked.clone()))
+            .collect();
+        *check = Some(tokio::spawn(lookup_all(
+            Arc::clone(&self.provider),
+            tracked,
+        )));
+    }
+
+    /// Publishes the checked transactions that left the mempool.
+    fn on_checked(&mut self, checked: Vec<Checked>) {
+        let max_age = Duration::from_secs(self.config.max_pending_secs);
+        for (hash, tracked, found) in checked {
+            let (status, block_number) = match found {
+                Lookup::Mined(block_number) => (PendingStatus::Mined, Some(block_number)),
+                Lookup::Replaced => (PendingStatus::Replaced, None),
+                Lookup::Missing if tracked.misses + 1 >= DROP_AFTER_MISSES => {
+                    (PendingStatus::Dropped, None)
+                }
+                Lookup::Missing => {
+                    if let Some(entry) = self.tracked.get_mut(&hash) {
+                        entry.misses += 1;
+                    }
+                    continue;
+                }
+                Lookup::StillPending if tracked.seen_at.elapsed() > max_age => {
+                    (PendingStatus::Dropped, None)
+                }
+                Lookup::StillPending => continue,
+            };
+            self.tracked.remove(&hash);
+            self.publish(PendingEvent {
+                status,
+                hash,
+                transaction: None,
+                block_number,
+            });
+        }
+        self.update_tracked_metric();
+    }
+
+    fn publish(&self, pending: PendingEvent) {
+        let send_span = info_span!(
+            "kafka_send_message",
+            messaging.destination.name = %self.topic,
+            tx.hash = ?pending.hash,
+            tx.status = pending.status.as_str(),
+        );
+        let _enter = send_span.enter();
+        let event = Event {
+            chain_id: self.chain_id,
+            headers: inject_context(&send_span),
+            data: &pending,
+        };
+        match self.kafka_producer.send_message(&self.topic, &event) {
+            Ok(()) => {
+                PENDING_EVENTS_PUBLISHED
+                    .with_label_values(&[&self.chain_id.to_string(), pending.status.as_str()])
+                    .inc();
+                debug!(
+                    tx.hash = ?pending.hash,
+                    status = pending.status.as_str(),
+                    "Sent pending transaction event"
+                );
+            }
+            Err(e) => {
+                KAFKA_SEND_FAILURES.with_label_values(&[&self.topic]).inc();
+                error!(
+                    tx.hash = ?pending.hash,
+                    topic = %self.topic,
+                    error = ?e,
+                    "Failed to send pending transaction event"
+                );
+            }
+        }
+    }
+
+    fn update_tracked_metric(&self) {
+        PENDING_TRACKED
+            .with_label_values(&[&self.chain_id.to_string()])
+            .set(self.tracked.len() as i64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::setup_provider;
+    use ethers::prelude::{providers::MockProvider, types::U64};
+    use serde_json::json;
+
+    fn tracked(nonce: u64) -> Tracked {
+        Tracked {
+            from: Address::zero(),
+            nonce: nonce.into(),
+            seen_at: Instant::now(),
+            misses: 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_lookup() {
+        let mock = MockProvider::new();
+        let provider = setup_provider(mock.clone());
+
+        mock.push(Transaction {
+            block_number: Some(U64::from(12)),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(
+            lookup(&provider, H256::zero(), &tracked(0)).await,
+            Lookup::Mined(12)
+        );
+
+        mock.push(Transaction::default()).unwrap();
+        assert_eq!(
+            lookup(&provider, H256::zero(), &tracked(0)).await,
+            Lookup::StillPending
+        );
+
+        // Responses are popped from the back: no transaction, then the nonce.
+        mock.push(U256::from(5)).unwrap();
+        mock.push(Option::<Transaction>::None).unwrap();
+        assert_eq!(
+            lookup(&provider, H256::zero(), &tracked(4)).await,
+            Lookup::Replaced
+        );
+
+        mock.push(U256::from(4)).unwrap();
+        mock.push(Option::<Transaction>::None).unwrap();
+        assert_eq!(
+            lookup(&provider, H256::zero(), &tracked(4)).await,
+            Lookup::Missing
+        );
+    }
+
+    #[test]
+    fn test_pending_event_payload() {
+        let event = PendingEvent {
+            status: PendingStatus::Mined,
+            hash: H256::zero(),
+            transaction: None,
+            block_number: Some(12),
+        };
+        assert_eq!(
+            serde_json::to_value(&event).unwrap(),
+            json!({
+                "status": "mined",
+                "hash": format!("{:?}", H256::zero()),
+                "blockNumber": 12
+            })
+        );
+    }
+}
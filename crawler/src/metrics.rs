@@ -0,0 +1,168 @@
+use axum::http::{header, StatusCode};
+use prometheus::{
+    register_gauge_vec, register_histogram_vec, register_int_counter_vec, register_int_gauge_vec,
+    Encoder, GaugeVec, HistogramVec, IntCounterVec, IntGaugeVec, TextEncoder,
+};
+use std::sync::LazyLock;
+
+pub static BLOCKS_PUBLISHED: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "crawler_blocks_published_total",
+        "Blocks sent to Kafka",
+        &["chain_id"]
+    )
+    .unwrap()
+});
+
+pub static TRANSACTIONS_PUBLISHED: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "crawler_transactions_published_total",
+        "Transactions sent to Kafka",
+        &["chain_id"]
+    )
+    .unwrap()
+});
+
+pub static PENDING_EVENTS_PUBLISHED: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "crawler_pending_events_published_total",
+        "Pending transaction events sent to Kafka, by status",
+        &["chain_id", "status"]
+    )
+    .unwrap()
+});
+
+pub static PENDING_TRACKED: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register_int_gauge_vec!(
+        "crawler_pending_tracked",
+        "Published pending transactions not yet mined, replaced or dropped",
+        &["chain_id"]
+    )
+    .unwrap()
+});
+
+pub static RPC_ERRORS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "crawler_rpc_errors_total",
+        "Failed JSON-RPC calls",
+        &["method"]
+    )
+    .unwrap()
+});
+
+pub static RPC_LATENCY: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "crawler_rpc_latency_seconds",
+        "JSON-RPC call latency",
+        &["method"]
+    )
+    .unwrap()
+});
+
+pub static RPC_ENDPOINT_REQUESTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "crawler_rpc_endpoint_requests_total",
+        "JSON-RPC calls per endpoint, by outcome",
+        &["endpoint", "outcome"]
+    )
+    .unwrap()
+});
+
+pub static RPC_ENDPOINT_LATENCY: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "crawler_rpc_endpoint_latency_seconds",
+        "JSON-RPC call latency per endpoint",
+        &["endpoint"]
+    )
+    .unwrap()
+});
+
+pub static RPC_ENDPOINT_HEAD: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register_int_gauge_vec!(
+        "crawler_rpc_endpoint_head_block",
+        "Latest block reported by each endpoint's last health check",
+        &["endpoint"]
+    )
+    .unwrap()
+});
+
+pub static RPC_ENDPOINT_HEALTHY: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register_int_gauge_vec!(
+        "crawler_rpc_endpoint_healthy",
+        "1 while an endpoint is in rotation, 0 while it is skipped",
+        &["endpoint"]
+    )
+    .unwrap()
+});
+
+pub static RPC_RATE_LIMITED: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "crawler_rpc_rate_limited_total",
+        "JSON-RPC calls rejected by an endpoint's rate limit",
+        &["endpoint"]
+    )
+    .unwrap()
+});
+
+pub static RPC_RATE_LIMIT_FACTOR: LazyLock<GaugeVec> = LazyLock::new(|| {
+    register_gauge_vec!(
+        "crawler_rpc_rate_limit_factor",
+        "Share of the configured request budget an endpoint currently gets",
+        &["endpoint"]
+    )
+    .unwrap()
+});
+
+pub static HEAD_DISTANCE: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register_int_gauge_vec!(
+        "crawler_head_distance_blocks",
+        "Blocks between the last crawled block and the chain head",
+        &["chain_id"]
+    )
+    .unwrap()
+});
+
+pub static RPC_RETRIES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "crawler_rpc_retries_total",
+        "JSON-RPC calls repeated after an error or an empty result",
+        &["method"]
+    )
+    .unwrap()
+});
+
+pub static GAP_BLOCKS: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register_int_gauge_vec!(
+        "crawler_gap_blocks",
+        "Blocks that failed to crawl and wait in the gap list",
+        &["chain_id"]
+    )
+    .unwrap()
+});
+
+pub static KAFKA_SEND_FAILURES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "crawler_kafka_send_failures_total",
+        "Messages that could not be sent to Kafka",
+        &["topic"]
+    )
+    .unwrap()
+});
+
+/// Renders every registered metric in the Prometheus text format.
+pub(crate) async fn metrics_handler() -> (StatusCode, [(header::HeaderName, String); 1], Vec<u8>) {
+    let encoder = TextEncoder::new();
+    let mut buffer = Vec::new();
+    match encoder.encode(&prometheus::gather(), &mut buffer) {
+        Ok(()) => (
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, encoder.format_type().to_string())],
+            buffer,
+        ),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            [(header::CONTENT_TYPE, "text/plain".to_string())],
+            e.to_string().into_bytes(),
+        ),
+    }
+}
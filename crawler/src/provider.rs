@@ -0,0 +1,246 @@
+use anyhow::bail;
+use async_trait::async_trait;
+use ethers::prelude::{
+    providers::{Http, Ipc, IpcError, JsonRpcClient, ProviderError, PubsubClient, Ws},
+    types::U256,
+};
+use futures::channel::mpsc::UnboundedReceiver;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::value::RawValue;
+use std::fmt::Debug;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::RwLock;
+use tracing::warn;
+use url::Url;
+
+/// How many times a dropped WebSocket is reconnected before requests fail.
+const WS_RECONNECTS: usize = usize::MAX;
+
+/// A JSON-RPC endpoint, told apart by its scheme: `http(s)://`, `ws(s)://`
+/// or `ipc://` followed by the socket path.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Endpoint {
+    Http(Url),
+    Ws(Url),
+    Ipc(PathBuf),
+}
+
+impl FromStr for Endpoint {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        if let Some(path) = s.strip_prefix("ipc://") {
+            if path.is_empty() {
+                bail!("{s} has no socket path");
+            }
+            return Ok(Self::Ipc(PathBuf::from(path)));
+        }
+        let url = Url::parse(s)?;
+        match url.scheme() {
+            "http" | "https" => Ok(Self::Http(url)),
+            "ws" | "wss" => Ok(Self::Ws(url)),
+            scheme => bail!("unsupported scheme {scheme}, use http, https, ws, wss or ipc"),
+        }
+    }
+}
+
+impl Endpoint {
+    /// Short name for logs and metric labels. Only the host and port of a
+    /// URL are kept, since paths and queries often carry API keys.
+    pub fn label(&self) -> String {
+        match self {
+            Self::Http(url) | Self::Ws(url) => match (url.host_str(), url.port()) {
+                (Some(host), Some(port)) => format!("{host}:{port}"),
+                (Some(host), None) => host.to_string(),
+                (None, _) => url.scheme().to_string(),
+            },
+            Self::Ipc(path) => path.display().to_string(),
+        }
+    }
+}
+
+/// The transport of one endpoint. WebSocket and IPC connections are
+/// re-established when they drop, and only they serve `eth_subscribe`.
+/// Rate limits are left to `RateLimited`.
+#[derive(Debug)]
+pub enum Transport {
+    Http(Http),
+    Ws(Ws),
+    Ipc(ReconnectingIpc),
+}
+
+impl Transport {
+    pub async fn connect(endpoint: &Endpoint) -> Result<Self, ProviderError> {
+        Ok(match endpoint {
+            Endpoint::Http(url) => Self::Http(Http::new(url.clone())),
+            Endpoint::Ws(url) => {
+                Self::Ws(Ws::connect_with_reconnects(url.as_str(), WS_RECONNECTS).await?)
+            }
+            Endpoint::Ipc(path) => Self::Ipc(ReconnectingIpc::connect(path.clone()).await?),
+        })
+    }
+}
+
+#[async_trait]
+impl JsonRpcClient for Transport {
+    type Error = ProviderError;
+
+    async fn request<T, R>(&self, method: &str, params: T) -> Result<R, ProviderError>
+    where
+        T: Debug + Serialize + Send + Sync,
+        R: DeserializeOwned + Send,
+    {
+        match self {
+            Self::Http(client) => client.request(method, params).await.map_err(Into::into),
+            Self::Ws(client) => client.request(method, params).await.map_err(Into::into),
+            Self::Ipc(client) => client.request(method, params).await,
+        }
+    }
+}
+
+impl PubsubClient for Transport {
+    type NotificationStream = UnboundedReceiver<Box<RawValue>>;
+
+    fn subscribe<T: Into<U256>>(&self, id: T) -> Result<Self::NotificationStream, ProviderError> {
+        match self {
+            Self::Http(_) => Err(ProviderError::UnsupportedRPC),
+            Self::Ws(client) => client.subscribe(id).map_err(Into::into),
+            Self::Ipc(client) => client.current().subscribe(id).map_err(Into::into),
+        }
+    }
+
+    fn unsubscribe<T: Into<U256>>(&self, id: T) -> Result<(), ProviderError> {
+        match self {
+            Self::Http(_) => Err(ProviderError::UnsupportedRPC),
+            Self::Ws(client) => client.unsubscribe(id).map_err(Into::into),
+            Self::Ipc(client) => client.current().unsubscribe(id).map_err(Into::into),
+        }
+    }
+}
+
+/// IPC client that reconnects to the socket when the node goes away, for
+/// example while it restarts. The WebSocket client does this by itself.
+#[derive(Debug)]
+pub struct ReconnectingIpc {
+    path: PathBuf,
+    ipc: RwLock<Ipc>,
+}
+
+impl ReconnectingIpc {
+    async fn connect(path: PathBuf) -> Result<Self, IpcError> {
+        let ipc = Ipc::connect(&path).await?;
+        Ok(Self {
+            path,
+            ipc: RwLock::new(ipc),
+        })
+    }
+
+    fn current(&self) -> Ipc {
+        self.ipc.read().unwrap().clone()
+    }
+
+    async fn request<T, R>(&self, method: &str, params: T) -> Result<R, ProviderError>
+    where
+        T: Debug + Serialize + Send + Sync,
+        R: DeserializeOwned + Send,
+    {
+        let params = serde_json::to_value(params)?;
+        match self.current().request(method, &params).await {
+            Err(e) if is_disconnect(&e) => {
+                warn!(path = %self.path.display(), error = %e, "IPC connection lost, reconnecting");
+                let ipc = Ipc::connect(&self.path).await?;
+                *self.ipc.write().unwrap() = ipc.clone();
+                ipc.request(method, &params).await.map_err(Into::into)
+            }
+            result => result.map_err(Into::into),
+        }
+    }
+}
+
+fn is_disconnect(error: &IpcError) -> bool {
+    matches!(
+        error,
+        IpcError::ServerExit
+            | IpcError::ChannelError(_)
+            | IpcError::RequestCancelled(_)
+            | IpcError::IoError(_)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::prelude::providers::{Middleware, Provider};
+    use std::env;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::UnixListener;
+
+    #[test]
+    fn test_endpoint_from_str() {
+        assert!(matches!(
+            "https://rpc.example.org".parse::<Endpoint>().unwrap(),
+            Endpoint::Http(_)
+        ));
+        assert!(matches!(
+            "wss://rpc.example.org/ws".parse::<Endpoint>().unwrap(),
+            Endpoint::Ws(_)
+        ));
+        assert_eq!(
+            "ipc:///tmp/geth.ipc".parse::<Endpoint>().unwrap(),
+            Endpoint::Ipc(PathBuf::from("/tmp/geth.ipc"))
+        );
+        assert!("ftp://rpc.example.org".parse::<Endpoint>().is_err());
+        assert!("ipc://".parse::<Endpoint>().is_err());
+        assert!("not a url".parse::<Endpoint>().is_err());
+    }
+
+    #[test]
+    fn test_endpoint_label_hides_path() {
+        let endpoint: Endpoint = "https://eth.example.org/v2/secret-key".parse().unwrap();
+        assert_eq!(endpoint.label(), "eth.example.org");
+        let endpoint: Endpoint = "ws://localhost:8546".parse().unwrap();
+        assert_eq!(endpoint.label(), "localhost:8546");
+    }
+
+    #[tokio::test]
+    async fn test_ipc_reconnects_after_drop() {
+        let path = env::temp_dir().join(format!("crawler-test-{}.ipc", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+
+        // Answers one request per connection, then hangs up.
+        let server = tokio::spawn(async move {
+            for chain_id in ["0x1", "0x2"] {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                // Requests are not newline delimited, read until one parses.
+                let mut buf = Vec::new();
+                let request: serde_json::Value = loop {
+                    let mut chunk = [0; 1024];
+                    let n = stream.read(&mut chunk).await.unwrap();
+                    buf.extend_from_slice(&chunk[..n]);
+                    if let Ok(request) = serde_json::from_slice(&buf) {
+                        break request;
+                    }
+                };
+                let response = serde_json::json!({
+                    "jsonrpc": "2.0",
+                    "id": request["id"],
+                    "result": chain_id,
+                });
+                stream
+                    .write_all(response.to_string().as_bytes())
+                    .await
+                    .unwrap();
+            }
+        });
+
+        let endpoint = Endpoint::Ipc(path.clone());
+        let provider = Provider::new(Transport::connect(&endpoint).await.unwrap());
+        assert_eq!(provider.get_chainid().await.unwrap(), 1.into());
+        assert_eq!(provider.get_chainid().await.unwrap(), 2.into());
+
+        server.await.unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,258 @@
+use crate::config::RpcConfig;
+use crate::metrics::{RPC_RATE_LIMITED, RPC_RATE_LIMIT_FACTOR};
+use async_trait::async_trait;
+use ethers::prelude::providers::{JsonRpcClient, JsonRpcError, ProviderError, RpcError};
+use serde::{de::DeserializeOwned, Serialize};
+use std::fmt::Debug;
+use std::sync::Mutex;
+use tokio::time::{sleep, Duration, Instant};
+use tracing::warn;
+
+/// How often a rate-limited call is repeated before its error is returned.
+const RATE_LIMIT_RETRIES: u32 = 10;
+/// The budget never drops below this share of the configured one.
+const MIN_FACTOR: f64 = 1.0 / 64.0;
+/// Share of the configured budget won back by every successful call.
+const RECOVERY_STEP: f64 = 0.02;
+
+/// Compute units charged per method, as published by Alchemy. Other
+/// providers price calls differently but in the same proportions.
+fn compute_units(method: &str) -> f64 {
+    match method {
+        "eth_chainId" | "net_version" => 0.0,
+        "eth_blockNumber" | "eth_subscribe" | "eth_unsubscribe" => 10.0,
+        "eth_getTransactionReceipt" => 15.0,
+        "eth_getBlockByNumber" | "eth_getBlockByHash" => 16.0,
+        "eth_getTransactionByHash" => 17.0,
+        "eth_newPendingTransactionFilter" | "eth_getFilterChanges" => 20.0,
+        _ => 26.0,
+    }
+}
+
+/// Whether `error` means the endpoint wants fewer calls, as opposed to the
+/// call itself failing.
+fn is_rate_limited(error: &ProviderError) -> bool {
+    if let Some(JsonRpcError { code, .. }) = error.as_error_response() {
+        // 429 is used by Alchemy, -32005 by Infura and -32029 by QuickNode.
+        if matches!(code, 429 | -32005 | -32029) {
+            return true;
+        }
+    }
+    let message = error.to_string().to_lowercase();
+    [
+        "rate limit",
+        "too many requests",
+        "compute units",
+        "request limit",
+    ]
+    .iter()
+    .any(|pattern| message.contains(pattern))
+}
+
+#[derive(Debug)]
+struct Bucket {
+    requests: f64,
+    compute_units: f64,
+    refilled_at: Instant,
+    /// Share of the configured budget currently allowed, halved on every
+    /// rate-limit error and raised again by successful calls.
+    factor: f64,
+}
+
+/// Paces the calls of one endpoint to a requests per second budget and,
+/// optionally, a compute units per second budget.
+///
+/// Both budgets are token buckets holding at most one second of calls. A
+/// rate-limit error halves the budget and empties the buckets before the
+/// call is repeated; every successful call restores a little of it.
+#[derive(Debug)]
+pub struct RateLimited<C> {
+    label: String,
+    client: C,
+    requests_per_second: f64,
+    compute_units_per_second: Option<f64>,
+    bucket: Mutex<Bucket>,
+}
+
+impl<C: JsonRpcClient> RateLimited<C>
+where
+    C::Error: Into<ProviderError>,
+{
+    pub fn new(label: String, client: C, config: &RpcConfig) -> Self {
+        RPC_RATE_LIMIT_FACTOR.with_label_values(&[&label]).set(1.0);
+        Self {
+            label,
+            client,
+            requests_per_second: config.requests_per_second,
+            compute_units_per_second: config.compute_units_per_second,
+            bucket: Mutex::new(Bucket {
+                requests: config.requests_per_second,
+                compute_units: config.compute_units_per_second.unwrap_or_default(),
+                refilled_at: Instant::now(),
+                factor: 1.0,
+            }),
+        }
+    }
+
+    /// Takes one request and `cost` compute units from the buckets, waiting
+    /// until they have refilled enough.
+    async fn acquire(&self, cost: f64) {
+        loop {
+            let wait = {
+                let mut bucket = self.bucket.lock().unwrap();
+                let now = Instant::now();
+                let elapsed = now.duration_since(bucket.refilled_at).as_secs_f64();
+                bucket.refilled_at = now;
+
+                let request_rate = self.requests_per_second * bucket.factor;
+                bucket.requests =
+                    (bucket.requests + elapsed * request_rate).min(request_rate.max(1.0));
+                let mut wait = (1.0 - bucket.requests).max(0.0) / request_rate;
+
+                if let Some(cu_per_second) = self.compute_units_per_second {
+                    let cu_rate = cu_per_second * bucket.factor;
+                    bucket.compute_units =
+                        (bucket.compute_units + elapsed * cu_rate).min(cu_rate.max(cost));
+                    wait = wait.max((cost - bucket.compute_units).max(0.0) / cu_rate);
+                }
+
+                if wait == 0.0 {
+                    bucket.requests -= 1.0;
+                    if self.compute_units_per_second.is_some() {
+                        bucket.compute_units -= cost;
+                    }
+                    return;
+                }
+                wait
+            };
+            sleep(Duration::from_secs_f64(wait)).await;
+        }
+    }
+
+    fn adjust(&self, rate_limited: bool) {
+        let mut bucket = self.bucket.lock().unwrap();
+        if rate_limited {
+            bucket.factor = (bucket.factor / 2.0).max(MIN_FACTOR);
+            bucket.requests = 0.0;
+            bucket.compute_units = 0.0;
+        } else if bucket.factor < 1.0 {
+            bucket.factor = (bucket.factor + RECOVERY_STEP).min(1.0);
+        } else {
+            return;
+        }
+        RPC_RATE_LIMIT_FACTOR
+            .with_label_values(&[&self.label])
+            .set(bucket.factor);
+    }
+}
+
+#[async_trait]
+impl<C: JsonRpcClient> JsonRpcClient for RateLimited<C>
+where
+    C::Error: Into<ProviderError>,
+{
+    type Error = ProviderError;
+
+    async fn request<T, R>(&self, method: &str, params: T) -> Result<R, ProviderError>
+    where
+        T: Debug + Serialize + Send + Sync,
+        R: DeserializeOwned + Send,
+    {
+        let params = serde_json::to_value(params)?;
+        let cost = compute_units(method);
+        let mut retries = 0;
+        loop {
+            self.acquire(cost).await;
+            match self
+                .client
+                .request(method, &params)
+                .await
+                .map_err(Into::into)
+            {
+                Err(e) if is_rate_limited(&e) && retries < RATE_LIMIT_RETRIES => {
+                    retries += 1;
+                    RPC_RATE_LIMITED.with_label_values(&[&self.label]).inc();
+                    self.adjust(true);
+                    warn!(
+                        rpc.endpoint = %self.label,
+                        rpc.method = method,
+                        error = %e,
+                        "Rate limited, slowing down"
+                    );
+                }
+                result => {
+                    if result.is_ok() {
+                        self.adjust(false);
+                    }
+                    return result;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::prelude::{
+        providers::{MockProvider, MockResponse},
+        types::U64,
+    };
+
+    fn config(requests_per_second: f64) -> RpcConfig {
+        RpcConfig {
+            requests_per_second,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_is_rate_limited() {
+        let error = |code, message: &str| {
+            ProviderError::JsonRpcClientError(Box::new(ethers::providers::MockError::JsonRpcError(
+                JsonRpcError {
+                    code,
+                    message: message.to_string(),
+                    data: None,
+                },
+            )))
+        };
+        assert!(is_rate_limited(&error(429, "")));
+        assert!(is_rate_limited(&error(-32005, "limit exceeded")));
+        assert!(is_rate_limited(&error(-32000, "Too Many Requests")));
+        assert!(!is_rate_limited(&error(-32000, "header not found")));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_paces_requests() {
+        let mock = MockProvider::new();
+        for _ in 0..4 {
+            mock.push(U64::from(1)).unwrap();
+        }
+        let client = RateLimited::new("paced".to_string(), mock, &config(2.0));
+
+        // The bucket starts full with two requests, the others wait half a
+        // second each.
+        let start = Instant::now();
+        for _ in 0..4 {
+            let _: U64 = client.request("eth_blockNumber", ()).await.unwrap();
+        }
+        assert_eq!(start.elapsed().as_millis(), 1000);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_backs_off_when_rate_limited() {
+        let mock = MockProvider::new();
+        mock.push(U64::from(1)).unwrap();
+        mock.push_response(MockResponse::Error(JsonRpcError {
+            code: 429,
+            message: "Your app has exceeded its compute units per second capacity".to_string(),
+            data: None,
+        }));
+        let client = RateLimited::new("limited".to_string(), mock, &config(10.0));
+
+        let _: U64 = client.request("eth_blockNumber", ()).await.unwrap();
+        let factor = client.bucket.lock().unwrap().factor;
+        assert_eq!(factor, 0.5 + RECOVERY_STEP);
+    }
+}
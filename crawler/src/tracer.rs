@@ -1,36 +1,221 @@
 use opentelemetry::{
-    global::{self, BoxedSpan},
-    trace::{Span, TraceError, Tracer},
-    KeyValue,
+    global, propagation::TextMapPropagator, trace::TraceError, trace::TracerProvider as _, KeyValue,
 };
-use opentelemetry_sdk::trace::TracerProvider;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{Sampler, Tracer, TracerProvider};
 use opentelemetry_sdk::{runtime, trace as sdktrace, Resource};
 use opentelemetry_semantic_conventions::resource::SERVICE_NAME;
+use std::collections::HashMap;
+use std::env;
+use tracing::{info, warn, Span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer};
 
-pub fn init_tracer_provider() -> Result<(), TraceError> {
-    let exporter = opentelemetry_otlp::new_exporter()
-        .tonic()
-        .build_span_exporter()?;
+const TRACER_NAME: &str = "crawler";
+const DEFAULT_SERVICE_NAME: &str = "crawler";
 
-    let tracer = TracerProvider::builder()
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OtlpProtocol {
+    Grpc,
+    Http,
+}
+
+/// Span export settings, read from the standard `OTEL_*` variables.
+#[derive(Debug, Clone)]
+pub struct TracingConfig {
+    /// `false` when `OTEL_TRACES_EXPORTER=none`: logs are still written but
+    /// no spans leave the process.
+    pub enabled: bool,
+    /// Collector URL; the exporter's default for `protocol` when unset.
+    pub endpoint: Option<String>,
+    pub protocol: OtlpProtocol,
+    pub service_name: String,
+    pub resource_attributes: Vec<KeyValue>,
+    /// Share of new traces to record. Traces continued from a parent keep the
+    /// parent's decision.
+    pub sample_ratio: f64,
+}
+
+impl TracingConfig {
+    pub fn from_env() -> Self {
+        Self::from_vars(|key| env::var(key).ok())
+    }
+
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Self {
+        let protocol = match var("OTEL_EXPORTER_OTLP_PROTOCOL").as_deref() {
+            None | Some("grpc") => OtlpProtocol::Grpc,
+            Some("http/protobuf") => OtlpProtocol::Http,
+            Some(other) => panic!("Unsupported OTEL_EXPORTER_OTLP_PROTOCOL {}", other),
+        };
+        Self {
+            enabled: var("OTEL_TRACES_EXPORTER").is_none_or(|exporter| exporter != "none"),
+            endpoint: var("OTEL_EXPORTER_OTLP_ENDPOINT"),
+            protocol,
+            service_name: var("OTEL_SERVICE_NAME")
+                .unwrap_or_else(|| DEFAULT_SERVICE_NAME.to_string()),
+            resource_attributes: var("OTEL_RESOURCE_ATTRIBUTES")
+                .map(|attributes| parse_resource_attributes(&attributes))
+                .unwrap_or_default(),
+            sample_ratio: var("OTEL_TRACES_SAMPLER_ARG")
+                .and_then(|val| val.parse::<f64>().ok())
+                .unwrap_or(1.0),
+        }
+    }
+}
+
+/// Parses `key=value,key=value` pairs, skipping malformed entries.
+fn parse_resource_attributes(attributes: &str) -> Vec<KeyValue> {
+    attributes
+        .split(',')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(key, value)| KeyValue::new(key.trim().to_string(), value.trim().to_string()))
+        .collect()
+}
+
+pub fn init_tracer_provider(config: &TracingConfig) -> Result<Tracer, TraceError> {
+    let exporter = match config.protocol {
+        OtlpProtocol::Grpc => {
+            let mut exporter = opentelemetry_otlp::new_exporter().tonic();
+            if let Some(endpoint) = &config.endpoint {
+                exporter = exporter.with_endpoint(endpoint);
+            }
+            exporter.build_span_exporter()?
+        }
+        OtlpProtocol::Http => {
+            let mut exporter = opentelemetry_otlp::new_exporter().http();
+            if let Some(endpoint) = &config.endpoint {
+                exporter =
+                    exporter.with_endpoint(format!("{}/v1/traces", endpoint.trim_end_matches('/')));
+            }
+            exporter.build_span_exporter()?
+        }
+    };
+
+    let mut resource = vec![KeyValue::new(SERVICE_NAME, config.service_name.clone())];
+    resource.extend(config.resource_attributes.iter().cloned());
+    let provider = TracerProvider::builder()
         .with_batch_exporter(exporter, runtime::Tokio)
         .with_config(
-            sdktrace::Config::default().with_resource(Resource::new(vec![KeyValue::new(
-                SERVICE_NAME,
-                "tracing-jaeger",
-            )])),
+            sdktrace::Config::default()
+                .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+                    config.sample_ratio,
+                ))))
+                .with_resource(Resource::new(resource)),
         )
         .build();
-    global::set_tracer_provider(tracer);
-    Ok(())
+    let tracer = provider.tracer(TRACER_NAME);
+    global::set_tracer_provider(provider);
+    Ok(tracer)
 }
 
-pub fn start_span(name: &str) -> BoxedSpan {
-    let tracer = global::tracer("my_service");
-    let span_name = name.to_string();
-    tracer.start(span_name)
+/// Sets up span export per `config` and installs the global `tracing`
+/// subscriber. A broken exporter setup is logged instead of stopping startup.
+pub fn init_tracing(config: &TracingConfig) {
+    let (tracer, error) = if config.enabled {
+        match init_tracer_provider(config) {
+            Ok(tracer) => (Some(tracer), None),
+            Err(e) => (None, Some(e)),
+        }
+    } else {
+        (None, None)
+    };
+    init_subscriber(tracer);
+    match error {
+        Some(e) => warn!(error = %e, "Failed to set up span export, tracing disabled"),
+        None if !config.enabled => info!("Span export disabled"),
+        None => {}
+    }
 }
 
-pub fn end_span(mut span: BoxedSpan) {
-    span.end();
+/// Installs the global `tracing` subscriber: log lines filtered by `RUST_LOG`
+/// (default `info`), printed as JSON when `LOG_FORMAT=json`, and, when span
+/// export is on, every span exported through `tracer` so logs carry the ids
+/// of the trace they belong to.
+fn init_subscriber(tracer: Option<Tracer>) {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let fmt = match env::var("LOG_FORMAT").as_deref() {
+        Ok("json") => tracing_subscriber::fmt::layer()
+            .json()
+            .with_current_span(true)
+            .boxed(),
+        _ => tracing_subscriber::fmt::layer().boxed(),
+    };
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt)
+        .with(tracer.map(|tracer| tracing_opentelemetry::layer().with_tracer(tracer)))
+        .init();
+}
+
+/// Serializes `span` as W3C trace context (`traceparent`, `tracestate`) so
+/// the consumer can continue the trace.
+pub fn inject_context(span: &Span) -> HashMap<String, String> {
+    let mut headers = HashMap::new();
+    TraceContextPropagator::new().inject_context(&span.context(), &mut headers);
+    headers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::trace::{
+        SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState,
+    };
+    use opentelemetry::Context;
+    use tracing_subscriber::Registry;
+
+    #[test]
+    fn test_inject_context() {
+        let subscriber = Registry::default().with(tracing_opentelemetry::layer());
+        let _guard = tracing::subscriber::set_default(subscriber);
+        let span_context = SpanContext::new(
+            TraceId::from_hex("4bf92f3577b34da6a3ce929d0e0e4736").unwrap(),
+            SpanId::from_hex("00f067aa0ba902b7").unwrap(),
+            TraceFlags::SAMPLED,
+            false,
+            TraceState::default(),
+        );
+        let span = tracing::info_span!("test");
+        span.set_parent(Context::new().with_remote_span_context(span_context));
+
+        let headers = inject_context(&span);
+
+        let traceparent = headers.get("traceparent").unwrap();
+        assert!(traceparent.starts_with("00-4bf92f3577b34da6a3ce929d0e0e4736-"));
+        assert!(traceparent.ends_with("-01"));
+    }
+
+    #[test]
+    fn test_tracing_config_from_vars() {
+        let vars = HashMap::from([
+            ("OTEL_EXPORTER_OTLP_PROTOCOL", "http/protobuf"),
+            ("OTEL_EXPORTER_OTLP_ENDPOINT", "http://collector:4318"),
+            (
+                "OTEL_RESOURCE_ATTRIBUTES",
+                "deployment.environment=dev, host.name=a,bad",
+            ),
+            ("OTEL_TRACES_SAMPLER_ARG", "0.25"),
+        ]);
+        let config = TracingConfig::from_vars(|key| vars.get(key).map(|v| v.to_string()));
+
+        assert!(config.enabled);
+        assert_eq!(config.protocol, OtlpProtocol::Http);
+        assert_eq!(config.endpoint.as_deref(), Some("http://collector:4318"));
+        assert_eq!(config.service_name, DEFAULT_SERVICE_NAME);
+        assert_eq!(
+            config.resource_attributes,
+            vec![
+                KeyValue::new("deployment.environment", "dev"),
+                KeyValue::new("host.name", "a"),
+            ]
+        );
+        assert_eq!(config.sample_ratio, 0.25);
+
+        let disabled = TracingConfig::from_vars(|key| {
+            (key == "OTEL_TRACES_EXPORTER").then(|| "none".to_string())
+        });
+        assert!(!disabled.enabled);
+        assert_eq!(disabled.protocol, OtlpProtocol::Grpc);
+    }
 }
@@ -0,0 +1,142 @@
+use crate::ParseError;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Token {
+    Ident(String),
+    /// Decimal number as written, possibly with a fraction, for units to scale.
+    Number(String),
+    /// `0x` literal, lowercased: an address, hash or hex quantity.
+    Hex(String),
+    Str(String),
+    /// `@name`, an address book reference.
+    Book(String),
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    Comma,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    And,
+    Or,
+    Not,
+}
+
+/// A token and the byte offset it starts at.
+pub(crate) type Spanned = (Token, usize);
+
+pub(crate) fn tokenize(source: &str) -> Result<Vec<Spanned>, ParseError> {
+    let chars: Vec<(usize, char)> = source.char_indices().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while let Some(&(start, c)) = chars.get(i) {
+        let next = chars.get(i + 1).map(|&(_, c)| c);
+        let (token, len) = match (c, next) {
+            (c, _) if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            ('(', _) => (Token::LParen, 1),
+            (')', _) => (Token::RParen, 1),
+            ('[', _) => (Token::LBracket, 1),
+            (']', _) => (Token::RBracket, 1),
+            (',', _) => (Token::Comma, 1),
+            ('=', Some('=')) => (Token::Eq, 2),
+            ('!', Some('=')) => (Token::Ne, 2),
+            ('<', Some('=')) => (Token::Le, 2),
+            ('>', Some('=')) => (Token::Ge, 2),
+            ('<', _) => (Token::Lt, 1),
+            ('>', _) => (Token::Gt, 1),
+            ('&', Some('&')) => (Token::And, 2),
+            ('|', Some('|')) => (Token::Or, 2),
+            ('!', _) => (Token::Not, 1),
+            ('"' | '\'', _) => {
+                let (value, len) = string(&chars[i..], start)?;
+                (Token::Str(value), len)
+            }
+            ('0', Some('x' | 'X')) => {
+                let len = 2 + run(&chars[i + 2..], |c| c.is_ascii_hexdigit());
+                let text: String = chars[i..i + len].iter().map(|&(_, c)| c).collect();
+                (Token::Hex(text.to_lowercase()), len)
+            }
+            (c, _) if c.is_ascii_digit() => {
+                let len = run(&chars[i..], |c| c.is_ascii_digit() || c == '_' || c == '.');
+                let text: String = chars[i..i + len]
+                    .iter()
+                    .map(|&(_, c)| c)
+                    .filter(|&c| c != '_')
+                    .collect();
+                (Token::Number(text), len)
+            }
+            ('@', _) => {
+                let len = 1 + run(&chars[i + 1..], is_ident_char);
+                if len == 1 {
+                    return Err(ParseError::new(
+                        start,
+                        "expected an address book name after @",
+                    ));
+                }
+                let name = chars[i + 1..i + len].iter().map(|&(_, c)| c).collect();
+                (Token::Book(name), len)
+            }
+            (c, _) if c.is_alphabetic() || c == '_' => {
+                let len = run(&chars[i..], is_ident_char);
+                let word: String = chars[i..i + len].iter().map(|&(_, c)| c).collect();
+                let token = match word.as_str() {
+                    "and" => Token::And,
+                    "or" => Token::Or,
+                    "not" => Token::Not,
+                    _ => Token::Ident(word),
+                };
+                (token, len)
+            }
+            (c, _) => {
+                return Err(ParseError::new(
+                    start,
+                    format!("unexpected character {c:?}"),
+                ))
+            }
+        };
+        tokens.push((token, start));
+        i += len;
+    }
+    Ok(tokens)
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Length of the leading run of `chars` satisfying `pred`.
+fn run(chars: &[(usize, char)], pred: impl Fn(char) -> bool) -> usize {
+    chars.iter().take_while(|&&(_, c)| pred(c)).count()
+}
+
+/// Reads a quoted string starting at `chars[0]`, with `\` escaping the next
+/// character. Returns the unescaped value and the length in characters.
+fn string(chars: &[(usize, char)], start: usize) -> Result<(String, usize), ParseError> {
+    let quote = chars[0].1;
+    let mut value = String::new();
+    let mut i = 1;
+    loop {
+        match chars.get(i).map(|&(_, c)| c) {
+            None => return Err(ParseError::new(start, "unterminated string")),
+            Some('\\') => match chars.get(i + 1) {
+                Some(&(_, c)) => {
+                    value.push(c);
+                    i += 2;
+                }
+                None => return Err(ParseError::new(start, "unterminated string")),
+            },
+            Some(c) if c == quote => return Ok((value, i + 1)),
+            Some(c) => {
+                value.push(c);
+                i += 1;
+            }
+        }
+    }
+}
@@ -0,0 +1,537 @@
+//! A small, side-effect free expression language for matching blocks and
+//! transactions, shared by the crawler's filter stage and the consumer's
+//! notification rules.
+//!
+//! ```text
+//! from in @treasury and value > 10 ether and to not in @allowlist and input != "0x"
+//! ```
+//!
+//! Expressions combine comparisons (`==`, `!=`, `<`, `<=`, `>`, `>=`,
+//! `contains`) and set membership (`in [..]`, `in @book`) with `and`, `or`
+//! and `not`. Numbers may carry a `wei`, `gwei` or `ether` unit, `0x`
+//! literals compare case-insensitively, and `@name` refers to an address
+//! book supplied at evaluation time. A field the record does not have, such
+//! as `miner` on a transaction, makes every comparison on it false.
+
+mod lexer;
+mod parser;
+
+use parser::{CmpOp, Expr, Operand, Parser, Set};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+
+/// Field names an expression may use. Records answer the ones that apply
+/// to them.
+pub const FIELDS: &[&str] = &[
+    // Both blocks and transactions.
+    "kind",
+    "chain_id",
+    "hash",
+    "block_number",
+    "block_hash",
+    // Transactions.
+    "from",
+    "to",
+    "value",
+    "nonce",
+    "gas",
+    "gas_price",
+    "max_fee_per_gas",
+    "max_priority_fee_per_gas",
+    "input",
+    "selector",
+    "type",
+    // Blocks.
+    "parent_hash",
+    "miner",
+    "timestamp",
+    "gas_used",
+    "gas_limit",
+    "base_fee_per_gas",
+    "transaction_count",
+];
+
+/// Fields holding an address.
+const ADDRESS_FIELDS: &[&str] = &["from", "to", "miner"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Int(u128),
+    Str(String),
+    Bool(bool),
+}
+
+impl From<u64> for Value {
+    fn from(value: u64) -> Self {
+        Self::Int(value.into())
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Self::Str(value)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Self::Str(value.to_string())
+    }
+}
+
+/// Something an expression can be evaluated against.
+pub trait Record {
+    /// The value of one of [`FIELDS`], or `None` when the record has no such
+    /// field or it is unset.
+    fn field(&self, name: &str) -> Option<Value>;
+}
+
+/// Named sets of addresses, referenced as `@name`. Keys are the names,
+/// addresses are compared case-insensitively.
+#[derive(Debug, Clone, Default)]
+pub struct AddressBooks(HashMap<String, HashSet<String>>);
+
+impl AddressBooks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, name: impl Into<String>, addresses: impl IntoIterator<Item = String>) {
+        let addresses = addresses.into_iter().map(|a| a.to_lowercase()).collect();
+        self.0.insert(name.into(), addresses);
+    }
+
+    pub fn contains_book(&self, name: &str) -> bool {
+        self.0.contains_key(name)
+    }
+
+    /// Every address in any book, lowercase.
+    pub fn addresses(&self) -> impl Iterator<Item = &str> {
+        self.0.values().flatten().map(String::as_str)
+    }
+
+    fn contains(&self, name: &str, value: &Value) -> bool {
+        match (self.0.get(name), value) {
+            (Some(book), Value::Str(address)) => book.contains(&address.to_lowercase()),
+            _ => false,
+        }
+    }
+}
+
+impl<S: Into<String>, A: IntoIterator<Item = String>> FromIterator<(S, A)> for AddressBooks {
+    fn from_iter<T: IntoIterator<Item = (S, A)>>(iter: T) -> Self {
+        let mut books = Self::new();
+        for (name, addresses) in iter {
+            books.insert(name, addresses);
+        }
+        books
+    }
+}
+
+/// Where and why an expression failed to parse.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    /// Byte offset into the expression.
+    pub position: usize,
+    pub message: String,
+}
+
+impl ParseError {
+    pub(crate) fn new(position: usize, message: impl Into<String>) -> Self {
+        Self {
+            position,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at position {}", self.message, self.position)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// A parsed expression together with its source text.
+#[derive(Debug, Clone)]
+pub struct Filter {
+    source: String,
+    expr: Expr,
+}
+
+impl PartialEq for Filter {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+    }
+}
+
+impl FromStr for Filter {
+    type Err = ParseError;
+
+    fn from_str(source: &str) -> Result<Self, ParseError> {
+        let tokens = lexer::tokenize(source)?;
+        let expr = Parser::new(tokens, source.len()).parse()?;
+        Ok(Self {
+            source: source.to_string(),
+            expr,
+        })
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl Filter {
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Address books the expression refers to, so callers can reject
+    /// unknown ones up front.
+    pub fn address_books(&self) -> Vec<&str> {
+        fn collect<'a>(expr: &'a Expr, books: &mut Vec<&'a str>) {
+            match expr {
+                Expr::Not(inner) => collect(inner, books),
+                Expr::And(operands) | Expr::Or(operands) => {
+                    for operand in operands {
+                        collect(operand, books);
+                    }
+                }
+                Expr::In(_, Set::Book(name)) if !books.contains(&name.as_str()) => books.push(name),
+                _ => {}
+            }
+        }
+        let mut books = Vec::new();
+        collect(&self.expr, &mut books);
+        books
+    }
+
+    /// Addresses the expression compares `from`, `to` or `miner` with.
+    /// Together with [`Filter::address_books`] these are the addresses a
+    /// caller needs to keep the records of.
+    pub fn addresses(&self) -> Vec<&str> {
+        fn literal(operand: &Operand) -> Option<&str> {
+            match operand {
+                Operand::Literal(Value::Str(value)) => Some(value),
+                _ => None,
+            }
+        }
+        fn is_address(operand: &Operand) -> bool {
+            matches!(operand, Operand::Field(name) if ADDRESS_FIELDS.contains(&name.as_str()))
+        }
+        fn collect<'a>(expr: &'a Expr, addresses: &mut Vec<&'a str>) {
+            match expr {
+                Expr::Not(inner) => collect(inner, addresses),
+                Expr::And(operands) | Expr::Or(operands) => {
+                    for operand in operands {
+                        collect(operand, addresses);
+                    }
+                }
+                Expr::Compare(left, CmpOp::Eq | CmpOp::Ne, right) => {
+                    if is_address(left) {
+                        addresses.extend(literal(right));
+                    } else if is_address(right) {
+                        addresses.extend(literal(left));
+                    }
+                }
+                Expr::In(operand, Set::Literal(values)) if is_address(operand) => {
+                    addresses.extend(values.iter().filter_map(|value| match value {
+                        Value::Str(value) => Some(value.as_str()),
+                        _ => None,
+                    }));
+                }
+                _ => {}
+            }
+        }
+        let mut addresses = Vec::new();
+        collect(&self.expr, &mut addresses);
+        addresses
+    }
+
+    /// Fails on the first address book the expression uses that `books`
+    /// does not have.
+    pub fn check_address_books(&self, books: &AddressBooks) -> Result<(), String> {
+        match self
+            .address_books()
+            .into_iter()
+            .find(|name| !books.contains_book(name))
+        {
+            Some(name) => Err(format!("unknown address book @{name}")),
+            None => Ok(()),
+        }
+    }
+
+    pub fn matches(&self, record: &impl Record, books: &AddressBooks) -> bool {
+        eval(&self.expr, record, books)
+    }
+}
+
+/// Wraps `value` in double quotes, escaping as needed, for building an
+/// expression from user input.
+pub fn quote(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        if c == '"' || c == '\\' {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn eval(expr: &Expr, record: &impl Record, books: &AddressBooks) -> bool {
+    match expr {
+        Expr::Bool(value) => *value,
+        Expr::Not(inner) => !eval(inner, record, books),
+        Expr::And(operands) => operands.iter().all(|expr| eval(expr, record, books)),
+        Expr::Or(operands) => operands.iter().any(|expr| eval(expr, record, books)),
+        Expr::Compare(left, op, right) => {
+            let (Some(left), Some(right)) = (resolve(left, record), resolve(right, record)) else {
+                return false;
+            };
+            match op {
+                CmpOp::Contains => match (&left, &right) {
+                    (Value::Str(haystack), Value::Str(needle)) => {
+                        haystack.to_lowercase().contains(&needle.to_lowercase())
+                    }
+                    _ => false,
+                },
+                op => compare(&left, &right).is_some_and(|ordering| match op {
+                    CmpOp::Eq => ordering == Ordering::Equal,
+                    CmpOp::Ne => ordering != Ordering::Equal,
+                    CmpOp::Lt => ordering == Ordering::Less,
+                    CmpOp::Le => ordering != Ordering::Greater,
+                    CmpOp::Gt => ordering == Ordering::Greater,
+                    CmpOp::Ge => ordering != Ordering::Less,
+                    CmpOp::Contains => unreachable!(),
+                }),
+            }
+        }
+        Expr::In(operand, set) => {
+            let Some(value) = resolve(operand, record) else {
+                return false;
+            };
+            match set {
+                Set::Literal(values) => values
+                    .iter()
+                    .any(|item| compare(&value, item) == Some(Ordering::Equal)),
+                Set::Book(name) => books.contains(name, &value),
+            }
+        }
+    }
+}
+
+fn resolve(operand: &Operand, record: &impl Record) -> Option<Value> {
+    match operand {
+        Operand::Field(name) => record.field(name),
+        Operand::Literal(value) => Some(value.clone()),
+    }
+}
+
+fn parse_hex(text: &str) -> Option<u128> {
+    let digits = text.strip_prefix("0x")?;
+    u128::from_str_radix(digits, 16).ok()
+}
+
+/// Orders two values, or `None` when they cannot be compared. A `0x` string
+/// compares with a number as the quantity it encodes, and with another
+/// string case-insensitively.
+fn compare(left: &Value, right: &Value) -> Option<Ordering> {
+    match (left, right) {
+        (Value::Int(a), Value::Int(b)) => Some(a.cmp(b)),
+        (Value::Int(a), Value::Str(b)) => parse_hex(b).map(|b| a.cmp(&b)),
+        (Value::Str(a), Value::Int(b)) => parse_hex(a).map(|a| a.cmp(b)),
+        (Value::Str(a), Value::Str(b)) if a.starts_with("0x") || b.starts_with("0x") => {
+            Some(a.to_lowercase().cmp(&b.to_lowercase()))
+        }
+        (Value::Str(a), Value::Str(b)) => Some(a.cmp(b)),
+        (Value::Bool(a), Value::Bool(b)) => Some(a.cmp(b)),
+        _ => None,
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Filter {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.source)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Filter {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let source = String::deserialize(deserializer)?;
+        source.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Tx(HashMap<&'static str, Value>);
+
+    impl Record for Tx {
+        fn field(&self, name: &str) -> Option<Value> {
+            self.0.get(name).cloned()
+        }
+    }
+
+    fn transfer(from: &str, to: &str, value: u128) -> Tx {
+        Tx(HashMap::from([
+            ("kind", Value::from("transaction")),
+            ("hash", Value::from("0xAbC123")),
+            ("from", Value::from(from)),
+            ("to", Value::from(to)),
+            ("value", Value::Int(value)),
+            ("selector", Value::from("0xa9059cbb")),
+        ]))
+    }
+
+    fn books() -> AddressBooks {
+        AddressBooks::from_iter([
+            ("treasury", vec!["0xAAAA".to_string()]),
+            ("allowlist", vec!["0xbbbb".to_string()]),
+        ])
+    }
+
+    fn matches(source: &str, record: &Tx) -> bool {
+        source.parse::<Filter>().unwrap().matches(record, &books())
+    }
+
+    #[test]
+    fn test_watched_transfer_to_unlisted_contract() {
+        let filter = "from in @treasury and value > 10 ether and to not in @allowlist";
+        assert!(matches(
+            filter,
+            &transfer("0xaaaa", "0xcccc", 11 * 10u128.pow(18))
+        ));
+        assert!(!matches(
+            filter,
+            &transfer("0xaaaa", "0xBBBB", 11 * 10u128.pow(18))
+        ));
+        assert!(!matches(
+            filter,
+            &transfer("0xaaaa", "0xcccc", 10 * 10u128.pow(18))
+        ));
+        assert!(!matches(
+            filter,
+            &transfer("0xdddd", "0xcccc", 11 * 10u128.pow(18))
+        ));
+    }
+
+    #[test]
+    fn test_operators_and_literals() {
+        let tx = transfer("0xaaaa", "0xcccc", 1_500_000_000);
+        assert!(matches("value == 1.5 gwei", &tx));
+        assert!(matches("value >= 0x59682f00 && value < 2 gwei", &tx));
+        assert!(matches("to in ['0xCCCC', \"0xdddd\"]", &tx));
+        assert!(matches("hash contains \"abc\"", &tx));
+        assert!(matches("not (kind == 'block') or false", &tx));
+        assert!(matches("selector == 0xA9059CBB", &tx));
+        // `miner` is not a transaction field, so neither comparison holds.
+        assert!(!matches("miner == 0xaaaa", &tx));
+        assert!(!matches("miner != 0xaaaa", &tx));
+        // `and` binds tighter than `or`.
+        assert!(matches("true or false and false", &tx));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let error = |source: &str| source.parse::<Filter>().unwrap_err().to_string();
+        assert_eq!(error(""), "empty expression at position 0");
+        assert_eq!(
+            error("balance > 1"),
+            "unknown field `balance` at position 0"
+        );
+        assert_eq!(
+            error("value >"),
+            "expected a field or value, found end of expression at position 7"
+        );
+        assert_eq!(
+            error("value > 1 value"),
+            "unexpected `value` at position 10"
+        );
+        assert_eq!(error("value > 0.5"), "invalid number `0.5` at position 8");
+        assert_eq!(
+            error("to in [from]"),
+            "sets may only hold values at position 7"
+        );
+        assert_eq!(error("from == 'x"), "unterminated string at position 8");
+        assert_eq!(
+            error("(value > 1"),
+            "expected `)`, found end of expression at position 10"
+        );
+    }
+
+    #[test]
+    fn test_nesting_limit() {
+        let nested = |depth: usize| format!("{}value > 1{}", "(".repeat(depth), ")".repeat(depth));
+        assert!(nested(64).parse::<Filter>().is_ok());
+        assert_eq!(
+            nested(65).parse::<Filter>().unwrap_err().to_string(),
+            "expression nested deeper than 64 levels at position 64"
+        );
+        assert!(nested(10_000).parse::<Filter>().is_err());
+        // Negations count as nesting too.
+        assert!(format!("{}true", "!".repeat(64)).parse::<Filter>().is_ok());
+        assert!(format!("{}true", "!".repeat(10_000))
+            .parse::<Filter>()
+            .is_err());
+        assert!(format!("not {}", nested(64)).parse::<Filter>().is_err());
+        // Siblings do not add up.
+        assert!(format!("{} and {}", nested(64), nested(64))
+            .parse::<Filter>()
+            .is_ok());
+    }
+
+    #[test]
+    fn test_long_flat_chains() {
+        let tx = transfer("0xaaaa", "0xcccc", 5);
+        let addresses: Vec<String> = (0..10_000).map(|i| format!("to == 0x{i:04x}")).collect();
+        let any = addresses.join(" or ");
+        assert!(!matches(&any, &tx));
+        assert!(matches(&format!("{any} or to == 0xcccc"), &tx));
+        let all = vec!["value > 1"; 10_000].join(" and ");
+        assert!(matches(&all, &tx));
+        assert!(!matches(&format!("{all} and value > 5"), &tx));
+        // Chains inside parentheses only add the parentheses.
+        let grouped = format!("{}{any}{}", "(".repeat(64), ")".repeat(64));
+        assert!(grouped.parse::<Filter>().is_ok());
+    }
+
+    #[test]
+    fn test_address_books() {
+        let filter: Filter = "from in @treasury or to in @unknown".parse().unwrap();
+        assert_eq!(filter.address_books(), vec!["treasury", "unknown"]);
+        assert_eq!(
+            filter.check_address_books(&books()).unwrap_err(),
+            "unknown address book @unknown"
+        );
+    }
+
+    #[test]
+    fn test_addresses() {
+        let filter: Filter = "(from == 0xAA or 0xbb != to) and not miner in [0xCC, 1] \
+            and input == 0xdd and hash contains 0xee"
+            .parse()
+            .unwrap();
+        assert_eq!(filter.addresses(), vec!["0xaa", "0xbb", "0xcc"]);
+    }
+
+    #[test]
+    fn test_quote() {
+        let source = format!("hash contains {}", quote(r#"a"b\c"#));
+        let filter: Filter = source.parse().unwrap();
+        let tx = Tx(HashMap::from([("hash", Value::from(r#"xa"b\cx"#))]));
+        assert!(filter.matches(&tx, &AddressBooks::new()));
+    }
+}
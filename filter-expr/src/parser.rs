@@ -0,0 +1,318 @@
+use crate::lexer::{Spanned, Token};
+use crate::ParseError;
+use crate::{Value, FIELDS};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum CmpOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Contains,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Set {
+    Literal(Vec<Value>),
+    Book(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Operand {
+    Field(String),
+    Literal(Value),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Expr {
+    Bool(bool),
+    Not(Box<Expr>),
+    /// Operands of a chain of `and`s, kept flat so that long chains do not
+    /// make the tree deep.
+    And(Vec<Expr>),
+    Or(Vec<Expr>),
+    Compare(Operand, CmpOp, Operand),
+    In(Operand, Set),
+}
+
+/// Deepest nesting of parentheses and negations accepted. Parsing and
+/// evaluation recurse once per level, so a limit keeps hostile input from
+/// overflowing the stack.
+const MAX_DEPTH: usize = 64;
+
+/// Recursive descent over the grammar below, loosest binding first.
+///
+/// ```text
+/// or         = and { ("or" | "||") and }
+/// and        = unary { ("and" | "&&") unary }
+/// unary      = ("not" | "!") unary | "(" or ")" | "true" | "false" | comparison
+/// comparison = operand ( op operand | ["not"] "in" set )
+/// operand    = field | number [unit] | hex | string
+/// set        = "[" [ operand { "," operand } ] "]" | "@" name
+/// ```
+pub(crate) struct Parser {
+    tokens: Vec<Spanned>,
+    pos: usize,
+    end: usize,
+    /// Parentheses and negations around the expression being parsed.
+    depth: usize,
+}
+
+impl Parser {
+    pub(crate) fn new(tokens: Vec<Spanned>, end: usize) -> Self {
+        Self {
+            tokens,
+            pos: 0,
+            end,
+            depth: 0,
+        }
+    }
+
+    pub(crate) fn parse(mut self) -> Result<Expr, ParseError> {
+        if self.tokens.is_empty() {
+            return Err(ParseError::new(0, "empty expression"));
+        }
+        let expr = self.or()?;
+        match self.tokens.get(self.pos) {
+            None => Ok(expr),
+            Some((token, at)) => Err(ParseError::new(
+                *at,
+                format!("unexpected {}", describe(token)),
+            )),
+        }
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(token, _)| token)
+    }
+
+    /// Byte offset of the next token, or the end of the source.
+    fn offset(&self) -> usize {
+        self.tokens.get(self.pos).map_or(self.end, |&(_, at)| at)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).map(|(token, _)| token.clone());
+        self.pos += 1;
+        token
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        if self.peek() == Some(token) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, token: Token, what: &str) -> Result<(), ParseError> {
+        if self.eat(&token) {
+            Ok(())
+        } else {
+            Err(self.error(format!("expected {what}")))
+        }
+    }
+
+    fn error(&self, message: impl Into<String>) -> ParseError {
+        let message = message.into();
+        match self.peek() {
+            Some(token) => ParseError::new(
+                self.offset(),
+                format!("{message}, found {}", describe(token)),
+            ),
+            None => ParseError::new(self.end, format!("{message}, found end of expression")),
+        }
+    }
+
+    /// Goes one level of nesting deeper, failing past `MAX_DEPTH`.
+    fn descend(&mut self) -> Result<(), ParseError> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(ParseError::new(
+                self.offset(),
+                format!("expression nested deeper than {MAX_DEPTH} levels"),
+            ));
+        }
+        Ok(())
+    }
+
+    fn or(&mut self) -> Result<Expr, ParseError> {
+        let mut operands = vec![self.and()?];
+        while self.eat(&Token::Or) {
+            operands.push(self.and()?);
+        }
+        Ok(match operands.len() {
+            1 => operands.remove(0),
+            _ => Expr::Or(operands),
+        })
+    }
+
+    fn and(&mut self) -> Result<Expr, ParseError> {
+        let mut operands = vec![self.unary()?];
+        while self.eat(&Token::And) {
+            operands.push(self.unary()?);
+        }
+        Ok(match operands.len() {
+            1 => operands.remove(0),
+            _ => Expr::And(operands),
+        })
+    }
+
+    fn unary(&mut self) -> Result<Expr, ParseError> {
+        match self.peek() {
+            Some(Token::Not) => {
+                self.descend()?;
+                self.pos += 1;
+                let expr = Expr::Not(Box::new(self.unary()?));
+                self.depth -= 1;
+                Ok(expr)
+            }
+            Some(Token::LParen) => {
+                self.descend()?;
+                self.pos += 1;
+                let expr = self.or()?;
+                self.expect(Token::RParen, "`)`")?;
+                self.depth -= 1;
+                Ok(expr)
+            }
+            Some(Token::Ident(word)) if word == "true" || word == "false" => {
+                let value = word == "true";
+                self.pos += 1;
+                Ok(Expr::Bool(value))
+            }
+            _ => self.comparison(),
+        }
+    }
+
+    fn comparison(&mut self) -> Result<Expr, ParseError> {
+        let left = self.operand()?;
+        let op = match self.peek() {
+            Some(Token::Eq) => CmpOp::Eq,
+            Some(Token::Ne) => CmpOp::Ne,
+            Some(Token::Lt) => CmpOp::Lt,
+            Some(Token::Le) => CmpOp::Le,
+            Some(Token::Gt) => CmpOp::Gt,
+            Some(Token::Ge) => CmpOp::Ge,
+            Some(Token::Ident(word)) if word == "contains" => CmpOp::Contains,
+            Some(Token::Ident(word)) if word == "in" => {
+                self.pos += 1;
+                return Ok(Expr::In(left, self.set()?));
+            }
+            Some(Token::Not) => {
+                self.pos += 1;
+                if !matches!(self.next(), Some(Token::Ident(word)) if word == "in") {
+                    self.pos -= 1;
+                    return Err(self.error("expected `in` after `not`"));
+                }
+                return Ok(Expr::Not(Box::new(Expr::In(left, self.set()?))));
+            }
+            _ => return Err(self.error("expected a comparison")),
+        };
+        self.pos += 1;
+        let right = self.operand()?;
+        Ok(Expr::Compare(left, op, right))
+    }
+
+    fn operand(&mut self) -> Result<Operand, ParseError> {
+        let at = self.offset();
+        match self.peek().cloned() {
+            Some(Token::Ident(name)) => {
+                if !FIELDS.contains(&name.as_str()) {
+                    return Err(ParseError::new(at, format!("unknown field `{name}`")));
+                }
+                self.pos += 1;
+                Ok(Operand::Field(name))
+            }
+            Some(Token::Number(text)) => {
+                self.pos += 1;
+                let unit = match self.peek() {
+                    Some(Token::Ident(unit)) => match unit.as_str() {
+                        "wei" => Some(0),
+                        "gwei" => Some(9),
+                        "ether" | "eth" => Some(18),
+                        _ => None,
+                    },
+                    _ => None,
+                };
+                if unit.is_some() {
+                    self.pos += 1;
+                }
+                number(&text, unit.unwrap_or(0))
+                    .map(|n| Operand::Literal(Value::Int(n)))
+                    .ok_or_else(|| ParseError::new(at, format!("invalid number `{text}`")))
+            }
+            Some(Token::Hex(text)) => {
+                self.pos += 1;
+                Ok(Operand::Literal(Value::Str(text)))
+            }
+            Some(Token::Str(text)) => {
+                self.pos += 1;
+                Ok(Operand::Literal(Value::Str(text)))
+            }
+            _ => Err(self.error("expected a field or value")),
+        }
+    }
+
+    fn set(&mut self) -> Result<Set, ParseError> {
+        if let Some(Token::Book(name)) = self.peek().cloned() {
+            self.pos += 1;
+            return Ok(Set::Book(name));
+        }
+        self.expect(Token::LBracket, "`[` or an address book")?;
+        let mut values = Vec::new();
+        if self.eat(&Token::RBracket) {
+            return Ok(Set::Literal(values));
+        }
+        loop {
+            let at = self.offset();
+            match self.operand()? {
+                Operand::Literal(value) => values.push(value),
+                Operand::Field(_) => {
+                    return Err(ParseError::new(at, "sets may only hold values"));
+                }
+            }
+            if self.eat(&Token::RBracket) {
+                return Ok(Set::Literal(values));
+            }
+            self.expect(Token::Comma, "`,` or `]`")?;
+        }
+    }
+}
+
+/// Parses a decimal number scaled by `10^decimals`, e.g. `1.5` with 18
+/// decimals for ether. Fails on overflow or more fractional digits than the
+/// unit allows.
+fn number(text: &str, decimals: u32) -> Option<u128> {
+    let (whole, fraction) = text.split_once('.').unwrap_or((text, ""));
+    if whole.is_empty() || fraction.len() > decimals as usize || fraction.contains('.') {
+        return None;
+    }
+    let digits = format!("{whole}{fraction:0<width$}", width = decimals as usize);
+    digits.parse().ok()
+}
+
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Ident(word) => format!("`{word}`"),
+        Token::Number(text) | Token::Hex(text) => format!("`{text}`"),
+        Token::Str(text) => format!("{text:?}"),
+        Token::Book(name) => format!("`@{name}`"),
+        Token::LParen => "`(`".to_string(),
+        Token::RParen => "`)`".to_string(),
+        Token::LBracket => "`[`".to_string(),
+        Token::RBracket => "`]`".to_string(),
+        Token::Comma => "`,`".to_string(),
+        Token::Eq => "`==`".to_string(),
+        Token::Ne => "`!=`".to_string(),
+        Token::Lt => "`<`".to_string(),
+        Token::Le => "`<=`".to_string(),
+        Token::Gt => "`>`".to_string(),
+        Token::Ge => "`>=`".to_string(),
+        Token::And => "`and`".to_string(),
+        Token::Or => "`or`".to_string(),
+        Token::Not => "`not`".to_string(),
+    }
+}